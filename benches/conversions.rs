@@ -0,0 +1,102 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, ImageBuffer, Rgb, Rgba};
+use serialimage::{Primitive, SerialImageBuffer};
+
+/// 24 MP frame.
+const WIDTH: usize = 6000;
+const HEIGHT: usize = 4000;
+
+fn pattern<T: Primitive>(elems: usize) -> Vec<T> {
+    (0..WIDTH * HEIGHT * elems)
+        .map(|i| T::from(i % 251).unwrap())
+        .collect()
+}
+
+fn bench_type<T: Primitive + Send + Sync>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("conversions_{}", name));
+    group.sample_size(10);
+    for (layout, elems) in [("rgb", 3), ("rgba", 4)] {
+        let data = pattern::<T>(elems);
+        group.throughput(Throughput::Bytes(
+            (data.len() * std::mem::size_of::<T>()) as u64,
+        ));
+        group.bench_with_input(
+            BenchmarkId::new("deinterleave", layout),
+            &data,
+            |b, data| {
+                b.iter(|| SerialImageBuffer::from_vec(WIDTH, HEIGHT, black_box(data.clone())))
+            },
+        );
+        let img = SerialImageBuffer::from_vec(WIDTH, HEIGHT, data).unwrap();
+        group.bench_with_input(BenchmarkId::new("interleave", layout), &img, |b, img| {
+            b.iter(|| black_box(img.clone()).into_vec())
+        });
+    }
+    group.finish();
+}
+
+fn bench_dynamic_image(c: &mut Criterion) {
+    let mut group = c.benchmark_group("conversions_dynamic_image");
+    group.sample_size(10);
+    let images = [
+        (
+            "rgb8",
+            DynamicImage::from(
+                ImageBuffer::<Rgb<u8>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(3))
+                    .unwrap(),
+            ),
+        ),
+        (
+            "rgba8",
+            DynamicImage::from(
+                ImageBuffer::<Rgba<u8>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(4))
+                    .unwrap(),
+            ),
+        ),
+        (
+            "rgb16",
+            DynamicImage::from(
+                ImageBuffer::<Rgb<u16>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(3))
+                    .unwrap(),
+            ),
+        ),
+        (
+            "rgba16",
+            DynamicImage::from(
+                ImageBuffer::<Rgba<u16>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(4))
+                    .unwrap(),
+            ),
+        ),
+        (
+            "rgb32f",
+            DynamicImage::from(
+                ImageBuffer::<Rgb<f32>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(3))
+                    .unwrap(),
+            ),
+        ),
+        (
+            "rgba32f",
+            DynamicImage::from(
+                ImageBuffer::<Rgba<f32>, _>::from_raw(WIDTH as u32, HEIGHT as u32, pattern(4))
+                    .unwrap(),
+            ),
+        ),
+    ];
+    for (name, img) in images.iter() {
+        group.throughput(Throughput::Bytes(img.as_bytes().len() as u64));
+        group.bench_with_input(BenchmarkId::new("from_borrowed", name), img, |b, img| {
+            b.iter(|| serialimage::DynamicSerialImage::from(black_box(img)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_conversions(c: &mut Criterion) {
+    bench_type::<u8>(c, "u8");
+    bench_type::<u16>(c, "u16");
+    bench_type::<f32>(c, "f32");
+    bench_dynamic_image(c);
+}
+
+criterion_group!(benches, bench_conversions);
+criterion_main!(benches);
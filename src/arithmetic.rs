@@ -0,0 +1,457 @@
+#![warn(missing_docs)]
+
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+
+use crate::parallel::{for_each_mut, zip_for_each_mut};
+use crate::{Channel, DynamicSerialImage, Primitive, SerialImageBuffer};
+
+/// Per-sample arithmetic used by the operator implementations of [`SerialImageBuffer`] and [`DynamicSerialImage`].
+///
+/// The trait is implemented for [`u8`], [`u16`] and [`f32`].
+///
+/// # Semantics
+///  * [`u8`] and [`u16`]: addition, subtraction and multiplication saturate at the
+///    bounds of the type. Division rounds to the nearest integer. Division of a
+///    non-zero value by zero saturates to the maximum of the type, and `0 / 0` is `0`.
+///  * [`f32`]: regular IEEE-754 floating point arithmetic.
+///
+/// # Operators
+/// [`SerialImageBuffer<T>`] implements [`Add`], [`Sub`], [`Mul`] and [`Div`] (and the corresponding
+/// `*Assign` traits) with another image or with a scalar of type `T`. [`DynamicSerialImage`]
+/// implements the same operators with another image or with an [`f32`] scalar; for integer
+/// images the scalar operation is evaluated in floating point, then rounded and saturated.
+///
+///  * Image-image operations require both images to have the same dimensions, channel layout
+///    (see [`SerialImageBuffer::is_compatible`]) and sample type. The binary operators return
+///    an error otherwise, and the `*Assign` operators panic. The `try_*_assign` methods, such as
+///    [`SerialImageBuffer::try_sub_assign`], modify the image in place and return an error.
+///  * The alpha channel of the left operand is passed through unchanged.
+///  * The metadata of the left operand is kept. If the left operand has no metadata, the
+///    metadata of the right operand is used.
+pub trait PixelArithmetic: Primitive + Send + Sync {
+    /// Add two samples.
+    fn add_sample(self, rhs: Self) -> Self;
+    /// Subtract `rhs` from this sample.
+    fn sub_sample(self, rhs: Self) -> Self;
+    /// Multiply two samples.
+    fn mul_sample(self, rhs: Self) -> Self;
+    /// Divide this sample by `rhs`.
+    fn div_sample(self, rhs: Self) -> Self;
+    /// Convert the sample to [`f32`].
+    fn to_f32_sample(self) -> f32;
+    /// Convert an [`f32`] value to a sample, rounding and saturating for integer types.
+    fn from_f32_sample(value: f32) -> Self;
+}
+
+impl PixelArithmetic for u8 {
+    fn add_sample(self, rhs: Self) -> Self {
+        self.saturating_add(rhs)
+    }
+
+    fn sub_sample(self, rhs: Self) -> Self {
+        self.saturating_sub(rhs)
+    }
+
+    fn mul_sample(self, rhs: Self) -> Self {
+        self.saturating_mul(rhs)
+    }
+
+    fn div_sample(self, rhs: Self) -> Self {
+        if rhs == 0 {
+            if self == 0 {
+                0
+            } else {
+                u8::MAX
+            }
+        } else {
+            ((self as u16 + rhs as u16 / 2) / rhs as u16) as u8
+        }
+    }
+
+    fn to_f32_sample(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32_sample(value: f32) -> Self {
+        value.round() as u8
+    }
+}
+
+impl PixelArithmetic for u16 {
+    fn add_sample(self, rhs: Self) -> Self {
+        self.saturating_add(rhs)
+    }
+
+    fn sub_sample(self, rhs: Self) -> Self {
+        self.saturating_sub(rhs)
+    }
+
+    fn mul_sample(self, rhs: Self) -> Self {
+        self.saturating_mul(rhs)
+    }
+
+    fn div_sample(self, rhs: Self) -> Self {
+        if rhs == 0 {
+            if self == 0 {
+                0
+            } else {
+                u16::MAX
+            }
+        } else {
+            ((self as u32 + rhs as u32 / 2) / rhs as u32) as u16
+        }
+    }
+
+    fn to_f32_sample(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32_sample(value: f32) -> Self {
+        value.round() as u16
+    }
+}
+
+impl PixelArithmetic for f32 {
+    fn add_sample(self, rhs: Self) -> Self {
+        self + rhs
+    }
+
+    fn sub_sample(self, rhs: Self) -> Self {
+        self - rhs
+    }
+
+    fn mul_sample(self, rhs: Self) -> Self {
+        self * rhs
+    }
+
+    fn div_sample(self, rhs: Self) -> Self {
+        self / rhs
+    }
+
+    fn to_f32_sample(self) -> f32 {
+        self
+    }
+
+    fn from_f32_sample(value: f32) -> Self {
+        value
+    }
+}
+
+impl<T: PixelArithmetic> SerialImageBuffer<T> {
+    /// Check if `other` has the same dimensions and channel layout as this image.
+    pub fn is_compatible(&self, other: &SerialImageBuffer<T>) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.data.pixel_elems == other.data.pixel_elems
+    }
+
+    fn apply_image(
+        &mut self,
+        rhs: &SerialImageBuffer<T>,
+        op: impl Fn(T, T) -> T + Send + Sync,
+    ) -> Result<(), &'static str> {
+        if self.width != rhs.width || self.height != rhs.height {
+            return Err("Image dimensions do not match");
+        }
+        if self.data.pixel_elems != rhs.data.pixel_elems {
+            return Err("Image channel layouts do not match");
+        }
+        for &channel in self.channels() {
+            if channel == Channel::Alpha {
+                continue;
+            }
+            let src = rhs.channel_data(channel).as_ref().unwrap();
+            let dst = self.channel_data_mut(channel).as_mut().unwrap();
+            zip_for_each_mut(dst, src, |a, b| *a = op(*a, *b));
+        }
+        if self.meta.is_none() {
+            self.meta.clone_from(&rhs.meta);
+        }
+        Ok(())
+    }
+
+    fn apply_scalar(&mut self, op: impl Fn(T) -> T + Send + Sync) {
+        self.map_channels_in_place(|channel, data| {
+            if channel != Channel::Alpha {
+                for_each_mut(data, |a| *a = op(*a));
+            }
+        });
+    }
+}
+
+/// Implements the image-image and image-scalar operators for [`SerialImageBuffer`].
+macro_rules! impl_serial_image_op {
+    (
+        $op_trait:ident,
+        $op:ident,
+        $assign_trait:ident,
+        $assign:ident,
+        $try_assign:ident,
+        $sample_op:ident
+    ) => {
+        impl<T: PixelArithmetic> SerialImageBuffer<T> {
+            #[doc = concat!(
+                "Fallible version of [`", stringify!($assign_trait), "`] with another image."
+            )]
+            ///
+            /// # Errors
+            ///  * If the images have different dimensions or channel layouts. The image is
+            ///    left unchanged.
+            pub fn $try_assign(&mut self, rhs: &SerialImageBuffer<T>) -> Result<(), &'static str> {
+                self.apply_image(rhs, T::$sample_op)
+            }
+        }
+
+        impl<T: PixelArithmetic> $assign_trait<&SerialImageBuffer<T>> for SerialImageBuffer<T> {
+            /// # Panics
+            ///
+            #[doc = concat!(
+                "If the images have different dimensions or channel layouts, see ",
+                "[`SerialImageBuffer::", stringify!($try_assign), "`]."
+            )]
+            fn $assign(&mut self, rhs: &SerialImageBuffer<T>) {
+                if let Err(msg) = self.$try_assign(rhs) {
+                    panic!("{}", msg);
+                }
+            }
+        }
+
+        impl<T: PixelArithmetic> $assign_trait<T> for SerialImageBuffer<T> {
+            fn $assign(&mut self, rhs: T) {
+                self.apply_scalar(|a| a.$sample_op(rhs));
+            }
+        }
+
+        impl<T: PixelArithmetic> $op_trait<&SerialImageBuffer<T>> for SerialImageBuffer<T> {
+            type Output = Result<SerialImageBuffer<T>, &'static str>;
+
+            fn $op(mut self, rhs: &SerialImageBuffer<T>) -> Self::Output {
+                self.apply_image(rhs, T::$sample_op)?;
+                Ok(self)
+            }
+        }
+
+        impl<T: PixelArithmetic> $op_trait<&SerialImageBuffer<T>> for &SerialImageBuffer<T> {
+            type Output = Result<SerialImageBuffer<T>, &'static str>;
+
+            fn $op(self, rhs: &SerialImageBuffer<T>) -> Self::Output {
+                self.clone().$op(rhs)
+            }
+        }
+
+        impl<T: PixelArithmetic> $op_trait<T> for SerialImageBuffer<T> {
+            type Output = SerialImageBuffer<T>;
+
+            fn $op(mut self, rhs: T) -> Self::Output {
+                self.$assign(rhs);
+                self
+            }
+        }
+
+        impl<T: PixelArithmetic> $op_trait<T> for &SerialImageBuffer<T> {
+            type Output = SerialImageBuffer<T>;
+
+            fn $op(self, rhs: T) -> Self::Output {
+                self.clone().$op(rhs)
+            }
+        }
+    };
+}
+
+impl_serial_image_op!(Add, add, AddAssign, add_assign, try_add_assign, add_sample);
+impl_serial_image_op!(Sub, sub, SubAssign, sub_assign, try_sub_assign, sub_sample);
+impl_serial_image_op!(Mul, mul, MulAssign, mul_assign, try_mul_assign, mul_sample);
+impl_serial_image_op!(Div, div, DivAssign, div_assign, try_div_assign, div_sample);
+
+impl DynamicSerialImage {
+    fn apply_scalar(&mut self, rhs: f32, op: impl Fn(f32, f32) -> f32 + Send + Sync) {
+        match self {
+            DynamicSerialImage::U8(value) => {
+                value.apply_scalar(|a| u8::from_f32_sample(op(a.to_f32_sample(), rhs)))
+            }
+            DynamicSerialImage::U16(value) => {
+                value.apply_scalar(|a| u16::from_f32_sample(op(a.to_f32_sample(), rhs)))
+            }
+            DynamicSerialImage::F32(value) => value.apply_scalar(|a| op(a, rhs)),
+        }
+    }
+}
+
+/// Implements the image-image and image-scalar operators for [`DynamicSerialImage`].
+macro_rules! impl_dynamic_image_op {
+    (
+        $op_trait:ident,
+        $op:ident,
+        $assign_trait:ident,
+        $assign:ident,
+        $try_assign:ident,
+        $sample_op:ident
+    ) => {
+        impl DynamicSerialImage {
+            #[doc = concat!(
+                "Fallible version of [`", stringify!($assign_trait), "`] with another image."
+            )]
+            ///
+            /// # Errors
+            ///  * If the images have different dimensions, channel layouts or sample types. The
+            ///    image is left unchanged.
+            pub fn $try_assign(&mut self, rhs: &DynamicSerialImage) -> Result<(), &'static str> {
+                match (self, rhs) {
+                    (DynamicSerialImage::U8(a), DynamicSerialImage::U8(b)) => a.$try_assign(b),
+                    (DynamicSerialImage::U16(a), DynamicSerialImage::U16(b)) => a.$try_assign(b),
+                    (DynamicSerialImage::F32(a), DynamicSerialImage::F32(b)) => a.$try_assign(b),
+                    _ => Err("Image sample types do not match"),
+                }
+            }
+        }
+
+        impl $assign_trait<&DynamicSerialImage> for DynamicSerialImage {
+            /// # Panics
+            ///
+            #[doc = concat!(
+                "If the images have different dimensions, channel layouts or sample types, see ",
+                "[`DynamicSerialImage::", stringify!($try_assign), "`]."
+            )]
+            fn $assign(&mut self, rhs: &DynamicSerialImage) {
+                if let Err(msg) = self.$try_assign(rhs) {
+                    panic!("{}", msg);
+                }
+            }
+        }
+
+        impl $assign_trait<f32> for DynamicSerialImage {
+            fn $assign(&mut self, rhs: f32) {
+                self.apply_scalar(rhs, f32::$sample_op);
+            }
+        }
+
+        impl $op_trait<&DynamicSerialImage> for &DynamicSerialImage {
+            type Output = Result<DynamicSerialImage, &'static str>;
+
+            fn $op(self, rhs: &DynamicSerialImage) -> Self::Output {
+                match (self, rhs) {
+                    (DynamicSerialImage::U8(a), DynamicSerialImage::U8(b)) => {
+                        Ok(DynamicSerialImage::U8(a.$op(b)?))
+                    }
+                    (DynamicSerialImage::U16(a), DynamicSerialImage::U16(b)) => {
+                        Ok(DynamicSerialImage::U16(a.$op(b)?))
+                    }
+                    (DynamicSerialImage::F32(a), DynamicSerialImage::F32(b)) => {
+                        Ok(DynamicSerialImage::F32(a.$op(b)?))
+                    }
+                    _ => Err("Image sample types do not match"),
+                }
+            }
+        }
+
+        impl $op_trait<&DynamicSerialImage> for DynamicSerialImage {
+            type Output = Result<DynamicSerialImage, &'static str>;
+
+            fn $op(self, rhs: &DynamicSerialImage) -> Self::Output {
+                match (self, rhs) {
+                    (DynamicSerialImage::U8(a), DynamicSerialImage::U8(b)) => {
+                        Ok(DynamicSerialImage::U8(a.$op(b)?))
+                    }
+                    (DynamicSerialImage::U16(a), DynamicSerialImage::U16(b)) => {
+                        Ok(DynamicSerialImage::U16(a.$op(b)?))
+                    }
+                    (DynamicSerialImage::F32(a), DynamicSerialImage::F32(b)) => {
+                        Ok(DynamicSerialImage::F32(a.$op(b)?))
+                    }
+                    _ => Err("Image sample types do not match"),
+                }
+            }
+        }
+
+        impl $op_trait<f32> for DynamicSerialImage {
+            type Output = DynamicSerialImage;
+
+            fn $op(mut self, rhs: f32) -> Self::Output {
+                self.$assign(rhs);
+                self
+            }
+        }
+
+        impl $op_trait<f32> for &DynamicSerialImage {
+            type Output = DynamicSerialImage;
+
+            fn $op(self, rhs: f32) -> Self::Output {
+                self.clone().$op(rhs)
+            }
+        }
+    };
+}
+
+impl_dynamic_image_op!(Add, add, AddAssign, add_assign, try_add_assign, add_sample);
+impl_dynamic_image_op!(Sub, sub, SubAssign, sub_assign, try_sub_assign, sub_sample);
+impl_dynamic_image_op!(Mul, mul, MulAssign, mul_assign, try_mul_assign, mul_sample);
+impl_dynamic_image_op!(Div, div, DivAssign, div_assign, try_div_assign, div_sample);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ImageMetaData;
+
+    #[test]
+    fn test_saturating_ops() {
+        let light = SerialImageBuffer::from_vec(2, 2, vec![10u16, 200, 65000, 0]).unwrap();
+        let mut dark = SerialImageBuffer::from_vec(2, 2, vec![20u16, 100, 1000, 0]).unwrap();
+        dark.set_metadata(Some(ImageMetaData::default()));
+
+        let diff = (&light - &dark).unwrap();
+        assert_eq!(diff.get_luma().unwrap(), &vec![0, 100, 64000, 0]);
+        assert_eq!(diff.get_metadata(), Some(ImageMetaData::default()));
+
+        let sum = (&light + &dark).unwrap();
+        assert_eq!(sum.get_luma().unwrap(), &vec![30, 300, 65535, 0]);
+
+        let quot = (&light / &dark).unwrap();
+        assert_eq!(quot.get_luma().unwrap(), &vec![1, 2, 65, 0]);
+
+        let scaled = &light * 2;
+        assert_eq!(scaled.get_luma().unwrap(), &vec![20, 400, 65535, 0]);
+
+        let mut img = light.clone();
+        img /= 0;
+        assert_eq!(img.get_luma().unwrap(), &vec![65535, 65535, 65535, 0]);
+    }
+
+    #[test]
+    fn test_incompatible_ops() {
+        let luma = SerialImageBuffer::from_vec(2, 2, vec![0u8; 4]).unwrap();
+        let rgb = SerialImageBuffer::from_vec(2, 2, vec![0u8; 12]).unwrap();
+        let small = SerialImageBuffer::from_vec(1, 2, vec![0u8; 2]).unwrap();
+        assert!((&luma + &rgb).is_err());
+        assert!((&luma + &small).is_err());
+
+        let a = DynamicSerialImage::from(luma.clone());
+        let b = DynamicSerialImage::from(SerialImageBuffer::from_vec(2, 2, vec![0u16; 4]).unwrap());
+        assert!((&a - &b).is_err());
+
+        let mut img = rgb.clone();
+        assert!(img.try_sub_assign(&luma).is_err());
+        assert_eq!(img, rgb);
+        assert!(img.try_add_assign(&rgb).is_ok());
+        let mut c = a.clone();
+        assert!(c.try_mul_assign(&b).is_err());
+        assert_eq!(c, a);
+    }
+
+    #[test]
+    #[should_panic(expected = "Image dimensions do not match")]
+    fn test_incompatible_assign() {
+        let mut img = SerialImageBuffer::from_vec(2, 2, vec![0u8; 4]).unwrap();
+        img -= &SerialImageBuffer::from_vec(1, 2, vec![0u8; 2]).unwrap();
+    }
+
+    #[test]
+    fn test_dynamic_scalar_ops() {
+        let rgba = SerialImageBuffer::from_vec(1, 1, vec![10u8, 20, 30, 255]).unwrap();
+        let img = DynamicSerialImage::from(rgba) * 1.5;
+        let img = img.as_u8().unwrap();
+        assert_eq!(img.get_red().unwrap(), &vec![15]);
+        assert_eq!(img.get_green().unwrap(), &vec![30]);
+        assert_eq!(img.get_blue().unwrap(), &vec![45]);
+        assert_eq!(img.get_alpha().unwrap(), &vec![255]);
+    }
+}
@@ -0,0 +1,235 @@
+#![warn(missing_docs)]
+
+use std::{io, path::Path};
+
+#[cfg(feature = "fitsio")]
+use fitsio::errors::Error as FitsError;
+#[cfg(feature = "fitsio")]
+use std::path::PathBuf;
+
+use image::{ImageError, ImageResult};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::{DynamicSerialImage, StreamOptions};
+
+/// Length of the chunks of encoded data sent by [`ChannelWriter`].
+const CHUNK_LEN: usize = 1 << 20;
+
+/// Run a blocking closure on the blocking thread pool of the runtime.
+///
+/// Panics of the closure are propagated to the caller.
+async fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    join(tokio::task::spawn_blocking(f)).await
+}
+
+/// Wait for a task, propagating its panics to the caller.
+async fn join<T>(task: JoinHandle<T>) -> io::Result<T> {
+    match task.await {
+        Ok(value) => Ok(value),
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(io::Error::new(io::ErrorKind::Interrupted, err)),
+    }
+}
+
+/// Writer used on the blocking thread pool, sending the data in chunks to an async task.
+struct ChannelWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl io::Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_LEN {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            // The receiver is dropped if the async task failed to write the data
+            self.sender
+                .blocking_send(std::mem::take(&mut self.buf))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        Ok(())
+    }
+}
+
+impl DynamicSerialImage {
+    /// Save the image to a file at the path specified, as [`DynamicSerialImage::save`] does,
+    /// without blocking the async runtime.
+    ///
+    /// The image is copied and encoded on the blocking thread pool of the runtime.
+    ///
+    /// # Cancellation
+    /// Dropping the future does not stop the encoding: the file is written even if the
+    /// future is cancelled.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    pub async fn save_async(&self, path: &str) -> ImageResult<()> {
+        let img = self.clone();
+        let path = path.to_owned();
+        blocking(move || img.save(&path))
+            .await
+            .map_err(ImageError::IoError)?
+    }
+
+    /// Save the image data to a FITS file, as [`DynamicSerialImage::savefits`] does,
+    /// without blocking the async runtime.
+    ///
+    /// The image is copied and encoded on the blocking thread pool of the runtime.
+    ///
+    /// # Cancellation
+    /// Dropping the future does not stop the encoding: the file is written even if the
+    /// future is cancelled.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    #[cfg(feature = "fitsio")]
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "tokio", feature = "fitsio"))))]
+    pub async fn savefits_async(
+        &self,
+        dir_prefix: &Path,
+        file_prefix: &str,
+        progname: Option<&str>,
+        compress: bool,
+        overwrite: bool,
+    ) -> Result<PathBuf, FitsError> {
+        let img = self.clone();
+        let dir_prefix = dir_prefix.to_owned();
+        let file_prefix = file_prefix.to_owned();
+        let progname = progname.map(str::to_owned);
+        blocking(move || {
+            img.savefits(
+                &dir_prefix,
+                &file_prefix,
+                progname.as_deref(),
+                compress,
+                overwrite,
+            )
+        })
+        .await
+        .map_err(FitsError::Io)?
+    }
+
+    /// Open an image, as [`DynamicSerialImage::open`] does, without blocking the async runtime.
+    ///
+    /// The file is read and decoded on the blocking thread pool of the runtime.
+    ///
+    /// # Cancellation
+    /// Dropping the future discards the image, and has no other effect.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    pub async fn open_async(path: &Path) -> ImageResult<Self> {
+        let path = path.to_owned();
+        blocking(move || Self::open(&path))
+            .await
+            .map_err(ImageError::IoError)?
+    }
+
+    /// Write the image with its metadata in the binary format of this crate to an
+    /// [`AsyncWrite`], as [`DynamicSerialImage::write_stream`] does.
+    ///
+    /// The image is copied and encoded on the blocking thread pool of the runtime. The encoded
+    /// data is written to `writer` in chunks of 1 MiB as it is produced, so that at most a few
+    /// chunks are held in memory besides the copy of the image, and `writer` is then flushed.
+    ///
+    /// # Cancellation
+    /// This method is not cancellation safe: if the future is dropped while the data is
+    /// being written, part of the image may have been written to `writer`.
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    #[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+    pub async fn write_async<W: AsyncWrite + Unpin>(
+        &self,
+        mut writer: W,
+        options: &StreamOptions,
+    ) -> io::Result<()> {
+        let img = self.clone();
+        let options = options.clone();
+        let (sender, mut receiver) = mpsc::channel(2);
+        let task = tokio::task::spawn_blocking(move || {
+            let mut chunks = ChannelWriter {
+                sender,
+                buf: Vec::with_capacity(CHUNK_LEN),
+            };
+            img.write_stream(&mut chunks, &options)
+        });
+        // The channel is closed once the encoding task ends
+        while let Some(chunk) = receiver.recv().await {
+            writer.write_all(&chunk).await?;
+        }
+        join(task).await??;
+        writer.flush().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ImageMetaData, SerialImageBuffer};
+
+    #[tokio::test]
+    async fn test_async() {
+        let dir = std::env::temp_dir().join(format!("serialimage_async_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut img: DynamicSerialImage =
+            SerialImageBuffer::from_vec(4, 3, (0..36u8).map(|x| x * 7).collect())
+                .unwrap()
+                .into();
+
+        let path = dir.join("image.png");
+        img.save_async(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(DynamicSerialImage::open_async(&path).await.unwrap(), img);
+        assert!(DynamicSerialImage::open_async(&dir.join("missing.png"))
+            .await
+            .is_err());
+
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        img.set_metadata(meta);
+        let mut bytes = Vec::new();
+        img.write_async(&mut bytes, &StreamOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(DynamicSerialImage::read_stream(&bytes[..]).unwrap(), img);
+
+        // Images larger than a chunk are written in several chunks
+        let large: DynamicSerialImage =
+            SerialImageBuffer::from_vec(1200, 1000, (0..1_200_000).map(|x| x as u8).collect())
+                .unwrap()
+                .into();
+        let mut bytes = Vec::new();
+        large
+            .write_async(&mut bytes, &StreamOptions::new())
+            .await
+            .unwrap();
+        assert!(bytes.len() > CHUNK_LEN);
+        assert_eq!(DynamicSerialImage::read_stream(&bytes[..]).unwrap(), large);
+
+        #[cfg(feature = "fitsio")]
+        {
+            let path = img
+                .savefits_async(&dir, "async", None, false, true)
+                .await
+                .unwrap();
+            assert!(path.exists());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+#![warn(missing_docs)]
+
+use serde::{Deserialize, Serialize};
+
+use crate::parallel::map;
+use crate::{Channel, DynamicSerialImage, PixelArithmetic, SerialImageBuffer};
+
+/// Sample (pixel element) types supported by [`DynamicSerialImage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SampleType {
+    /// 8-bit unsigned integer samples.
+    U8,
+    /// 16-bit unsigned integer samples.
+    U16,
+    /// 32-bit floating point samples.
+    F32,
+}
+
+/// Scaling policy used when converting between sample types.
+///
+/// The full scale of a sample type is `255` for [`u8`], `65535` for [`u16`] and `1.0` for [`f32`].
+/// Integer targets are rounded and saturated at the bounds of the type.
+///
+/// The alpha channel is always converted with [`ScalePolicy::Normalize`], so that
+/// opacity is preserved irrespective of the policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalePolicy {
+    /// Map the full scale of the source type to the full scale of the target type,
+    /// e.g. `u16` data is normalized to `[0, 1]` when converted to `f32`.
+    Normalize,
+    /// Keep the raw (ADU) values, e.g. a `u16` value of `1234` becomes `1234.0` in `f32`.
+    Raw,
+    /// Map the source range `[min, max]` to the full scale of the target type.
+    MinMax {
+        /// Source value mapped to zero.
+        min: f32,
+        /// Source value mapped to the full scale of the target type.
+        max: f32,
+    },
+    /// Treat the source data as having the given number of significant bits,
+    /// and map `[0, 2^bits - 1]` to the full scale of the target type.
+    /// For example, `BitDepth(12)` maps the output of a 12-bit ADC stored in `u16` to `[0, 1]` in `f32`.
+    BitDepth(u8),
+}
+
+impl ScalePolicy {
+    /// Get the `(offset, scale)` pair such that `target = (source - offset) * scale`.
+    fn coefficients(&self, src_max: f32, dst_max: f32) -> Result<(f32, f32), &'static str> {
+        match *self {
+            ScalePolicy::Normalize => Ok((0.0, dst_max / src_max)),
+            ScalePolicy::Raw => Ok((0.0, 1.0)),
+            ScalePolicy::MinMax { min, max } => {
+                if !(min.is_finite() && max.is_finite()) || max <= min {
+                    return Err("Scaling range must be finite with max > min");
+                }
+                Ok((min, dst_max / (max - min)))
+            }
+            ScalePolicy::BitDepth(bits) => {
+                if !(1..=32).contains(&bits) {
+                    return Err("Bit depth must be between 1 and 32");
+                }
+                Ok((0.0, dst_max / ((2f64.powi(bits as i32) - 1.0) as f32)))
+            }
+        }
+    }
+}
+
+fn full_scale<T: PixelArithmetic>() -> f32 {
+    T::DEFAULT_MAX_VALUE.to_f32_sample()
+}
+
+fn convert_channel<T: PixelArithmetic, U: PixelArithmetic>(
+    src: &[T],
+    (offset, scale): (f32, f32),
+) -> Vec<U> {
+    map(src, |x| {
+        U::from_f32_sample((x.to_f32_sample() - offset) * scale)
+    })
+}
+
+impl<T: PixelArithmetic> SerialImageBuffer<T> {
+    /// Convert the image to a different sample type using the given [`ScalePolicy`].
+    ///
+    /// All channels, including the alpha channel, are converted, and the metadata is preserved.
+    ///
+    /// Note: Grayscale [`SerialImageBuffer<f32>`] images can not be converted to [`image::DynamicImage`].
+    ///
+    /// # Errors
+    ///  - If the scaling policy is invalid.
+    pub fn convert<U: PixelArithmetic>(
+        &self,
+        policy: ScalePolicy,
+    ) -> Result<SerialImageBuffer<U>, &'static str> {
+        let (src_max, dst_max) = (full_scale::<T>(), full_scale::<U>());
+        let coeffs = policy.coefficients(src_max, dst_max)?;
+        let alpha_coeffs = ScalePolicy::Normalize.coefficients(src_max, dst_max)?;
+        self.map_channels(|channel, data| {
+            if channel == Channel::Alpha {
+                convert_channel(data, alpha_coeffs)
+            } else {
+                convert_channel(data, coeffs)
+            }
+        })
+    }
+}
+
+impl DynamicSerialImage {
+    /// Get the sample type of the image.
+    pub fn sample_type(&self) -> SampleType {
+        match self {
+            DynamicSerialImage::U8(_) => SampleType::U8,
+            DynamicSerialImage::U16(_) => SampleType::U16,
+            DynamicSerialImage::F32(_) => SampleType::F32,
+        }
+    }
+
+    /// Convert the image to a different sample type using the given [`ScalePolicy`].
+    ///
+    /// All channels, including the alpha channel, are converted, and the metadata is preserved.
+    /// See [`SerialImageBuffer::convert`] for details.
+    ///
+    /// # Errors
+    ///  - If the scaling policy is invalid.
+    pub fn convert_to(
+        &self,
+        sample: SampleType,
+        policy: ScalePolicy,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        match (self, sample) {
+            (DynamicSerialImage::U8(value), SampleType::U8) => {
+                Ok(value.convert::<u8>(policy)?.into())
+            }
+            (DynamicSerialImage::U8(value), SampleType::U16) => {
+                Ok(value.convert::<u16>(policy)?.into())
+            }
+            (DynamicSerialImage::U8(value), SampleType::F32) => {
+                Ok(value.convert::<f32>(policy)?.into())
+            }
+            (DynamicSerialImage::U16(value), SampleType::U8) => {
+                Ok(value.convert::<u8>(policy)?.into())
+            }
+            (DynamicSerialImage::U16(value), SampleType::U16) => {
+                Ok(value.convert::<u16>(policy)?.into())
+            }
+            (DynamicSerialImage::U16(value), SampleType::F32) => {
+                Ok(value.convert::<f32>(policy)?.into())
+            }
+            (DynamicSerialImage::F32(value), SampleType::U8) => {
+                Ok(value.convert::<u8>(policy)?.into())
+            }
+            (DynamicSerialImage::F32(value), SampleType::U16) => {
+                Ok(value.convert::<u16>(policy)?.into())
+            }
+            (DynamicSerialImage::F32(value), SampleType::F32) => {
+                Ok(value.convert::<f32>(policy)?.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ImageMetaData;
+
+    #[test]
+    fn test_convert_roundtrip() {
+        let mut img =
+            SerialImageBuffer::from_vec(2, 1, vec![0u16, 4095, 65535, 32768, 100, 0, 1000, 65535])
+                .unwrap();
+        img.set_metadata(Some(ImageMetaData::default()));
+        let img = DynamicSerialImage::from(img);
+
+        let float = img
+            .convert_to(SampleType::F32, ScalePolicy::Normalize)
+            .unwrap();
+        let fbuf = float.as_f32().unwrap();
+        assert_eq!(fbuf.get_blue().unwrap()[0], 1.0);
+        assert_eq!(fbuf.get_alpha().unwrap()[1], 1.0);
+        assert_eq!(float.get_metadata(), Some(ImageMetaData::default()));
+
+        let back = float
+            .convert_to(SampleType::U16, ScalePolicy::Normalize)
+            .unwrap();
+        assert_eq!(back, img);
+
+        let raw = img.convert_to(SampleType::F32, ScalePolicy::Raw).unwrap();
+        assert_eq!(raw.as_f32().unwrap().get_red().unwrap(), &vec![0.0, 100.0]);
+        let back = raw.convert_to(SampleType::U16, ScalePolicy::Raw).unwrap();
+        assert_eq!(back, img);
+    }
+
+    #[test]
+    fn test_convert_policies() {
+        let img = SerialImageBuffer::from_vec(2, 2, vec![0u16, 1024, 2048, 4095]).unwrap();
+        let bytes = img.convert::<u8>(ScalePolicy::BitDepth(12)).unwrap();
+        assert_eq!(bytes.get_luma().unwrap(), &vec![0, 64, 128, 255]);
+
+        let bytes = img
+            .convert::<u8>(ScalePolicy::MinMax {
+                min: 1024.,
+                max: 2048.,
+            })
+            .unwrap();
+        assert_eq!(bytes.get_luma().unwrap(), &vec![0, 0, 255, 255]);
+
+        assert!(img
+            .convert::<u8>(ScalePolicy::MinMax { min: 1., max: 1. })
+            .is_err());
+        assert!(img.convert::<u8>(ScalePolicy::BitDepth(0)).is_err());
+    }
+}
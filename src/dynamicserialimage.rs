@@ -103,7 +103,8 @@ impl DynamicSerialImage {
         }
     }
 
-    /// Convert the image to grayscale. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue` (ITU-R BT.709) for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// See [`DynamicSerialImage::into_luma_with`] for other weights and output types.
     pub fn into_luma(&self) -> SerialImageBuffer<u16> {
         match self {
             DynamicSerialImage::U8(value) => value.into_luma(),
@@ -112,7 +113,8 @@ impl DynamicSerialImage {
         }
     }
 
-    /// Convert the image to grayscale with alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale with alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue` (ITU-R BT.709) for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// See [`DynamicSerialImage::into_luma_alpha_with`] for other weights and output types.
     pub fn into_luma_alpha(&self) -> SerialImageBuffer<u16> {
         match self {
             DynamicSerialImage::U8(value) => value.into_luma_alpha(),
@@ -137,6 +139,10 @@ impl DynamicSerialImage {
     ///
     /// The image format is derived from the file extension.
     /// `png`, `jpg`, `bmp`, `ico`, `tiff` and `exr` files are supported.
+    /// The image metadata is not saved, use [`DynamicSerialImage::save_tiff`],
+    /// [`DynamicSerialImage::save_png`] or [`DynamicSerialImage::save_with_sidecar`] to preserve it.
+    /// Use [`DynamicSerialImage::save_with`] to choose the encoder options or write to any writer.
+    /// Images are loaded with [`DynamicSerialImage::open`].
     pub fn save(&self, path: &str) -> ImageResult<()> {
         let img: DynamicImage = self.into();
         img.save(path)
@@ -150,7 +156,7 @@ impl DynamicSerialImage {
     ///  * `dir_prefix` - The directory where the file will be saved.
     ///  * `file_prefix` - The prefix of the file name. The file name will be of the form `{file_prefix}_{timestamp}.fits`.
     ///  * `progname` - The name of the program that generated the image.
-    ///  * `compress` - Whether to compress the FITS file.
+    ///  * `compress` - Whether to compress the FITS file, using [`FitsCompression::Rice`](crate::FitsCompression::Rice).
     ///  * `overwrite` - Whether to overwrite the file if it already exists.
     ///
     /// # Errors
@@ -0,0 +1,334 @@
+#![warn(missing_docs)]
+
+use std::{
+    borrow::Cow,
+    io::{Seek, Write},
+};
+
+use image::{
+    codecs::jpeg::JpegEncoder,
+    error::{EncodingError, ImageFormatHint},
+    DynamicImage, ImageBuffer, ImageError, ImageFormat, ImageResult, Rgb, Rgba,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{DynamicSerialImage, SampleType, ScalePolicy, SerialImageBuffer};
+
+/// Compression level of PNG images.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PngCompression {
+    /// Balance between encoding speed and file size.
+    #[default]
+    Default,
+    /// Fast encoding, with larger files.
+    Fast,
+    /// Smallest files, with slow encoding.
+    Best,
+    /// No compression, the fastest encoding.
+    None,
+}
+
+/// Row filter of PNG images, applied before compression.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PngFilter {
+    /// No filter.
+    NoFilter,
+    /// Difference to the previous pixel.
+    Sub,
+    /// Difference to the pixel above.
+    Up,
+    /// Difference to the average of the previous pixel and the pixel above.
+    Avg,
+    /// Difference to the Paeth predictor of the neighboring pixels.
+    Paeth,
+    /// The best filter for each row.
+    #[default]
+    Adaptive,
+}
+
+/// Compression of TIFF images.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TiffCompression {
+    /// The image is not compressed.
+    #[default]
+    None,
+    /// LZW compression.
+    Lzw,
+    /// Deflate (zlib) compression.
+    Deflate,
+    /// PackBits run-length encoding.
+    PackBits,
+}
+
+/// Options for encoding images with [`DynamicSerialImage::save_with`].
+///
+/// The default values are:
+/// * `jpeg_quality` - `75`
+/// * `png_compression` - [`PngCompression::Default`]
+/// * `png_filter` - `None`, chosen for the compression level
+/// * `tiff_compression` - [`TiffCompression::None`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderOptions {
+    pub(crate) jpeg_quality: u8,
+    pub(crate) png_compression: PngCompression,
+    pub(crate) png_filter: Option<PngFilter>,
+    pub(crate) tiff_compression: TiffCompression,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            jpeg_quality: 75,
+            png_compression: PngCompression::Default,
+            png_filter: None,
+            tiff_compression: TiffCompression::None,
+        }
+    }
+}
+
+impl EncoderOptions {
+    /// Create the default encoder options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the quality of JPEG images, from `1` (smallest files) to `100` (best quality).
+    /// Values outside this range are clamped.
+    pub fn jpeg_quality(mut self, quality: u8) -> Self {
+        self.jpeg_quality = quality.clamp(1, 100);
+        self
+    }
+
+    /// Set the compression level of PNG images.
+    pub fn png_compression(mut self, compression: PngCompression) -> Self {
+        self.png_compression = compression;
+        self
+    }
+
+    /// Set the row filter of PNG images.
+    pub fn png_filter(mut self, filter: PngFilter) -> Self {
+        self.png_filter = Some(filter);
+        self
+    }
+
+    /// Set the compression of TIFF images.
+    pub fn tiff_compression(mut self, compression: TiffCompression) -> Self {
+        self.tiff_compression = compression;
+        self
+    }
+}
+
+/// Largest sample type that can be stored in an image format.
+fn max_sample_type(format: ImageFormat) -> SampleType {
+    match format {
+        ImageFormat::Tiff | ImageFormat::OpenExr | ImageFormat::Hdr => SampleType::F32,
+        ImageFormat::Png | ImageFormat::Pnm | ImageFormat::Farbfeld => SampleType::U16,
+        _ => SampleType::U8,
+    }
+}
+
+/// Convert the channels of an image to a layout supported by the encoder of a format.
+fn supported_layout(img: DynamicImage, format: ImageFormat) -> DynamicImage {
+    let gray = matches!(
+        img,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+    );
+    let alpha = img.color().has_alpha();
+    let deep = img.color().bytes_per_pixel() / img.color().channel_count() > 1;
+    match format {
+        ImageFormat::Jpeg if gray => img.into_luma8().into(),
+        ImageFormat::Jpeg => img.into_rgb8().into(),
+        ImageFormat::Gif | ImageFormat::Qoi if alpha => img.into_rgba8().into(),
+        ImageFormat::Gif | ImageFormat::Qoi => img.into_rgb8().into(),
+        ImageFormat::Pnm if gray && deep => img.into_luma16().into(),
+        ImageFormat::Pnm if gray => img.into_luma8().into(),
+        ImageFormat::Pnm if deep => img.into_rgb16().into(),
+        ImageFormat::Pnm => img.into_rgb8().into(),
+        ImageFormat::Farbfeld => img.into_rgba16().into(),
+        ImageFormat::Hdr => img.into_rgb32f().into(),
+        _ => img,
+    }
+}
+
+/// Convert an [`f32`] image to [`DynamicImage`], expanding grayscale images to RGB.
+fn float_image(img: &SerialImageBuffer<f32>) -> DynamicImage {
+    let (width, height) = (img.width() as u32, img.height() as u32);
+    let data = img.to_interleaved();
+    match img.pixel_elems() {
+        1 => ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([data[(y * width + x) as usize]; 3])
+        })
+        .into(),
+        2 => ImageBuffer::from_fn(width, height, |x, y| {
+            let idx = 2 * (y * width + x) as usize;
+            let v = data[idx];
+            Rgba([v, v, v, data[idx + 1]])
+        })
+        .into(),
+        _ => img.clone().into(),
+    }
+}
+
+impl DynamicSerialImage {
+    /// Encode the image in the given format to any writer, with the given [`EncoderOptions`].
+    ///
+    /// PNG and TIFF images are written with their metadata, as with [`DynamicSerialImage::write_png`]
+    /// and [`DynamicSerialImage::write_tiff`]. The other formats are encoded by the [`image`] crate,
+    /// without metadata.
+    ///
+    /// Samples are converted to the deepest sample type supported by the format, with
+    /// [`ScalePolicy::Normalize`]: 16-bit for PNG, PNM and farbfeld, 32-bit floating point
+    /// for OpenEXR and Radiance HDR, and 8-bit for the other formats except TIFF, which stores all
+    /// sample types. The channels are converted if required by the encoder, e.g. JPEG images are
+    /// stored as grayscale or RGB without the alpha channel.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. if the format can not be encoded.
+    pub fn save_with<W: Write + Seek>(
+        &self,
+        mut writer: W,
+        format: ImageFormat,
+        options: &EncoderOptions,
+    ) -> ImageResult<()> {
+        let img = self.reduced(format)?;
+        match (format, img.as_ref()) {
+            (ImageFormat::Png, DynamicSerialImage::U8(img)) => {
+                img.write_png_with(writer, options.png_compression, options.png_filter)
+            }
+            (ImageFormat::Png, DynamicSerialImage::U16(img)) => {
+                img.write_png_with(writer, options.png_compression, options.png_filter)
+            }
+            (ImageFormat::Tiff, DynamicSerialImage::U8(img)) => {
+                img.write_tiff_with(writer, options.tiff_compression)
+            }
+            (ImageFormat::Tiff, DynamicSerialImage::U16(img)) => {
+                img.write_tiff_with(writer, options.tiff_compression)
+            }
+            (ImageFormat::Tiff, DynamicSerialImage::F32(img)) => {
+                img.write_tiff_with(writer, options.tiff_compression)
+            }
+            (_, img) => {
+                let img = match img {
+                    DynamicSerialImage::F32(img) => float_image(img),
+                    img => img.into(),
+                };
+                let img = supported_layout(img, format);
+                if format == ImageFormat::Jpeg {
+                    img.write_with_encoder(JpegEncoder::new_with_quality(
+                        &mut writer,
+                        options.jpeg_quality,
+                    ))
+                } else {
+                    img.write_to(&mut writer, format)
+                }
+            }
+        }
+    }
+
+    /// Convert the image to the deepest sample type supported by a format.
+    fn reduced(&self, format: ImageFormat) -> ImageResult<Cow<'_, DynamicSerialImage>> {
+        let target = match (self.sample_type(), max_sample_type(format)) {
+            (_, SampleType::F32) if format != ImageFormat::Tiff => SampleType::F32,
+            (SampleType::F32, max) => max,
+            (SampleType::U16, SampleType::U8) => SampleType::U8,
+            (sample, _) => sample,
+        };
+        if target == self.sample_type() {
+            return Ok(Cow::Borrowed(self));
+        }
+        self.convert_to(target, ScalePolicy::Normalize)
+            .map(Cow::Owned)
+            .map_err(|err| {
+                ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(format), err))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ImageMetaData;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_save_with() {
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        let mut img: DynamicSerialImage =
+            SerialImageBuffer::from_vec(16, 16, (0..1024u16).map(|x| x / 64 * 0x1111).collect())
+                .unwrap()
+                .into();
+        img.set_metadata(meta);
+        let encode = |img: &DynamicSerialImage, format, options: &EncoderOptions| {
+            let mut bytes = Cursor::new(Vec::new());
+            img.save_with(&mut bytes, format, options).unwrap();
+            bytes.into_inner()
+        };
+
+        let options = EncoderOptions::new()
+            .png_compression(PngCompression::Best)
+            .png_filter(PngFilter::Paeth);
+        let bytes = encode(&img, ImageFormat::Png, &options);
+        assert_eq!(
+            DynamicSerialImage::read_png(Cursor::new(&bytes)).unwrap(),
+            img
+        );
+        let uncompressed = encode(
+            &img,
+            ImageFormat::Png,
+            &EncoderOptions::new().png_compression(PngCompression::None),
+        );
+        assert!(bytes.len() < uncompressed.len());
+
+        let uncompressed = encode(&img, ImageFormat::Tiff, &EncoderOptions::new());
+        for compression in [
+            TiffCompression::Lzw,
+            TiffCompression::Deflate,
+            TiffCompression::PackBits,
+        ] {
+            let options = EncoderOptions::new().tiff_compression(compression);
+            let bytes = encode(&img, ImageFormat::Tiff, &options);
+            assert!(bytes.len() < uncompressed.len());
+            assert_eq!(
+                DynamicSerialImage::read_tiff(Cursor::new(&bytes)).unwrap(),
+                img
+            );
+        }
+
+        // 16-bit RGBA images are stored as 8-bit RGB JPEG images
+        let low = encode(
+            &img,
+            ImageFormat::Jpeg,
+            &EncoderOptions::new().jpeg_quality(10),
+        );
+        let high = encode(
+            &img,
+            ImageFormat::Jpeg,
+            &EncoderOptions::new().jpeg_quality(100),
+        );
+        assert!(low.len() < high.len());
+        let decoded = image::load_from_memory(&low).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgb8);
+        assert_eq!(decoded.width(), 16);
+
+        // Grayscale floating point images
+        let gray: DynamicSerialImage =
+            SerialImageBuffer::from_vec(2, 2, vec![0f32, 0.25, 0.5, 1.0])
+                .unwrap()
+                .into();
+        let bytes = encode(&gray, ImageFormat::Png, &EncoderOptions::new());
+        let decoded = DynamicSerialImage::read_png(Cursor::new(&bytes)).unwrap();
+        assert_eq!(
+            decoded.as_u16().unwrap().to_interleaved(),
+            vec![0, 16384, 32768, 65535]
+        );
+        let bytes = encode(&gray, ImageFormat::Bmp, &EncoderOptions::new());
+        assert_eq!(image::load_from_memory(&bytes).unwrap().width(), 2);
+        let bytes = encode(&gray, ImageFormat::OpenExr, &EncoderOptions::new());
+        let decoded = image::load_from_memory(&bytes).unwrap().into_rgb32f();
+        assert_eq!(decoded.get_pixel(1, 0).0, [0.25; 3]);
+    }
+}
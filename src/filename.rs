@@ -0,0 +1,366 @@
+#![warn(missing_docs)]
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::format::{Item, StrftimeItems};
+use image::{ImageError, ImageFormat, ImageResult};
+
+use crate::{imagemetadata::utc_datetime, DynamicSerialImage, ImageMetaData};
+
+/// Default format of the `{timestamp}` field, with millisecond resolution.
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y%m%d_%H%M%S_%3f";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Camera,
+    Timestamp(String),
+    Exposure,
+    ExposureMs,
+    ExposureUs,
+    Gain,
+    Offset,
+    Sequence(usize),
+    Attribute(String),
+}
+
+/// File name template, used to build file names from the image metadata.
+///
+/// A template is a string with fields enclosed in braces, e.g. `{camera}_{timestamp}_{seq:4}.fits`.
+/// The supported fields are:
+/// * `{camera}` - The camera name.
+/// * `{timestamp}` - The timestamp of the image in UTC, as `YYYYmmdd_HHMMSS_mmm`.
+/// * `{timestamp:FORMAT}` - The timestamp of the image in UTC, with a [`chrono` format string](https://docs.rs/chrono/latest/chrono/format/strftime/index.html).
+/// * `{exposure}`, `{exposure_ms}`, `{exposure_us}` - The exposure time in seconds, milliseconds or microseconds.
+/// * `{gain}`, `{offset}` - The gain and offset (raw).
+/// * `{seq}`, `{seq:N}` - The sequence counter of the template, zero-padded to `N` digits.
+///   The counter starts at zero and is incremented every time a file name is built.
+/// * `{attr:KEY}` - The value of the extended attribute `KEY`, or an empty string.
+///
+/// Literal braces are written as `{{` and `}}`. Characters that are not allowed in file
+/// names (`/ \ : * ? " < > |` and control characters) are replaced by `_` in the field values.
+///
+/// If the image has no metadata, the camera name is `unknown`, the timestamp is the current
+/// time, and the numeric fields are zero.
+#[derive(Debug)]
+pub struct FilenameTemplate {
+    tokens: Vec<Token>,
+    counter: AtomicU64,
+}
+
+impl Clone for FilenameTemplate {
+    fn clone(&self) -> Self {
+        Self {
+            tokens: self.tokens.clone(),
+            counter: AtomicU64::new(self.counter.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl FilenameTemplate {
+    /// Create a new file name template.
+    ///
+    /// # Errors
+    ///  - If a field is not closed, or the field name is unknown.
+    ///  - If a literal `}` is not escaped.
+    pub fn new(template: &str) -> Result<Self, &'static str> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err("Unescaped '}' in file name template"),
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err("Unclosed field in file name template"),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                    }
+                    tokens.push(Self::parse_field(&field)?);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(Self {
+            tokens,
+            counter: AtomicU64::new(0),
+        })
+    }
+
+    fn parse_field(field: &str) -> Result<Token, &'static str> {
+        let (name, arg) = match field.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (field, None),
+        };
+        match (name, arg) {
+            ("camera", None) => Ok(Token::Camera),
+            ("timestamp", None) => Ok(Token::Timestamp(DEFAULT_TIMESTAMP_FORMAT.to_owned())),
+            ("timestamp", Some(format)) => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err("Invalid timestamp format in file name template");
+                }
+                Ok(Token::Timestamp(format.to_owned()))
+            }
+            ("exposure", None) => Ok(Token::Exposure),
+            ("exposure_ms", None) => Ok(Token::ExposureMs),
+            ("exposure_us", None) => Ok(Token::ExposureUs),
+            ("gain", None) => Ok(Token::Gain),
+            ("offset", None) => Ok(Token::Offset),
+            ("seq", None) => Ok(Token::Sequence(0)),
+            ("seq", Some(width)) => width
+                .parse()
+                .map(Token::Sequence)
+                .map_err(|_| "Invalid sequence width in file name template"),
+            ("attr", Some(key)) => Ok(Token::Attribute(key.to_owned())),
+            _ => Err("Unknown field in file name template"),
+        }
+    }
+
+    /// Escape braces in `text`, so that it can be used as literal text in a template.
+    pub fn escape(text: &str) -> String {
+        text.replace('{', "{{").replace('}', "}}")
+    }
+
+    /// Get the current value of the sequence counter.
+    pub fn sequence(&self) -> u64 {
+        self.counter.load(Ordering::Relaxed)
+    }
+
+    /// Set the sequence counter.
+    pub fn set_sequence(&self, seq: u64) {
+        self.counter.store(seq, Ordering::Relaxed);
+    }
+
+    /// Build a file name from the image metadata, and increment the sequence counter.
+    pub fn render(&self, meta: Option<&ImageMetaData>) -> String {
+        let seq = self.counter.fetch_add(1, Ordering::Relaxed);
+        let mut name = String::new();
+        for token in &self.tokens {
+            let value = match token {
+                Token::Literal(text) => {
+                    name.push_str(text);
+                    continue;
+                }
+                Token::Camera => meta
+                    .map(|m| m.camera_name.clone())
+                    .unwrap_or_else(|| "unknown".to_owned()),
+                Token::Timestamp(format) => {
+                    let timestamp = meta.map(|m| m.timestamp).unwrap_or_else(SystemTime::now);
+                    let timestamp = utc_datetime(timestamp.max(UNIX_EPOCH));
+                    timestamp.format(format).to_string()
+                }
+                Token::Exposure => meta
+                    .map(|m| m.exposure.as_secs_f64())
+                    .unwrap_or(0.)
+                    .to_string(),
+                Token::ExposureMs => meta
+                    .map(|m| m.exposure.as_millis())
+                    .unwrap_or(0)
+                    .to_string(),
+                Token::ExposureUs => meta
+                    .map(|m| m.exposure.as_micros())
+                    .unwrap_or(0)
+                    .to_string(),
+                Token::Gain => meta.map(|m| m.gain).unwrap_or(0).to_string(),
+                Token::Offset => meta.map(|m| m.offset).unwrap_or(0).to_string(),
+                Token::Sequence(width) => format!("{:0width$}", seq, width = width),
+                Token::Attribute(key) => meta
+                    .and_then(|m| {
+                        m.get_extended_data()
+                            .iter()
+                            .find(|(k, _)| k == key)
+                            .map(|(_, v)| v.clone())
+                    })
+                    .unwrap_or_default(),
+            };
+            name.extend(value.chars().map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            }));
+        }
+        name
+    }
+
+    /// Build a path in `dir` from the image metadata, and increment the sequence counter.
+    ///
+    /// If a file with the same name exists, a suffix `_1`, `_2`, ... is appended to the
+    /// file stem until the path is unique.
+    ///
+    /// Note:
+    ///  - The path is not reserved, so another process may create a file at the same path
+    ///    before it is used. Use [`FilenameTemplate::create_unique`] to create the file.
+    pub fn unique_path(&self, dir: &Path, meta: Option<&ImageMetaData>) -> PathBuf {
+        unique_path(dir.join(self.render(meta)))
+    }
+
+    /// Build a path in `dir` from the image metadata, increment the sequence counter, and
+    /// create an empty file at the path.
+    ///
+    /// If a file with the same name exists, a suffix `_1`, `_2`, ... is appended to the
+    /// file stem until a new file can be created. The file is created atomically, so
+    /// concurrent calls never return the same path.
+    ///
+    /// Returns the path and the file, opened for writing.
+    ///
+    /// # Errors
+    ///  * Any error of the file system, e.g. if `dir` does not exist.
+    pub fn create_unique(
+        &self,
+        dir: &Path,
+        meta: Option<&ImageMetaData>,
+    ) -> io::Result<(PathBuf, File)> {
+        create_unique(dir.join(self.render(meta)))
+    }
+}
+
+/// Get the path with a suffix `_idx` appended to the file stem.
+fn with_suffix(path: &Path, idx: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    path.with_file_name(format!("{}_{}{}", stem, idx, ext))
+}
+
+/// De-duplicate a path by appending a suffix `_1`, `_2`, ... to the file stem.
+pub(crate) fn unique_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    (1..)
+        .map(|idx| with_suffix(&path, idx))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Create a new file at the path, or at the path with a suffix `_1`, `_2`, ... appended to the
+/// file stem if a file exists.
+pub(crate) fn create_unique(path: PathBuf) -> io::Result<(PathBuf, File)> {
+    let mut candidate = path.clone();
+    for idx in 1.. {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                candidate = with_suffix(&path, idx);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+impl DynamicSerialImage {
+    /// Save the image to a file in `dir`, with the name built from the image metadata
+    /// using a [`FilenameTemplate`]. The file name is de-duplicated, so existing files are never overwritten.
+    ///
+    /// The image format is derived from the file extension of the template, see [`DynamicSerialImage::save`].
+    ///
+    /// Returns the path of the saved file.
+    pub fn save_template(&self, dir: &Path, template: &FilenameTemplate) -> ImageResult<PathBuf> {
+        let (path, file) = template.create_unique(dir, self.get_metadata().as_ref())?;
+        let img: image::DynamicImage = self.into();
+        let mut writer = BufWriter::new(file);
+        let res = ImageFormat::from_path(&path)
+            .and_then(|format| img.write_to(&mut writer, format))
+            .and_then(|_| writer.flush().map_err(ImageError::IoError));
+        if let Err(err) = res {
+            let _ = std::fs::remove_file(&path);
+            return Err(err);
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_template() {
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "ZWO/ASI".into();
+        meta.timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        meta.exposure = Duration::from_millis(1500);
+        meta.gain = 100;
+        meta.add_extended_attrib("FILTER", "Ha");
+        let template = FilenameTemplate::new(
+            "{camera}_{timestamp}_{exposure}s_g{gain}_{attr:FILTER}_{seq:3}{{x}}.fits",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(Some(&meta)),
+            "ZWO_ASI_20231114_221320_123_1.5s_g100_Ha_000{x}.fits"
+        );
+        let template = FilenameTemplate::new("{timestamp:%H%M}_{seq}").unwrap();
+        template.set_sequence(7);
+        assert_eq!(template.render(Some(&meta)), "2213_7");
+        assert_eq!(template.sequence(), 8);
+        assert!(FilenameTemplate::new("{camera").is_err());
+        assert!(FilenameTemplate::new("{nope}").is_err());
+        assert!(FilenameTemplate::new("a}b").is_err());
+        assert!(FilenameTemplate::new("{timestamp:%Q}").is_err());
+    }
+
+    #[test]
+    fn test_unique_path() {
+        let dir = std::env::temp_dir().join(format!("serialimage_unique_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = DynamicSerialImage::from_vec_u8(2, 2, vec![0, 64, 128, 255]).unwrap();
+        let template = FilenameTemplate::new("image.png").unwrap();
+        let first = img.save_template(&dir, &template).unwrap();
+        let second = img.save_template(&dir, &template).unwrap();
+        assert_ne!(first, second);
+        assert!(second.ends_with("image_1.png"));
+        assert_eq!(template.unique_path(&dir, None), dir.join("image_2.png"));
+
+        // Concurrent saves get distinct files
+        let paths: Vec<PathBuf> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| template.create_unique(&dir, None).unwrap().0))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let mut unique = paths.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), paths.len());
+
+        let template = FilenameTemplate::new("image.unknown").unwrap();
+        assert!(img.save_template(&dir, &template).is_err());
+        assert!(!dir.join("image.unknown").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
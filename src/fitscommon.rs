@@ -0,0 +1,482 @@
+//! Backend-independent parts of the FITS support: header keys, sample scaling and metadata.
+//!
+//! The header keys are also embedded in XISF files.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{imagemetadata::utc_datetime, ImageMetaData};
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+use crate::{Channel, FitsScaling, PixelArithmetic, SerialImageBuffer};
+
+/// Header keys written by [`header_cards`] that are not extended attributes.
+pub(crate) const RESERVED_KEYS: [&str; 23] = [
+    "CHANNELS",
+    "PROGRAM",
+    "DATE",
+    "DATE-OBS",
+    "EXPTIME",
+    "CCD-TEMP",
+    "XBINNING",
+    "YBINNING",
+    "XORGSUBF",
+    "YORGSUBF",
+    "INSTRUME",
+    "GAIN",
+    "OFFSET",
+    "GAIN_MIN",
+    "GAIN_MAX",
+    // Legacy keys
+    "CAMERA",
+    "TIMESTAMP",
+    "TEMPERATURE",
+    "EXPOSURE_US",
+    "ORIGIN_X",
+    "ORIGIN_Y",
+    "BIN_X",
+    "BIN_Y",
+];
+
+/// Length of a FITS header card.
+pub(crate) const CARD_LEN: usize = 80;
+
+/// Maximum length of HIERARCH key names, so that at least 26 characters remain for the value.
+const MAX_HIERARCH_KEY_LEN: usize = 40;
+
+/// Comment of the `BZERO` and `BSCALE` keys.
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+pub(crate) const SCALE_COMMENT: &str = "Physical value = BZERO + BSCALE * stored";
+
+/// Mandatory and reserved keys of the FITS standard and of the tiled image compression
+/// convention, which can not be used by extended attributes.
+const STANDARD_KEYS: [&str; 45] = [
+    "SIMPLE", "BITPIX", "NAXIS", "EXTEND", "END", "XTENSION", "EXTNAME", "EXTVER", "EXTLEVEL",
+    "PCOUNT", "GCOUNT", "GROUPS", "BZERO", "BSCALE", "BLANK", "BUNIT", "DATAMIN", "DATAMAX",
+    "TFIELDS", "THEAP", "CHECKSUM", "DATASUM", "COMMENT", "HISTORY", "CONTINUE", "INHERIT",
+    "ZIMAGE", "ZBITPIX", "ZNAXIS", "ZCMPTYPE", "ZQUANTIZ", "ZDITHER0", "ZSIMPLE", "ZEXTEND",
+    "ZBLOCKED", "ZTENSION", "ZPCOUNT", "ZGCOUNT", "ZHECKSUM", "ZDATASUM", "ZBLANK", "ZSCALE",
+    "ZZERO", "ZMASKCMP", "HIERARCH",
+];
+
+/// Prefixes of the indexed standard keys, e.g. `NAXIS1` or `TTYPE2`.
+const INDEXED_KEYS: [&str; 16] = [
+    "NAXIS", "ZNAXIS", "ZTILE", "ZNAME", "ZVAL", "TTYPE", "TFORM", "TUNIT", "TSCAL", "TZERO",
+    "TNULL", "TDISP", "TDIM", "TBCOL", "PTYPE", "PSCAL",
+];
+
+/// Check if a header key is reserved, i.e. written by [`header_cards`] or by the FITS
+/// standard, so that it is neither written nor read as an extended attribute.
+pub(crate) fn is_reserved_key(key: &str) -> bool {
+    RESERVED_KEYS.contains(&key)
+        || STANDARD_KEYS.contains(&key)
+        || INDEXED_KEYS.iter().any(|prefix| {
+            key.strip_prefix(prefix)
+                .map_or(false, |index| index.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+/// Name of the image extension holding a channel in the [`FitsLayout::Extensions`](crate::FitsLayout::Extensions) layout.
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+pub(crate) fn extension_name(channel: Channel) -> &'static str {
+    match channel {
+        Channel::Luma | Channel::Red => "PRIMARY",
+        Channel::Green => "GREEN",
+        Channel::Blue => "BLUE",
+        Channel::Alpha => "ALPHA",
+    }
+}
+
+/// Number of quantization steps of [`FitsScaling::Quantize`].
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+const QUANTIZE_STEPS: f64 = u16::MAX as f64;
+
+/// Round a value to the 15 significant digits written to real header keys,
+/// so that the samples are scaled with exactly the values read back from the header.
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+fn fits_real(value: f64) -> f64 {
+    format!("{:.14E}", value).parse().unwrap_or(value)
+}
+
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+impl<T: PixelArithmetic> SerialImageBuffer<T> {
+    /// Get the `(BZERO, BSCALE)` used to store the samples as 16-bit integers,
+    /// or `None` if the samples are stored in their native type.
+    ///
+    /// `unsigned` is set for [`u16`] samples, which are stored with the standard offset of 32768.
+    pub(crate) fn fits_scale(
+        &self,
+        scaling: FitsScaling,
+        unsigned: bool,
+    ) -> Result<Option<(f64, f64)>, &'static str> {
+        match scaling {
+            FitsScaling::Native if unsigned => Ok(Some((32768., 1.))),
+            FitsScaling::Native => Ok(None),
+            FitsScaling::Linear { bzero, bscale } => {
+                if !bzero.is_finite() || !bscale.is_finite() || bscale == 0. {
+                    return Err("BZERO and BSCALE must be finite, and BSCALE non-zero");
+                }
+                Ok(Some((fits_real(bzero), fits_real(bscale))))
+            }
+            FitsScaling::Quantize => {
+                let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+                for &channel in self.channels() {
+                    for x in self.channel_data(channel).as_ref().unwrap().iter() {
+                        let x = f64::from(x.to_f32_sample());
+                        if !x.is_finite() {
+                            return Err("Cannot quantize non-finite samples");
+                        }
+                        min = min.min(x);
+                        max = max.max(x);
+                    }
+                }
+                let bscale = if max > min {
+                    fits_real((max - min) / QUANTIZE_STEPS)
+                } else {
+                    1.
+                };
+                Ok(Some((
+                    fits_real(min - f64::from(i16::MIN) * bscale),
+                    bscale,
+                )))
+            }
+        }
+    }
+}
+
+/// Scale samples to the 16-bit integers stored with `(BZERO, BSCALE)`.
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+pub(crate) fn scale_samples<T: PixelArithmetic>(
+    data: &[T],
+    (bzero, bscale): (f64, f64),
+) -> Vec<i16> {
+    data.iter()
+        .map(|x| {
+            let x = f64::from(x.to_f32_sample());
+            // Saturating conversion, NaN is stored as zero
+            ((x - bzero) / bscale).round() as i16
+        })
+        .collect()
+}
+
+/// Value of a header card.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CardValue {
+    Int(i64),
+    Float(f32),
+    Double(f64),
+    Str(String),
+}
+
+/// Header card written by the crate.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Card {
+    /// Key of the card, without the HIERARCH prefix. Keys longer than eight characters
+    /// must be written using the HIERARCH convention.
+    pub key: String,
+    pub value: CardValue,
+    pub comment: &'static str,
+}
+
+impl fmt::Display for CardValue {
+    /// Format the value as written in a header card.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CardValue::Int(value) => write!(f, "{}", value),
+            CardValue::Float(value) => f.write_str(&format!("{:?}", value).to_uppercase()),
+            CardValue::Double(value) => f.write_str(&format!("{:?}", value).to_uppercase()),
+            // Quotes are escaped by doubling them, and strings are padded to eight characters
+            CardValue::Str(value) => write!(f, "'{:<8}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+impl Card {
+    fn new(key: &str, value: CardValue, comment: &'static str) -> Self {
+        Self {
+            key: key.to_owned(),
+            value,
+            comment,
+        }
+    }
+}
+
+/// Build the header cards describing the image and its metadata.
+pub(crate) fn header_cards(
+    channels: usize,
+    meta: Option<&ImageMetaData>,
+    progname: Option<&str>,
+    legacy_keys: bool,
+) -> Vec<Card> {
+    use CardValue::*;
+    let mut cards = vec![
+        Card::new("CHANNELS", Int(channels as i64), "Number of image channels"),
+        Card::new(
+            "PROGRAM",
+            Str(progname.unwrap_or("unknown").to_owned()),
+            "Program that created the file",
+        ),
+        Card::new(
+            "DATE",
+            Str(iso_date(SystemTime::now())),
+            "[UTC] Date the file was written",
+        ),
+    ];
+    if legacy_keys {
+        let (camera, timestamp) = match meta {
+            Some(meta) => (meta.camera_name.as_str(), meta.timestamp),
+            None => ("unknown", SystemTime::now()),
+        };
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_millis() as i64;
+        cards.push(Card::new("CAMERA", Str(camera.to_owned()), ""));
+        cards.push(Card::new("TIMESTAMP", Int(timestamp), ""));
+    }
+    let meta = match meta {
+        Some(meta) => meta,
+        None => return cards,
+    };
+    cards.extend([
+        Card::new(
+            "DATE-OBS",
+            Str(iso_date(meta.timestamp)),
+            "[UTC] Start of exposure",
+        ),
+        Card::new(
+            "EXPTIME",
+            Double(meta.exposure.as_secs_f64()),
+            "[s] Exposure time",
+        ),
+        Card::new(
+            "CCD-TEMP",
+            Float(meta.temperature),
+            "[C] Sensor temperature",
+        ),
+        Card::new("XBINNING", Int(meta.bin_x.into()), "Binning factor in X"),
+        Card::new("YBINNING", Int(meta.bin_y.into()), "Binning factor in Y"),
+        Card::new(
+            "XORGSUBF",
+            Int(meta.img_left.into()),
+            "[px] Subframe origin in X, binned",
+        ),
+        Card::new(
+            "YORGSUBF",
+            Int(meta.img_top.into()),
+            "[px] Subframe origin in Y, binned",
+        ),
+        Card::new("INSTRUME", Str(meta.camera_name.clone()), "Camera name"),
+        Card::new("GAIN", Int(meta.gain), "Gain (raw)"),
+        Card::new("OFFSET", Int(meta.offset), "Offset (raw)"),
+        Card::new("GAIN_MIN", Int(meta.min_gain.into()), "Minimum gain (raw)"),
+        Card::new("GAIN_MAX", Int(meta.max_gain.into()), "Maximum gain (raw)"),
+    ]);
+    if legacy_keys {
+        cards.extend([
+            Card::new("TEMPERATURE", Float(meta.temperature), ""),
+            Card::new("EXPOSURE_US", Int(meta.exposure.as_micros() as i64), ""),
+            Card::new("ORIGIN_X", Int(meta.img_left.into()), ""),
+            Card::new("ORIGIN_Y", Int(meta.img_top.into()), ""),
+            Card::new("BIN_X", Int(meta.bin_x.into()), ""),
+            Card::new("BIN_Y", Int(meta.bin_y.into()), ""),
+        ]);
+    }
+    cards.extend(
+        meta.get_extended_data()
+            .iter()
+            .filter_map(|(key, value)| extended_card(key, value)),
+    );
+    cards
+}
+
+/// Build the string-valued header card of an extended attribute.
+///
+/// Keys are converted to upper case, and characters other than letters, digits, `-` and `_`
+/// are replaced by `_`. Keys longer than eight characters are truncated to [`MAX_HIERARCH_KEY_LEN`]
+/// characters. Values are truncated to fit in a single header card. Keys that collide with the
+/// keys written by the crate are skipped.
+fn extended_card(key: &str, value: &str) -> Option<Card> {
+    let key: String = key
+        .trim()
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9' | '-' | '_') => c,
+            _ => '_',
+        })
+        .collect();
+    if key.is_empty() || is_reserved_key(&key) {
+        return None;
+    }
+    // Space left for the quoted value: `KEYWORD = 'value'` or `HIERARCH KEY = 'value'`
+    let (key, space) = if key.len() <= 8 {
+        (key, CARD_LEN - 10)
+    } else {
+        let key = key[..key.len().min(MAX_HIERARCH_KEY_LEN)].to_owned();
+        let space = CARD_LEN - 12 - key.len();
+        (key, space)
+    };
+    let value = truncate_str(value, space);
+    Some(Card::new(&key, CardValue::Str(value), ""))
+}
+
+/// Truncate a string value so that it fits in `space` characters once quoted, quotes
+/// being escaped by doubling them.
+pub(crate) fn truncate_str(value: &str, space: usize) -> String {
+    let mut value = value.to_owned();
+    while value.len() + value.matches('\'').count() > space.saturating_sub(2) {
+        value.pop();
+    }
+    value
+}
+
+/// Format a timestamp as an ISO-8601 UTC date.
+fn iso_date(timestamp: SystemTime) -> String {
+    utc_datetime(timestamp)
+        .format("%Y-%m-%dT%H:%M:%S%.3f")
+        .to_string()
+}
+
+/// Parse an ISO-8601 UTC date, with or without the time.
+fn parse_iso_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let datetime = NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+    Some(datetime.and_utc().into())
+}
+
+/// Keys of an HDU header, with the raw values of the cards (e.g. `'text'`, `12` or `T`).
+///
+/// HIERARCH keys are stored without the prefix.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Header {
+    keys: Vec<(String, String)>,
+}
+
+impl Header {
+    /// Add a key with its raw value.
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.keys.push((key.to_owned(), value.trim().to_owned()));
+    }
+
+    fn raw(&self, key: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get a string value, without the quotes.
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.raw(key).and_then(parse_string)
+    }
+
+    /// Get an integer value. Real values are truncated.
+    pub fn int(&self, key: &str) -> Option<i64> {
+        let raw = self.raw(key)?;
+        raw.parse()
+            .ok()
+            .or_else(|| self.real(key).map(|x| x as i64))
+    }
+
+    /// Get a real value.
+    pub fn real(&self, key: &str) -> Option<f64> {
+        self.raw(key)?.replace('D', "E").parse().ok()
+    }
+
+    /// Get a logical value.
+    #[cfg_attr(not(feature = "fits-native"), allow(dead_code))]
+    pub fn logical(&self, key: &str) -> Option<bool> {
+        match self.raw(key)? {
+            "T" => Some(true),
+            "F" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Get the `(BZERO, BSCALE)` of the image data, `(0, 1)` if the data is not scaled.
+    #[cfg_attr(not(feature = "fits-native"), allow(dead_code))]
+    pub fn scale(&self) -> (f64, f64) {
+        (
+            self.real("BZERO").unwrap_or(0.),
+            self.real("BSCALE").unwrap_or(1.),
+        )
+    }
+
+    /// Restore the image metadata from the header keys written by [`header_cards`].
+    ///
+    /// The standard keys are preferred, and the legacy keys are used as a fallback.
+    /// Returns `None` if neither `EXPTIME` nor `EXPOSURE_US` is present.
+    pub fn metadata(&self) -> Option<ImageMetaData> {
+        let exposure = if let Some(exptime) = self.real("EXPTIME") {
+            Duration::from_micros((exptime * 1e6).round() as u64)
+        } else {
+            Duration::from_micros(self.int("EXPOSURE_US")? as u64)
+        };
+        let int = |keys: &[&str], default: i64| {
+            keys.iter().find_map(|key| self.int(key)).unwrap_or(default)
+        };
+        let (bin_x, bin_y) = (
+            int(&["XBINNING", "BIN_X"], 1),
+            int(&["YBINNING", "BIN_Y"], 1),
+        );
+        let (left, top) = (
+            int(&["XORGSUBF", "ORIGIN_X"], 0),
+            int(&["YORGSUBF", "ORIGIN_Y"], 0),
+        );
+        let (gain, offset) = (int(&["GAIN"], 0), int(&["OFFSET"], 0));
+        let (min_gain, max_gain) = (int(&["GAIN_MIN"], 0), int(&["GAIN_MAX"], 0));
+        let legacy_timestamp = int(&["TIMESTAMP"], 0);
+        let timestamp = self
+            .string("DATE-OBS")
+            .and_then(|date| parse_iso_date(&date))
+            .unwrap_or(UNIX_EPOCH + Duration::from_millis(legacy_timestamp as u64));
+        let temperature = self
+            .real("CCD-TEMP")
+            .or_else(|| self.real("TEMPERATURE"))
+            .unwrap_or(0.) as f32;
+        let camera = self
+            .string("INSTRUME")
+            .or_else(|| self.string("CAMERA"))
+            .unwrap_or_default();
+        let mut meta = ImageMetaData::full_builder(
+            bin_x as u32,
+            bin_y as u32,
+            top as u32,
+            left as u32,
+            temperature,
+            exposure,
+            timestamp,
+            &camera,
+            gain,
+            offset,
+            min_gain as i32,
+            max_gain as i32,
+        );
+        for (key, value) in &self.keys {
+            if is_reserved_key(key) {
+                continue;
+            }
+            if let Some(value) = parse_string(value) {
+                meta.add_extended_attrib(key, &value);
+            }
+        }
+        Some(meta)
+    }
+}
+
+/// Parse a quoted string value, unescaping the quotes.
+fn parse_string(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    if raw.len() >= 2 && raw.starts_with('\'') && raw.ends_with('\'') {
+        Some(raw[1..raw.len() - 1].trim_end().replace("''", "'"))
+    } else {
+        None
+    }
+}
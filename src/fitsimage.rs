@@ -0,0 +1,1192 @@
+#![warn(missing_docs)]
+
+use std::{
+    ffi::{CStr, CString},
+    fs::remove_file,
+    io::{self, Write},
+    marker::PhantomData,
+    os::raw::{c_char, c_int, c_long, c_void},
+    path::{Path, PathBuf},
+    ptr, slice,
+};
+
+use fitsio::{
+    errors::Error as FitsError,
+    hdu::{FitsHdu, HduInfo},
+    images::{ImageDescription, ImageType, WriteImage},
+    FileOpenMode, FitsFile,
+};
+
+use crate::{
+    fitscommon::{extension_name, header_cards, scale_samples, CardValue, Header, SCALE_COMMENT},
+    Channel, DynamicSerialImage, FilenameTemplate, FitsCompression, FitsLayout, FitsOptions,
+    ImageMetaData, PixelArithmetic, SerialImageBuffer,
+};
+
+/// Sample types that can be stored in and read from FITS images.
+pub trait FitsPrimitive: PixelArithmetic + WriteImage {
+    /// FITS image type used to store the samples.
+    const IMAGE_TYPE: ImageType;
+
+    /// Read the image data of an HDU.
+    #[doc(hidden)]
+    fn read_hdu(hdu: &FitsHdu, fptr: &mut FitsFile) -> Result<Vec<Self>, FitsError>;
+}
+
+macro_rules! impl_fits_primitive {
+    ($t:ty, $image_type:expr) => {
+        impl FitsPrimitive for $t {
+            const IMAGE_TYPE: ImageType = $image_type;
+
+            fn read_hdu(hdu: &FitsHdu, fptr: &mut FitsFile) -> Result<Vec<Self>, FitsError> {
+                hdu.read_image(fptr)
+            }
+        }
+    };
+}
+
+impl_fits_primitive!(u8, ImageType::UnsignedByte);
+impl_fits_primitive!(u16, ImageType::UnsignedShort);
+impl_fits_primitive!(f32, ImageType::Float);
+
+fn fits_message(msg: &str) -> FitsError {
+    FitsError::Message(msg.to_owned())
+}
+
+/// Check the status returned by a `cfitsio` function.
+fn check_status(status: c_int, action: &str) -> Result<(), FitsError> {
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(FitsError::Message(format!(
+            "Could not {}: status {}",
+            action, status
+        )))
+    }
+}
+
+/// Create a new FITS file without any HDU.
+fn create_file(path: &Path) -> Result<FitsFile, FitsError> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| fits_message("File path is not valid UTF-8"))?;
+    let path = CString::new(path).map_err(|_| fits_message("File path contains a NUL byte"))?;
+    let mut raw = ptr::null_mut();
+    let mut status: c_int = 0;
+    unsafe { fitsio::sys::ffinit(&mut raw, path.as_ptr(), &mut status) };
+    check_status(status, "create the file")?;
+    unsafe { FitsFile::from_raw(raw, FileOpenMode::READWRITE) }
+}
+
+/// Size of a FITS file, i.e. the end of the data of the last HDU.
+fn file_size(fptr: &mut FitsFile) -> Result<usize, FitsError> {
+    let raw = unsafe { fptr.as_raw() };
+    let mut status: c_int = 0;
+    let (mut nhdus, mut hdutype): (c_int, c_int) = (0, 0);
+    let (mut headstart, mut datastart, mut dataend) = (0, 0, 0);
+    unsafe {
+        fitsio::sys::ffflus(raw, &mut status);
+        fitsio::sys::ffthdu(raw, &mut nhdus, &mut status);
+        fitsio::sys::ffmahd(raw, nhdus, &mut hdutype, &mut status);
+        fitsio::sys::ffghadll(
+            raw,
+            &mut headstart,
+            &mut datastart,
+            &mut dataend,
+            &mut status,
+        );
+    }
+    check_status(status, "get the size of the file")?;
+    Ok(dataend as usize)
+}
+
+extern "C" {
+    fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+/// Length of the prefix of the buffers allocated by [`zeroed_realloc`], holding their size.
+const PREFIX_LEN: usize = 16;
+
+/// Reallocate a buffer of a FITS file created in memory, with the new bytes set to zero.
+///
+/// `cfitsio` expects the memory of new blocks to be zeroed when writing compressed images, which
+/// `realloc` does not guarantee. The size of the buffer is stored in a prefix, before the pointer
+/// returned to `cfitsio`.
+///
+/// The prefix is sound because the buffer never reaches `free` or `realloc` directly: files
+/// created with `ffimem` use the `memkeep://` driver, which only grows and truncates the buffer
+/// through this function and leaves it allocated when the file is closed. [`MemBuffer`] frees
+/// it, from the start of the prefix.
+unsafe extern "C" fn zeroed_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    let (base, old) = if ptr.is_null() {
+        (ptr::null_mut(), 0)
+    } else {
+        let base = (ptr as *mut u8).sub(PREFIX_LEN);
+        (base as *mut c_void, (base as *const usize).read())
+    };
+    let new = match size.checked_add(PREFIX_LEN) {
+        Some(len) => realloc(base, len) as *mut u8,
+        None => return ptr::null_mut(),
+    };
+    if new.is_null() {
+        return ptr::null_mut();
+    }
+    (new as *mut usize).write(size);
+    if size > old {
+        ptr::write_bytes(new.add(PREFIX_LEN + old), 0, size - old);
+    }
+    new.add(PREFIX_LEN) as *mut c_void
+}
+
+/// Memory buffer of a FITS file created in memory, allocated and grown by `cfitsio`
+/// with [`zeroed_realloc`].
+///
+/// The buffer must outlive the [`FitsFile`] writing to it.
+struct MemBuffer {
+    ptr: *mut c_void,
+    size: usize,
+}
+
+impl Default for MemBuffer {
+    fn default() -> Self {
+        Self {
+            ptr: ptr::null_mut(),
+            size: 0,
+        }
+    }
+}
+
+impl MemBuffer {
+    /// Size of the increments of the buffer.
+    const DELTA: usize = 1 << 20;
+
+    /// Create a FITS file in the buffer.
+    fn create(&mut self) -> Result<FitsFile, FitsError> {
+        let mut raw = ptr::null_mut();
+        let mut status: c_int = 0;
+        unsafe {
+            fitsio::sys::ffimem(
+                &mut raw,
+                &mut self.ptr,
+                &mut self.size,
+                Self::DELTA,
+                Some(zeroed_realloc),
+                &mut status,
+            )
+        };
+        check_status(status, "create the file in memory")?;
+        unsafe { FitsFile::from_raw(raw, FileOpenMode::READWRITE) }
+    }
+
+    /// Get the first `len` bytes of the buffer, once the file is closed.
+    fn as_slice(&self, len: usize) -> &[u8] {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, len.min(self.size)) }
+    }
+}
+
+impl Drop for MemBuffer {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            unsafe { free((self.ptr as *mut u8).sub(PREFIX_LEN) as *mut c_void) };
+        }
+    }
+}
+
+/// Borrowed memory buffer of a FITS file opened from memory, read-only.
+///
+/// The buffer must outlive the [`FitsFile`] reading from it.
+struct MemSlice<'a> {
+    ptr: *mut c_void,
+    size: usize,
+    _bytes: PhantomData<&'a [u8]>,
+}
+
+impl<'a> MemSlice<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr() as *mut c_void,
+            size: bytes.len(),
+            _bytes: PhantomData,
+        }
+    }
+
+    /// Open the FITS file in the buffer.
+    fn open(&mut self) -> Result<FitsFile, FitsError> {
+        let name = CString::new("mem://").unwrap();
+        let mut raw = ptr::null_mut();
+        let mut status: c_int = 0;
+        // The file is opened read-only, so `cfitsio` never writes to or reallocates the buffer
+        unsafe {
+            fitsio::sys::ffomem(
+                &mut raw,
+                name.as_ptr(),
+                0,
+                &mut self.ptr,
+                &mut self.size,
+                0,
+                None,
+                &mut status,
+            )
+        };
+        check_status(status, "open the file in memory")?;
+        unsafe { FitsFile::from_raw(raw, FileOpenMode::READONLY) }
+    }
+}
+
+/// Algorithm identifier used by `cfitsio`.
+fn compression_algorithm(compression: FitsCompression) -> c_int {
+    (match compression {
+        FitsCompression::None => 0,
+        FitsCompression::Rice => fitsio::sys::RICE_1,
+        FitsCompression::Gzip1 => fitsio::sys::GZIP_1,
+        FitsCompression::Gzip2 => fitsio::sys::GZIP_2,
+        FitsCompression::Hcompress(_) => fitsio::sys::HCOMPRESS_1,
+        FitsCompression::Plio => fitsio::sys::PLIO_1,
+    }) as c_int
+}
+
+/// Set the compression parameters of the images created in a file.
+fn set_compression(fptr: &mut FitsFile, options: &FitsOptions) -> Result<(), FitsError> {
+    let mut status: c_int = 0;
+    let raw = unsafe { fptr.as_raw() };
+    unsafe {
+        fitsio::sys::fits_set_compression_type(
+            raw,
+            compression_algorithm(options.compression),
+            &mut status,
+        )
+    };
+    if let Some((width, height)) = options.tile_size {
+        if width == 0 || height == 0 {
+            return Err(fits_message("Tile size must be non-zero"));
+        }
+        // Every channel of a cube is a separate tile
+        let mut dims = [width as c_long, height as c_long, 1];
+        unsafe { fitsio::sys::fits_set_tile_dim(raw, 3, dims.as_mut_ptr(), &mut status) };
+    }
+    if let Some(level) = options.quantize_level {
+        if !level.is_finite() {
+            return Err(fits_message("Quantization level must be finite"));
+        }
+        unsafe { fitsio::sys::fits_set_quantize_level(raw, level, &mut status) };
+    }
+    if let FitsCompression::Hcompress(scale) = options.compression {
+        if !scale.is_finite() || scale < 0. {
+            return Err(fits_message(
+                "H-compress scale must be finite and non-negative",
+            ));
+        }
+        unsafe { fitsio::sys::fits_set_hcomp_scale(raw, scale, &mut status) };
+    }
+    check_status(status, "set the compression parameters")
+}
+
+/// Write the samples of an image HDU, optionally scaled to 16-bit integers with `(BZERO, BSCALE)`.
+fn write_plane<T: FitsPrimitive>(
+    fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+    data: &[T],
+    scale: Option<(f64, f64)>,
+) -> Result<(), FitsError> {
+    let (bzero, bscale) = match scale {
+        Some(scale) => scale,
+        None => return hdu.write_image(fptr, data),
+    };
+    hdu.write_image(fptr, &scale_samples(data, (bzero, bscale)))?;
+    // The keys are written after the data, so that cfitsio does not scale the stored integers again
+    hdu.write_key(fptr, "BZERO", (bzero, SCALE_COMMENT))?;
+    hdu.write_key(fptr, "BSCALE", (bscale, SCALE_COMMENT))
+}
+
+impl<T: FitsPrimitive> SerialImageBuffer<T> {
+    /// Save the image data to a FITS file in `dir_prefix`, named `{file_prefix}_{timestamp}.fits`.
+    ///
+    /// This is the implementation of [`SerialImageBuffer::savefits`](SerialImageBuffer<u8>::savefits).
+    pub(crate) fn savefits_generic(
+        &self,
+        dir_prefix: &Path,
+        file_prefix: &str,
+        progname: Option<&str>,
+        compress: bool,
+        overwrite: bool,
+    ) -> Result<PathBuf, FitsError> {
+        let template = if file_prefix.trim().is_empty() {
+            "{camera}".to_owned()
+        } else {
+            FilenameTemplate::escape(file_prefix)
+        };
+        let template =
+            FilenameTemplate::new(&format!("{}_{{timestamp:%Y%m%d_%H%M%S}}.fits", template))
+                .map_err(fits_message)?;
+        let path = dir_prefix.join(template.render(self.get_metadata().as_ref()));
+        let mut options = FitsOptions::new().overwrite(overwrite);
+        if compress {
+            options = options.compression(FitsCompression::Rice);
+        }
+        if let Some(progname) = progname {
+            options = options.progname(progname);
+        }
+        self.savefits_to(&path, &options)?;
+        Ok(path)
+    }
+
+    /// Save the image data to a FITS file at `path`, with the specified [`FitsOptions`].
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description, e.g. if the parent directory
+    ///    does not exist, or if the file exists and [`FitsOptions::overwrite`] is not set.
+    pub fn savefits_to(&self, path: &Path, options: &FitsOptions) -> Result<(), FitsError> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            if !dir.exists() {
+                return Err(FitsError::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Directory {:?} does not exist", dir),
+                )));
+            }
+        }
+        if path.exists() {
+            if !options.overwrite {
+                return Err(FitsError::Io(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("File {:?} already exists", path),
+                )));
+            } else if let Err(msg) = remove_file(path) {
+                return Err(FitsError::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Could not remove file {:?}: {}", path, msg),
+                )));
+            }
+        }
+        let mut fptr = create_file(path)?;
+        self.write_fitsfile(&mut fptr, options)
+    }
+
+    /// Encode the image data as a FITS file in memory, with the specified [`FitsOptions`].
+    ///
+    /// The file has the same headers and data as the one written by
+    /// [`SerialImageBuffer::savefits_to`], except for the `DATE` card.
+    /// [`FitsOptions::overwrite`] is ignored.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn to_fits_bytes(&self, options: &FitsOptions) -> Result<Vec<u8>, FitsError> {
+        let mut buffer = MemBuffer::default();
+        let mut fptr = buffer.create()?;
+        self.write_fitsfile(&mut fptr, options)?;
+        let size = file_size(&mut fptr)?;
+        drop(fptr);
+        Ok(buffer.as_slice(size).to_vec())
+    }
+
+    /// Decode a FITS file in memory.
+    ///
+    /// See [`SerialImageBuffer::open_fits`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description, e.g. if the data is not a FITS
+    ///    file, or does not contain an image of this sample type.
+    pub fn from_fits_bytes(bytes: &[u8]) -> Result<Self, FitsError> {
+        let mut buffer = MemSlice::new(bytes);
+        let mut fptr = buffer.open()?;
+        let hdu = image_hdu(&mut fptr)?;
+        if sample_type_of(&hdu)? != T::IMAGE_TYPE {
+            return Err(fits_message("Image sample type does not match"));
+        }
+        read_image(&mut fptr, &hdu)
+    }
+
+    /// Write the image data to a new FITS file, which must not contain any HDU.
+    fn write_fitsfile(&self, fptr: &mut FitsFile, options: &FitsOptions) -> Result<(), FitsError> {
+        let width = self.width();
+        let height = self.height();
+        let channels = self.channels();
+        let imgsize = [height, width];
+        let cubesize = [channels.len(), height, width];
+        // Unsigned 16-bit samples are always stored with the standard offset of 32768
+        let scale = self
+            .fits_scale(options.scaling, T::IMAGE_TYPE == ImageType::UnsignedShort)
+            .map_err(fits_message)?;
+        // Scaled samples are stored as 16-bit signed integers
+        let data_type = if scale.is_some() {
+            ImageType::Short
+        } else {
+            T::IMAGE_TYPE
+        };
+
+        let img_desc = ImageDescription {
+            data_type,
+            dimensions: &imgsize,
+        };
+        let primary_desc = match options.layout {
+            FitsLayout::Extensions => img_desc.clone(),
+            FitsLayout::Cube => ImageDescription {
+                data_type,
+                dimensions: &cubesize,
+            },
+        };
+
+        let hdu = if options.compression == FitsCompression::None {
+            fptr.create_image("_PRIMARY", &primary_desc)?
+        } else {
+            // Compressed images can not be stored in the primary HDU, and are written to the first extension
+            fptr.create_image(
+                "_PRIMARY",
+                &ImageDescription {
+                    data_type: ImageType::UnsignedByte,
+                    dimensions: &[],
+                },
+            )?;
+            set_compression(fptr, options)?;
+            fptr.create_image("COMPRESSED_IMAGE", &primary_desc)?
+        };
+        match options.layout {
+            FitsLayout::Extensions => {
+                write_plane(
+                    fptr,
+                    &hdu,
+                    self.channel_data(channels[0]).as_ref().unwrap(),
+                    scale,
+                )?;
+                for &channel in &channels[1..] {
+                    let chdu = fptr.create_image(extension_name(channel), &img_desc)?;
+                    write_plane(
+                        fptr,
+                        &chdu,
+                        self.channel_data(channel).as_ref().unwrap(),
+                        scale,
+                    )?;
+                }
+            }
+            FitsLayout::Cube => {
+                let mut data = Vec::with_capacity(width * height * channels.len());
+                for &channel in channels {
+                    data.extend_from_slice(self.channel_data(channel).as_ref().unwrap());
+                }
+                write_plane(fptr, &hdu, &data, scale)?;
+            }
+        }
+        write_header(
+            fptr,
+            &hdu,
+            channels.len(),
+            self.get_metadata().as_ref(),
+            options,
+        )
+    }
+
+    /// Save the image data to a FITS file in `dir`, with the name built from the image metadata
+    /// using a [`FilenameTemplate`], and the specified [`FitsOptions`].
+    ///
+    /// The file name is de-duplicated by appending `_1`, `_2`, ... to the file stem,
+    /// so existing files are never overwritten. The file is created atomically (see
+    /// [`FilenameTemplate::create_unique`]), and the image is encoded in memory before it is
+    /// written to the file. [`FitsOptions::overwrite`] is ignored.
+    ///
+    /// Returns the path of the saved file.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn savefits_template(
+        &self,
+        dir: &Path,
+        template: &FilenameTemplate,
+        options: &FitsOptions,
+    ) -> Result<PathBuf, FitsError> {
+        let (path, mut file) = template.create_unique(dir, self.get_metadata().as_ref())?;
+        let res = self
+            .to_fits_bytes(options)
+            .and_then(|bytes| Ok(file.write_all(&bytes)?));
+        if let Err(err) = res {
+            let _ = remove_file(&path);
+            return Err(err);
+        }
+        Ok(path)
+    }
+
+    /// Open a FITS image.
+    ///
+    /// Both channel layouts ([`FitsLayout::Extensions`] and [`FitsLayout::Cube`]) are accepted.
+    /// The image metadata is restored if the file was written by this crate.
+    ///
+    /// Images of unsigned integers (`BITPIX = 8`, or `BITPIX = 16` with `BZERO = 32768`) are read
+    /// as [`u8`] and [`u16`] samples. All other images, including signed integer images and images
+    /// saved with [`FitsScaling::Linear`](crate::FitsScaling::Linear) or
+    /// [`FitsScaling::Quantize`](crate::FitsScaling::Quantize), are read as [`f32`] samples.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description, e.g. if the file does not contain
+    ///    an image of this sample type, or the image is empty.
+    pub fn open_fits(path: &Path) -> Result<Self, FitsError> {
+        let mut fptr = FitsFile::open(path)?;
+        let hdu = image_hdu(&mut fptr)?;
+        if sample_type_of(&hdu)? != T::IMAGE_TYPE {
+            return Err(fits_message("Image sample type does not match"));
+        }
+        read_image(&mut fptr, &hdu)
+    }
+}
+
+impl DynamicSerialImage {
+    /// Save the image data to a FITS file at `path`, with the specified [`FitsOptions`].
+    ///
+    /// See [`SerialImageBuffer::savefits_to`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn savefits_to(&self, path: &Path, options: &FitsOptions) -> Result<(), FitsError> {
+        match self {
+            DynamicSerialImage::U8(value) => value.savefits_to(path, options),
+            DynamicSerialImage::U16(value) => value.savefits_to(path, options),
+            DynamicSerialImage::F32(value) => value.savefits_to(path, options),
+        }
+    }
+
+    /// Save the image data to a FITS file in `dir`, with the name built from the image metadata
+    /// using a [`FilenameTemplate`], and the specified [`FitsOptions`].
+    ///
+    /// See [`SerialImageBuffer::savefits_template`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn savefits_template(
+        &self,
+        dir: &Path,
+        template: &FilenameTemplate,
+        options: &FitsOptions,
+    ) -> Result<PathBuf, FitsError> {
+        match self {
+            DynamicSerialImage::U8(value) => value.savefits_template(dir, template, options),
+            DynamicSerialImage::U16(value) => value.savefits_template(dir, template, options),
+            DynamicSerialImage::F32(value) => value.savefits_template(dir, template, options),
+        }
+    }
+
+    /// Encode the image data as a FITS file in memory, with the specified [`FitsOptions`].
+    ///
+    /// See [`SerialImageBuffer::to_fits_bytes`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn to_fits_bytes(&self, options: &FitsOptions) -> Result<Vec<u8>, FitsError> {
+        match self {
+            DynamicSerialImage::U8(value) => value.to_fits_bytes(options),
+            DynamicSerialImage::U16(value) => value.to_fits_bytes(options),
+            DynamicSerialImage::F32(value) => value.to_fits_bytes(options),
+        }
+    }
+
+    /// Decode a FITS file in memory. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::open_fits`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn from_fits_bytes(bytes: &[u8]) -> Result<Self, FitsError> {
+        let mut buffer = MemSlice::new(bytes);
+        let mut fptr = buffer.open()?;
+        read_dynamic(&mut fptr)
+    }
+
+    /// Open a FITS image. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::open_fits`] for details.
+    ///
+    /// # Errors
+    ///  * [`fitsio::errors::Error`] with the error description.
+    pub fn open_fits(path: &Path) -> Result<Self, FitsError> {
+        let mut fptr = FitsFile::open(path)?;
+        read_dynamic(&mut fptr)
+    }
+}
+
+/// Get the first HDU containing image data. Compressed images are stored in the first extension.
+fn image_hdu(fptr: &mut FitsFile) -> Result<FitsHdu, FitsError> {
+    let hdu = fptr.primary_hdu()?;
+    match &hdu.info {
+        HduInfo::ImageInfo { shape, .. } if !shape.is_empty() => Ok(hdu),
+        _ => {
+            let hdu = fptr.hdu(1)?;
+            match &hdu.info {
+                HduInfo::ImageInfo { shape, .. } if !shape.is_empty() => Ok(hdu),
+                _ => Err(fits_message("File does not contain an image")),
+            }
+        }
+    }
+}
+
+/// Get the sample type used to represent an image HDU.
+///
+/// Signed integer images (e.g. `BITPIX = 16` with `BZERO = 0`) are represented as [`f32`],
+/// since their samples can be negative.
+fn sample_type_of(hdu: &FitsHdu) -> Result<ImageType, FitsError> {
+    match &hdu.info {
+        HduInfo::ImageInfo { image_type, .. } => match image_type {
+            ImageType::UnsignedByte => Ok(ImageType::UnsignedByte),
+            ImageType::UnsignedShort => Ok(ImageType::UnsignedShort),
+            _ => Ok(ImageType::Float),
+        },
+        _ => Err(fits_message("HDU does not contain an image")),
+    }
+}
+
+/// Read the image of a FITS file, with the sample type determined from the file.
+fn read_dynamic(fptr: &mut FitsFile) -> Result<DynamicSerialImage, FitsError> {
+    let hdu = image_hdu(fptr)?;
+    match sample_type_of(&hdu)? {
+        ImageType::UnsignedByte => Ok(read_image::<u8>(fptr, &hdu)?.into()),
+        ImageType::UnsignedShort => Ok(read_image::<u16>(fptr, &hdu)?.into()),
+        _ => Ok(read_image::<f32>(fptr, &hdu)?.into()),
+    }
+}
+
+fn read_image<T: FitsPrimitive>(
+    fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+) -> Result<SerialImageBuffer<T>, FitsError> {
+    let shape = match &hdu.info {
+        HduInfo::ImageInfo { shape, .. } => shape.clone(),
+        _ => return Err(fits_message("HDU does not contain an image")),
+    };
+    if shape.contains(&0) {
+        return Err(fits_message("Image must not be empty"));
+    }
+    let data = T::read_hdu(hdu, fptr)?;
+    let (height, width, planes) = match shape[..] {
+        [height, width] => {
+            let channels = hdu
+                .read_key::<i64>(fptr, "CHANNELS")
+                .map(|c| c as usize)
+                .unwrap_or(1);
+            let mut planes = vec![data];
+            // The extension names depend on the number of channels
+            let names: &[Channel] = match channels {
+                2 => &[Channel::Alpha],
+                3 => &[Channel::Green, Channel::Blue],
+                4 => &[Channel::Green, Channel::Blue, Channel::Alpha],
+                _ => &[],
+            };
+            for &channel in names {
+                let chdu = fptr.hdu(extension_name(channel))?;
+                planes.push(T::read_hdu(&chdu, fptr)?);
+            }
+            (height, width, planes)
+        }
+        [channels, height, width] => {
+            let planes = data
+                .chunks_exact(width * height)
+                .take(channels)
+                .map(|p| p.to_vec())
+                .collect();
+            (height, width, planes)
+        }
+        _ => return Err(fits_message("Unsupported number of image dimensions")),
+    };
+    let meta = read_metadata(fptr, hdu)?;
+    SerialImageBuffer::from_planes(width, height, planes, meta).map_err(fits_message)
+}
+
+/// Write the image metadata to the header of an HDU.
+fn write_header(
+    fptr: &mut FitsFile,
+    hdu: &FitsHdu,
+    channels: usize,
+    meta: Option<&ImageMetaData>,
+    options: &FitsOptions,
+) -> Result<(), FitsError> {
+    for card in header_cards(
+        channels,
+        meta,
+        options.progname.as_deref(),
+        options.legacy_keys,
+    ) {
+        let key = if card.key.len() > 8 {
+            format!("HIERARCH {}", card.key)
+        } else {
+            card.key
+        };
+        match card.value {
+            CardValue::Int(value) => hdu.write_key(fptr, &key, (value, card.comment))?,
+            CardValue::Float(value) => hdu.write_key(fptr, &key, (value, card.comment))?,
+            CardValue::Double(value) => hdu.write_key(fptr, &key, (value, card.comment))?,
+            CardValue::Str(value) => hdu.write_key(fptr, &key, (value.as_str(), card.comment))?,
+        }
+    }
+    Ok(())
+}
+
+/// Restore the image metadata from the header keys written by [`SerialImageBuffer::savefits`].
+fn read_metadata(fptr: &mut FitsFile, hdu: &FitsHdu) -> Result<Option<ImageMetaData>, FitsError> {
+    Ok(read_header(fptr, hdu)?.metadata())
+}
+
+/// Read all keys of the header of an HDU, with their raw values.
+fn read_header(fptr: &mut FitsFile, hdu: &FitsHdu) -> Result<Header, FitsError> {
+    let mut status: c_int = 0;
+    let (mut nexist, mut nmore): (c_int, c_int) = (0, 0);
+    let raw = unsafe { fptr.as_raw() };
+    unsafe {
+        fitsio::sys::ffmahd(raw, hdu.number as c_int + 1, ptr::null_mut(), &mut status);
+        fitsio::sys::ffghsp(raw, &mut nexist, &mut nmore, &mut status);
+    };
+    check_status(status, "read the header")?;
+    let mut header = Header::default();
+    for idx in 1..=nexist {
+        let mut name = [0 as c_char; 80];
+        let mut value = [0 as c_char; 80];
+        let mut comment = [0 as c_char; 80];
+        unsafe {
+            fitsio::sys::ffgkyn(
+                raw,
+                idx,
+                name.as_mut_ptr(),
+                value.as_mut_ptr(),
+                comment.as_mut_ptr(),
+                &mut status,
+            )
+        };
+        check_status(status, &format!("read header key {}", idx))?;
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) }.to_string_lossy();
+        let value = unsafe { CStr::from_ptr(value.as_ptr()) }.to_string_lossy();
+        header.push(&name, &value);
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FitsScaling, SampleType, ScalePolicy};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Create an empty directory for the files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "serialimage_fits_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_image() -> SerialImageBuffer<u16> {
+        let data: Vec<u16> = (0..24).map(|x| x * 1000).collect();
+        let mut img = SerialImageBuffer::from_vec(3, 2, data).unwrap();
+        let mut meta = ImageMetaData::full_builder(
+            2,
+            2,
+            10,
+            20,
+            -10.5,
+            Duration::from_micros(1_500_250),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            "test",
+            100,
+            10,
+            0,
+            500,
+        );
+        meta.add_extended_attrib("FILTER", "Ha");
+        meta.add_extended_attrib("OBSERVER_NAME", "O'Brien");
+        img.set_metadata(Some(meta));
+        img
+    }
+
+    #[test]
+    fn test_fits_layouts() {
+        let dir = test_dir("layouts");
+        let img = test_image();
+        for (prefix, layout, compress) in [
+            (
+                "serialimage_ext",
+                FitsLayout::Extensions,
+                FitsCompression::None,
+            ),
+            ("serialimage_cube", FitsLayout::Cube, FitsCompression::None),
+            (
+                "serialimage_ext_rice",
+                FitsLayout::Extensions,
+                FitsCompression::Rice,
+            ),
+            (
+                "serialimage_cube_rice",
+                FitsLayout::Cube,
+                FitsCompression::Rice,
+            ),
+        ] {
+            let options = FitsOptions::new()
+                .layout(layout)
+                .compression(compress)
+                .overwrite(true);
+            let path = dir.join(format!("{}.fits", prefix));
+            img.savefits_to(&path, &options).unwrap();
+            let back = SerialImageBuffer::<u16>::open_fits(&path).unwrap();
+            assert_eq!(back, img);
+            let back = DynamicSerialImage::open_fits(&path).unwrap();
+            assert_eq!(back, img.clone().into());
+            assert!(SerialImageBuffer::<u8>::open_fits(&path).is_err());
+            remove_file(path).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_keys() {
+        let dir = test_dir("keys");
+        let img = test_image();
+        for legacy in [false, true] {
+            let options = FitsOptions::new().legacy_keys(legacy);
+            let template =
+                FilenameTemplate::new("serialimage_{camera}_{exposure_ms}ms.fits").unwrap();
+            let path = img.savefits_template(&dir, &template, &options).unwrap();
+            assert!(path.ends_with("serialimage_test_1500ms.fits"));
+            let mut fptr = FitsFile::open(&path).unwrap();
+            let hdu = fptr.primary_hdu().unwrap();
+            let date: String = hdu.read_key(&mut fptr, "DATE-OBS").unwrap();
+            assert_eq!(date, "2023-11-14T22:13:20.123");
+            let exptime: f64 = hdu.read_key(&mut fptr, "EXPTIME").unwrap();
+            assert_eq!(exptime, 1.50025);
+            let temp: f32 = hdu.read_key(&mut fptr, "CCD-TEMP").unwrap();
+            assert_eq!(temp, -10.5);
+            let observer: String = hdu.read_key(&mut fptr, "OBSERVER_NAME").unwrap();
+            assert_eq!(observer, "O'Brien");
+            assert_eq!(
+                hdu.read_key::<i64>(&mut fptr, "EXPOSURE_US").is_ok(),
+                legacy
+            );
+            drop(fptr);
+
+            let back = SerialImageBuffer::<u16>::open_fits(&path).unwrap();
+            assert_eq!(back, img);
+            remove_file(path).unwrap();
+        }
+        let template = FilenameTemplate::new("image.fits").unwrap();
+        let options = FitsOptions::new().overwrite(true);
+        let first = img.savefits_template(&dir, &template, &options).unwrap();
+        let second = img.savefits_template(&dir, &template, &options).unwrap();
+        assert!(second.ends_with("image_1.fits"));
+        assert_eq!(SerialImageBuffer::<u16>::open_fits(&first).unwrap(), img);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_reserved_keys() {
+        let dir = test_dir("reserved");
+        let mut img = test_image();
+        let mut reserved = img.get_metadata().unwrap();
+        for key in ["END", "BZERO", "naxis1", "ZTILE2", "TTYPE1"] {
+            reserved.add_extended_attrib(key, "1000");
+        }
+        img.set_metadata(Some(reserved));
+        for compression in [FitsCompression::None, FitsCompression::Rice] {
+            let path = dir.join("reserved.fits");
+            let options = FitsOptions::new()
+                .compression(compression)
+                .overwrite(true);
+            img.savefits_to(&path, &options).unwrap();
+            let back = SerialImageBuffer::<u16>::open_fits(&path).unwrap();
+            assert_eq!(back, test_image());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_long_strings() {
+        let dir = test_dir("long_strings");
+        let mut img = test_image();
+        let mut meta = img.get_metadata().unwrap();
+        meta.camera_name = format!("Camera '{}'", "x".repeat(90));
+        img.set_metadata(Some(meta));
+        let path = dir.join("long_strings.fits");
+        let options = FitsOptions::new().progname(&"p".repeat(100));
+        img.savefits_to(&path, &options).unwrap();
+        let back = SerialImageBuffer::<u16>::open_fits(&path).unwrap();
+        let camera = back.get_metadata().unwrap().camera_name;
+        assert_eq!(camera, format!("Camera '{}", "x".repeat(59)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_scaling() {
+        let dir = test_dir("scaling");
+        let img = test_image();
+        for (scaling, compress) in [
+            (FitsScaling::Native, FitsCompression::None),
+            (FitsScaling::Native, FitsCompression::Rice),
+            (
+                FitsScaling::Linear {
+                    bzero: 32768.,
+                    bscale: 1.,
+                },
+                FitsCompression::None,
+            ),
+            (
+                FitsScaling::Linear {
+                    bzero: 32768.,
+                    bscale: 1.,
+                },
+                FitsCompression::Gzip2,
+            ),
+        ] {
+            let options = FitsOptions::new()
+                .scaling(scaling)
+                .compression(compress)
+                .overwrite(true);
+            let path = dir.join("serialimage_scaled_u16.fits");
+            img.savefits_to(&path, &options).unwrap();
+            let back = SerialImageBuffer::<u16>::open_fits(&path).unwrap();
+            assert_eq!(back, img);
+            remove_file(path).unwrap();
+        }
+
+        let data: Vec<f32> = (0..24).map(|x| (x as f32 * 0.37).sin() * 1e3).collect();
+        let mut img = SerialImageBuffer::from_vec(3, 2, data).unwrap();
+        img.set_metadata(test_image().get_metadata());
+        let tolerance = 2e3 / 131070. + 1e-3;
+        for (scaling, layout, compress, tolerance) in [
+            (
+                FitsScaling::Native,
+                FitsLayout::Extensions,
+                FitsCompression::None,
+                0.,
+            ),
+            (
+                FitsScaling::Quantize,
+                FitsLayout::Extensions,
+                FitsCompression::None,
+                tolerance,
+            ),
+            (
+                FitsScaling::Quantize,
+                FitsLayout::Cube,
+                FitsCompression::Rice,
+                tolerance,
+            ),
+            (
+                FitsScaling::Linear {
+                    bzero: 0.,
+                    bscale: 0.1,
+                },
+                FitsLayout::Cube,
+                FitsCompression::None,
+                0.05 + 1e-3,
+            ),
+        ] {
+            let options = FitsOptions::new()
+                .scaling(scaling)
+                .layout(layout)
+                .compression(compress)
+                .overwrite(true);
+            let path = dir.join("serialimage_scaled_f32.fits");
+            img.savefits_to(&path, &options).unwrap();
+            let back = DynamicSerialImage::open_fits(&path).unwrap();
+            let back = back.as_f32().unwrap();
+            assert_eq!(back.get_metadata(), img.get_metadata());
+            for &channel in img.channels() {
+                let orig = img.channel_data(channel).as_ref().unwrap();
+                let read = back.channel_data(channel).as_ref().unwrap();
+                for (a, b) in orig.iter().zip(read.iter()) {
+                    assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+                }
+            }
+            remove_file(path).unwrap();
+        }
+        // Scaled images are read as f32, whatever the saved sample type
+        let img8 = SerialImageBuffer::from_vec(3, 2, (0..6u8).map(|x| x * 50).collect()).unwrap();
+        let path = dir.join("serialimage_scaled_u8.fits");
+        let options = FitsOptions::new().scaling(FitsScaling::Linear {
+            bzero: 0.,
+            bscale: 1.,
+        });
+        img8.savefits_to(&path, &options).unwrap();
+        assert!(SerialImageBuffer::<u8>::open_fits(&path).is_err());
+        let back = DynamicSerialImage::open_fits(&path).unwrap();
+        assert_eq!(back.sample_type(), SampleType::F32);
+        let back = back.convert_to(SampleType::U8, ScalePolicy::Raw).unwrap();
+        assert_eq!(back, img8.into());
+
+        let options = FitsOptions::new().scaling(FitsScaling::Linear {
+            bzero: 0.,
+            bscale: 0.,
+        });
+        assert!(img
+            .savefits_to(&dir.join("serialimage_scaled_err.fits"), &options)
+            .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_compression() {
+        let dir = test_dir("compression");
+        let path = dir.join("serialimage_compression.fits");
+        let data: Vec<u16> = (0..16 * 16 * 3).map(|x| (x * 37 % 4096) as u16).collect();
+        let img = SerialImageBuffer::from_vec(16, 16, data).unwrap();
+        let unsigned = FitsScaling::Linear {
+            bzero: 0.,
+            bscale: 1.,
+        };
+        for (compression, scaling) in [
+            (FitsCompression::Rice, FitsScaling::Native),
+            (FitsCompression::Gzip1, FitsScaling::Native),
+            (FitsCompression::Gzip2, FitsScaling::Native),
+            (FitsCompression::Hcompress(0.), FitsScaling::Native),
+            (FitsCompression::Plio, unsigned),
+        ] {
+            for layout in [FitsLayout::Extensions, FitsLayout::Cube] {
+                let options = FitsOptions::new()
+                    .compression(compression)
+                    .scaling(scaling)
+                    .layout(layout)
+                    .tile_size(8, 8)
+                    .overwrite(true);
+                img.savefits_to(&path, &options).unwrap();
+                let mut fptr = FitsFile::open(&path).unwrap();
+                let hdu = fptr.hdu(1).unwrap();
+                let ztile: i64 = hdu.read_key(&mut fptr, "ZTILE2").unwrap();
+                assert_eq!(ztile, 8);
+                drop(fptr);
+                // Signed integer images are read as f32
+                let back = DynamicSerialImage::open_fits(&path)
+                    .unwrap()
+                    .convert_to(SampleType::U16, ScalePolicy::Raw)
+                    .unwrap();
+                assert_eq!(back, img.clone().into(), "{:?}", compression);
+            }
+        }
+
+        let data: Vec<f32> = (0..16 * 16).map(|x| (x as f32 * 0.37).sin()).collect();
+        let img = SerialImageBuffer::from_vec(16, 16, data).unwrap();
+        for (compression, level, tolerance) in [
+            (FitsCompression::Gzip2, 0., 0.),
+            (FitsCompression::Rice, -1e-3, 1e-3),
+        ] {
+            let options = FitsOptions::new()
+                .compression(compression)
+                .quantize_level(level)
+                .overwrite(true);
+            img.savefits_to(&path, &options).unwrap();
+            let back = SerialImageBuffer::<f32>::open_fits(&path).unwrap();
+            let orig = img.get_luma().unwrap();
+            for (a, b) in orig.iter().zip(back.get_luma().unwrap().iter()) {
+                assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+            }
+        }
+        let options = FitsOptions::new()
+            .compression(FitsCompression::Rice)
+            .tile_size(0, 1)
+            .overwrite(true);
+        assert!(img.savefits_to(&path, &options).is_err());
+        remove_file(path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fits_bytes() {
+        let dir = test_dir("bytes");
+        let img: DynamicSerialImage = test_image().into();
+        for compression in [FitsCompression::None, FitsCompression::Rice] {
+            let options = FitsOptions::new()
+                .compression(compression)
+                .legacy_keys(true);
+            let bytes = img.to_fits_bytes(&options).unwrap();
+            assert_eq!(bytes.len() % 2880, 0);
+            assert!(bytes.starts_with(b"SIMPLE  ="));
+            assert_eq!(DynamicSerialImage::from_fits_bytes(&bytes).unwrap(), img);
+            assert!(SerialImageBuffer::<u8>::from_fits_bytes(&bytes).is_err());
+
+            let path = dir.join("serialimage_bytes.fits");
+            img.savefits_to(&path, &options.clone().overwrite(true))
+                .unwrap();
+            let file = std::fs::read(&path).unwrap();
+            assert_eq!(file.len(), bytes.len());
+            let back = SerialImageBuffer::<u16>::from_fits_bytes(&file).unwrap();
+            assert_eq!(DynamicSerialImage::from(back), img);
+            remove_file(path).unwrap();
+        }
+        assert!(DynamicSerialImage::from_fits_bytes(b"not a FITS file").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zeroed_realloc() {
+        unsafe {
+            let ptr = zeroed_realloc(ptr::null_mut(), 16) as *mut u8;
+            assert!(!ptr.is_null());
+            assert_eq!(slice::from_raw_parts(ptr, 16), &[0; 16]);
+            ptr::write_bytes(ptr, 0xff, 16);
+            // The old bytes are kept, and the new ones zeroed
+            let ptr = zeroed_realloc(ptr as *mut c_void, 1 << 20) as *mut u8;
+            assert!(!ptr.is_null());
+            let buffer = slice::from_raw_parts(ptr, 1 << 20);
+            assert_eq!(&buffer[..16], &[0xff; 16]);
+            assert!(buffer[16..].iter().all(|&b| b == 0));
+            // Shrinking and growing again zeroes the bytes past the smaller size
+            let ptr = zeroed_realloc(ptr as *mut c_void, 8) as *mut u8;
+            let ptr = zeroed_realloc(ptr as *mut c_void, 32) as *mut u8;
+            let buffer = slice::from_raw_parts(ptr, 32);
+            assert_eq!(&buffer[..8], &[0xff; 8]);
+            assert_eq!(&buffer[8..], &[0; 24]);
+            free(ptr.sub(PREFIX_LEN) as *mut c_void);
+        }
+    }
+
+    /// Build a FITS file from header cards and big-endian data.
+    fn raw_fits(cards: &[&str], data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for card in cards.iter().chain(&["END"]) {
+            bytes.extend(format!("{:<80}", card).bytes());
+        }
+        bytes.resize((bytes.len() + 2879) / 2880 * 2880, b' ');
+        bytes.extend_from_slice(data);
+        bytes.resize((bytes.len() + 2879) / 2880 * 2880, 0);
+        bytes
+    }
+
+    #[test]
+    fn test_fits_signed() {
+        let short = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    1",
+            ],
+            &[0xff, 0xfb, 0x00, 0x07],
+        );
+        let img = DynamicSerialImage::from_fits_bytes(&short).unwrap();
+        assert_eq!(img.as_f32().unwrap().get_luma().unwrap(), &vec![-5., 7.]);
+        assert!(SerialImageBuffer::<u16>::from_fits_bytes(&short).is_err());
+
+        let byte = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                    8",
+                "NAXIS   =                    2",
+                "NAXIS1  =                    2",
+                "NAXIS2  =                    1",
+                "BZERO   =                 -128",
+            ],
+            &[0, 255],
+        );
+        let img = DynamicSerialImage::from_fits_bytes(&byte).unwrap();
+        assert_eq!(img.as_f32().unwrap().get_luma().unwrap(), &vec![-128., 127.]);
+
+        let empty = raw_fits(
+            &[
+                "SIMPLE  =                    T",
+                "BITPIX  =                   16",
+                "NAXIS   =                    3",
+                "NAXIS1  =                    0",
+                "NAXIS2  =                    2",
+                "NAXIS3  =                    2",
+            ],
+            &[],
+        );
+        assert!(DynamicSerialImage::from_fits_bytes(&empty).is_err());
+    }
+}
@@ -0,0 +1,711 @@
+#![warn(missing_docs)]
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    fitscommon::{
+        extension_name, header_cards, scale_samples, truncate_str, CardValue, Header, CARD_LEN,
+        SCALE_COMMENT,
+    },
+    Channel, DynamicSerialImage, FitsCompression, FitsLayout, FitsOptions, PixelArithmetic,
+    SerialImageBuffer,
+};
+
+/// Size of a FITS block. Headers and data are padded to a multiple of the block size.
+const BLOCK_LEN: usize = 2880;
+
+/// Sample types that can be stored in and read from FITS images without `cfitsio`.
+pub trait NativeFitsPrimitive: PixelArithmetic {
+    /// `BITPIX` of the FITS images holding the samples.
+    const BITPIX: i64;
+
+    /// Append the samples to the data unit of an HDU, in big-endian order.
+    #[doc(hidden)]
+    fn write_be(data: &[Self], out: &mut Vec<u8>);
+}
+
+impl NativeFitsPrimitive for u8 {
+    const BITPIX: i64 = 8;
+
+    fn write_be(data: &[Self], out: &mut Vec<u8>) {
+        out.extend_from_slice(data);
+    }
+}
+
+impl NativeFitsPrimitive for u16 {
+    const BITPIX: i64 = 16;
+
+    fn write_be(data: &[Self], out: &mut Vec<u8>) {
+        // Stored as signed integers with BZERO = 32768
+        for x in data {
+            out.extend_from_slice(&(x ^ 0x8000).to_be_bytes());
+        }
+    }
+}
+
+impl NativeFitsPrimitive for f32 {
+    const BITPIX: i64 = -32;
+
+    fn write_be(data: &[Self], out: &mut Vec<u8>) {
+        for x in data {
+            out.extend_from_slice(&x.to_be_bytes());
+        }
+    }
+}
+
+/// Length padded to a multiple of the block size.
+fn padded(len: usize) -> usize {
+    (len + BLOCK_LEN - 1) / BLOCK_LEN * BLOCK_LEN
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<T: NativeFitsPrimitive> SerialImageBuffer<T> {
+    /// Write the image data as a FITS file, with the specified [`FitsOptions`], without `cfitsio`.
+    ///
+    /// The file has the same layout, scaling and header keys as the files written by
+    /// `savefits_to()` with the `fitsio` feature.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::Unsupported`] if compression is requested.
+    ///  * [`io::ErrorKind::InvalidInput`] if the scaling is invalid.
+    ///  * Any error of the writer.
+    #[cfg_attr(docsrs, doc(cfg(feature = "fits-native")))]
+    pub fn write_fits<W: Write>(&self, mut writer: W, options: &FitsOptions) -> io::Result<()> {
+        if options.compression != FitsCompression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Compressed FITS images are not supported without cfitsio",
+            ));
+        }
+        let width = self.width();
+        let height = self.height();
+        let channels = self.channels();
+        let scale = self
+            .fits_scale(options.scaling, T::BITPIX == 16)
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, msg))?;
+        // Scaled samples are stored as 16-bit signed integers
+        let bitpix = if scale.is_some() { 16 } else { T::BITPIX };
+        let plane = |channel: Channel, out: &mut Vec<u8>| {
+            let data = self.channel_data(channel).as_ref().unwrap();
+            match scale {
+                Some(scale) => {
+                    for x in scale_samples(data, scale) {
+                        out.extend_from_slice(&x.to_be_bytes());
+                    }
+                }
+                None => T::write_be(data, out),
+            }
+        };
+
+        let mut cards = HeaderWriter::default();
+        cards.push("SIMPLE", "T", "file does conform to FITS standard");
+        cards.push_int("BITPIX", bitpix, "number of bits per data pixel");
+        let mut data = Vec::new();
+        match options.layout {
+            FitsLayout::Extensions => {
+                cards.push_int("NAXIS", 2, "number of data axes");
+                cards.push_int("NAXIS1", width as i64, "length of data axis 1");
+                cards.push_int("NAXIS2", height as i64, "length of data axis 2");
+                plane(channels[0], &mut data);
+            }
+            FitsLayout::Cube => {
+                cards.push_int("NAXIS", 3, "number of data axes");
+                cards.push_int("NAXIS1", width as i64, "length of data axis 1");
+                cards.push_int("NAXIS2", height as i64, "length of data axis 2");
+                cards.push_int("NAXIS3", channels.len() as i64, "length of data axis 3");
+                for &channel in channels {
+                    plane(channel, &mut data);
+                }
+            }
+        }
+        cards.push("EXTEND", "T", "FITS dataset may contain extensions");
+        cards.push_scale(scale);
+        for card in header_cards(
+            channels.len(),
+            self.get_metadata().as_ref(),
+            options.progname.as_deref(),
+            options.legacy_keys,
+        ) {
+            cards.push_value(&card.key, &card.value, card.comment);
+        }
+        write_hdu(&mut writer, cards, data)?;
+
+        if options.layout == FitsLayout::Extensions {
+            for &channel in &channels[1..] {
+                let mut cards = HeaderWriter::default();
+                cards.push("XTENSION", "'IMAGE   '", "IMAGE extension");
+                cards.push_int("BITPIX", bitpix, "number of bits per data pixel");
+                cards.push_int("NAXIS", 2, "number of data axes");
+                cards.push_int("NAXIS1", width as i64, "length of data axis 1");
+                cards.push_int("NAXIS2", height as i64, "length of data axis 2");
+                cards.push_int("PCOUNT", 0, "required keyword; must = 0");
+                cards.push_int("GCOUNT", 1, "required keyword; must = 1");
+                cards.push_value(
+                    "EXTNAME",
+                    &CardValue::Str(extension_name(channel).to_owned()),
+                    "",
+                );
+                cards.push_scale(scale);
+                let mut data = Vec::new();
+                plane(channel, &mut data);
+                write_hdu(&mut writer, cards, data)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Read a FITS image without `cfitsio`.
+    ///
+    /// Both channel layouts ([`FitsLayout::Extensions`] and [`FitsLayout::Cube`]) are accepted,
+    /// with `BITPIX` 8, 16, 32, -32 or -64. `BZERO` and `BSCALE` are applied to the stored values.
+    /// The image metadata is restored if the file was written by this crate.
+    ///
+    /// Images of unsigned integers (`BITPIX = 8`, or `BITPIX = 16` with `BZERO = 32768`) are read
+    /// as [`u8`] and [`u16`] samples. All other images, including signed integer images, are read
+    /// as [`f32`] samples.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the data is not a FITS file, or does not contain
+    ///    an image of this sample type, or the image is empty.
+    ///  * [`io::ErrorKind::Unsupported`] if the image is compressed.
+    ///  * Any error of the reader.
+    #[cfg_attr(docsrs, doc(cfg(feature = "fits-native")))]
+    pub fn read_fits<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let hdus = parse_hdus(&bytes)?;
+        let idx = image_hdu(&hdus)?;
+        if sample_type_of(&hdus[idx]) != T::BITPIX {
+            return Err(invalid_data("Image sample type does not match"));
+        }
+        read_image(&hdus, idx)
+    }
+}
+
+impl DynamicSerialImage {
+    /// Write the image data as a FITS file, with the specified [`FitsOptions`], without `cfitsio`.
+    ///
+    /// See [`SerialImageBuffer::write_fits`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "fits-native")))]
+    pub fn write_fits<W: Write>(&self, writer: W, options: &FitsOptions) -> io::Result<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_fits(writer, options),
+            DynamicSerialImage::U16(value) => value.write_fits(writer, options),
+            DynamicSerialImage::F32(value) => value.write_fits(writer, options),
+        }
+    }
+
+    /// Read a FITS image without `cfitsio`. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_fits`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "fits-native")))]
+    pub fn read_fits<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let hdus = parse_hdus(&bytes)?;
+        let idx = image_hdu(&hdus)?;
+        match sample_type_of(&hdus[idx]) {
+            8 => Ok(read_image::<u8>(&hdus, idx)?.into()),
+            16 => Ok(read_image::<u16>(&hdus, idx)?.into()),
+            _ => Ok(read_image::<f32>(&hdus, idx)?.into()),
+        }
+    }
+}
+
+/// Header cards of an HDU being written.
+#[derive(Default)]
+struct HeaderWriter {
+    bytes: Vec<u8>,
+}
+
+impl HeaderWriter {
+    /// Add a card with a formatted value.
+    ///
+    /// Keys longer than eight characters are written using the HIERARCH convention. Strings are
+    /// left-justified, and other values right-justified in column 30 (fixed format).
+    fn push(&mut self, key: &str, value: &str, comment: &str) {
+        let mut card = if key.len() > 8 {
+            format!("HIERARCH {} = {}", key, value)
+        } else if value.starts_with('\'') {
+            format!("{:<8}= {}", key, value)
+        } else {
+            format!("{:<8}= {:>20}", key, value)
+        };
+        if !comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(comment);
+        }
+        // Header cards are restricted to printable ASCII characters
+        let card: Vec<u8> = card
+            .chars()
+            .map(|c| {
+                if (' '..='~').contains(&c) {
+                    c as u8
+                } else {
+                    b'?'
+                }
+            })
+            .chain(std::iter::repeat(b' '))
+            .take(CARD_LEN)
+            .collect();
+        self.bytes.extend_from_slice(&card);
+    }
+
+    fn push_int(&mut self, key: &str, value: i64, comment: &str) {
+        self.push(key, &value.to_string(), comment);
+    }
+
+    fn push_value(&mut self, key: &str, value: &CardValue, comment: &str) {
+        let value = match value {
+            // Long strings are truncated before quoting them, to keep the closing quote
+            CardValue::Str(value) => {
+                let space = CARD_LEN - if key.len() > 8 { 12 + key.len() } else { 10 };
+                CardValue::Str(truncate_str(value, space)).to_string()
+            }
+            value => value.to_string(),
+        };
+        self.push(key, &value, comment);
+    }
+
+    fn push_scale(&mut self, scale: Option<(f64, f64)>) {
+        if let Some((bzero, bscale)) = scale {
+            self.push_value("BZERO", &CardValue::Double(bzero), SCALE_COMMENT);
+            self.push_value("BSCALE", &CardValue::Double(bscale), SCALE_COMMENT);
+        }
+    }
+}
+
+/// Write an HDU, padding the header and the data to a multiple of the block size.
+fn write_hdu<W: Write>(writer: &mut W, header: HeaderWriter, data: Vec<u8>) -> io::Result<()> {
+    let mut header = header.bytes;
+    header.extend_from_slice(format!("{:<80}", "END").as_bytes());
+    header.resize(padded(header.len()), b' ');
+    writer.write_all(&header)?;
+    let padding = padded(data.len()) - data.len();
+    writer.write_all(&data)?;
+    writer.write_all(&vec![0; padding])
+}
+
+/// HDU of a FITS file being read.
+struct Hdu<'a> {
+    header: Header,
+    bitpix: i64,
+    shape: Vec<usize>,
+    data: &'a [u8],
+}
+
+/// Parse a header card into its key and raw value. Returns `None` for cards without a value.
+fn parse_card(card: &str) -> Option<(&str, &str)> {
+    let (key, value) = if let Some(rest) = card.strip_prefix("HIERARCH ") {
+        let (key, value) = rest.split_once('=')?;
+        (key.trim(), value)
+    } else if card.get(8..10) == Some("= ") {
+        (card[..8].trim_end(), &card[10..])
+    } else {
+        return None;
+    };
+    let value = value.trim_start();
+    if let Some(string) = value.strip_prefix('\'') {
+        // The string ends at the first quote that is not doubled
+        let mut chars = string.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            if c == '\'' {
+                if chars.peek().map(|(_, c)| *c) == Some('\'') {
+                    chars.next();
+                } else {
+                    return Some((key, &value[..idx + 2]));
+                }
+            }
+        }
+        None
+    } else {
+        let end = value.find('/').unwrap_or(value.len());
+        Some((key, value[..end].trim_end()))
+    }
+}
+
+/// Split a FITS file into its HDUs.
+fn parse_hdus(bytes: &[u8]) -> io::Result<Vec<Hdu<'_>>> {
+    let mut hdus = Vec::new();
+    let mut pos = 0;
+    // Trailing bytes shorter than a block are ignored
+    while pos + BLOCK_LEN <= bytes.len() {
+        let mut header = Header::default();
+        let mut end = false;
+        while !end {
+            let card = bytes
+                .get(pos..pos + CARD_LEN)
+                .ok_or_else(|| invalid_data("Header is not terminated by END"))?;
+            pos += CARD_LEN;
+            let card = String::from_utf8_lossy(card);
+            if card.trim_end() == "END" {
+                end = true;
+            } else if let Some((key, value)) = parse_card(&card) {
+                header.push(key, value);
+            }
+        }
+        pos = padded(pos);
+        let valid = if hdus.is_empty() {
+            header.logical("SIMPLE").is_some()
+        } else {
+            header.string("XTENSION").is_some()
+        };
+        if !valid {
+            return Err(invalid_data("Data is not a FITS file"));
+        }
+        let bitpix = header
+            .int("BITPIX")
+            .ok_or_else(|| invalid_data("Missing BITPIX"))?;
+        let naxis = header
+            .int("NAXIS")
+            .ok_or_else(|| invalid_data("Missing NAXIS"))?;
+        let shape = (1..=naxis)
+            .map(|axis| {
+                header
+                    .int(&format!("NAXIS{}", axis))
+                    .and_then(|n| usize::try_from(n).ok())
+                    .ok_or_else(|| invalid_data("Missing or invalid NAXISn"))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let size = if shape.is_empty() {
+            0
+        } else {
+            data_size(
+                bitpix,
+                &shape,
+                header.int("PCOUNT").unwrap_or(0),
+                header.int("GCOUNT").unwrap_or(1),
+            )
+            .ok_or_else(|| invalid_data("Invalid size of the data unit"))?
+        };
+        let data = pos
+            .checked_add(size)
+            .and_then(|end| bytes.get(pos..end))
+            .ok_or_else(|| invalid_data("Data unit is truncated"))?;
+        pos += padded(size);
+        hdus.push(Hdu {
+            header,
+            bitpix,
+            shape,
+            data,
+        });
+    }
+    if hdus.is_empty() {
+        return Err(invalid_data("Data is not a FITS file"));
+    }
+    Ok(hdus)
+}
+
+/// Get the size in bytes of the data unit of an HDU, or `None` if the size is invalid or overflows.
+fn data_size(bitpix: i64, shape: &[usize], pcount: i64, gcount: i64) -> Option<usize> {
+    let pcount = usize::try_from(pcount).ok()?;
+    let gcount = usize::try_from(gcount).ok()?;
+    let samples = shape.iter().try_fold(1usize, |acc, &n| acc.checked_mul(n))?;
+    usize::try_from(bitpix.unsigned_abs() / 8)
+        .ok()?
+        .checked_mul(gcount)?
+        .checked_mul(pcount.checked_add(samples)?)
+}
+
+/// Get the index of the first HDU containing image data.
+fn image_hdu(hdus: &[Hdu]) -> io::Result<usize> {
+    let idx = hdus
+        .iter()
+        .position(|hdu| !hdu.shape.is_empty())
+        .ok_or_else(|| invalid_data("File does not contain an image"))?;
+    let header = &hdus[idx].header;
+    if header.logical("ZIMAGE") == Some(true) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Compressed FITS images are not supported without cfitsio",
+        ));
+    }
+    if idx > 0 && header.string("XTENSION").as_deref() != Some("IMAGE") {
+        return Err(invalid_data("File does not contain an image"));
+    }
+    Ok(idx)
+}
+
+/// Get the `BITPIX` of the sample type used to represent an image HDU.
+///
+/// Unsigned 8-bit integers and unsigned 16-bit integers stored with the standard offset
+/// (`BZERO = 32768`) are read as [`u8`] and [`u16`]. All other images, including signed
+/// integers, are read as [`f32`], since their samples can be negative.
+fn sample_type_of(hdu: &Hdu) -> i64 {
+    match (hdu.bitpix, hdu.header.scale()) {
+        (8, (0., 1.)) => 8,
+        (16, (32768., 1.)) => 16,
+        _ => -32,
+    }
+}
+
+/// Read the physical values of the samples of an image HDU.
+fn read_plane<T: NativeFitsPrimitive>(hdu: &Hdu, len: usize) -> io::Result<Vec<T>> {
+    let (bzero, bscale) = hdu.header.scale();
+    let size = hdu.bitpix.unsigned_abs() as usize / 8;
+    let data = hdu
+        .data
+        .get(..len * size)
+        .ok_or_else(|| invalid_data("Data unit is truncated"))?;
+    let stored: fn(&[u8]) -> f64 = match hdu.bitpix {
+        8 => |b| f64::from(b[0]),
+        16 => |b| f64::from(i16::from_be_bytes([b[0], b[1]])),
+        32 => |b| f64::from(i32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        -32 => |b| f64::from(f32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+        -64 => |b| f64::from_be_bytes(b.try_into().unwrap()),
+        _ => return Err(invalid_data("Unsupported BITPIX")),
+    };
+    Ok(data
+        .chunks_exact(size)
+        .map(|b| T::from_f32_sample((bzero + bscale * stored(b)) as f32))
+        .collect())
+}
+
+/// Read the image of the HDU `idx`, in either channel layout.
+fn read_image<T: NativeFitsPrimitive>(
+    hdus: &[Hdu],
+    idx: usize,
+) -> io::Result<SerialImageBuffer<T>> {
+    let hdu = &hdus[idx];
+    if hdu.shape.contains(&0) {
+        return Err(invalid_data("Image must not be empty"));
+    }
+    let (height, width, planes) = match hdu.shape[..] {
+        [width, height] => {
+            let channels = hdu.header.int("CHANNELS").unwrap_or(1);
+            let mut planes = vec![read_plane(hdu, width * height)?];
+            // The extension names depend on the number of channels
+            let names: &[Channel] = match channels {
+                2 => &[Channel::Alpha],
+                3 => &[Channel::Green, Channel::Blue],
+                4 => &[Channel::Green, Channel::Blue, Channel::Alpha],
+                _ => &[],
+            };
+            for &channel in names {
+                let name = extension_name(channel);
+                let chdu = hdus
+                    .iter()
+                    .find(|hdu| hdu.header.string("EXTNAME").as_deref() == Some(name))
+                    .ok_or_else(|| invalid_data("Missing image extension"))?;
+                if chdu.shape != hdu.shape {
+                    return Err(invalid_data("Image extension has a different size"));
+                }
+                planes.push(read_plane(chdu, width * height)?);
+            }
+            (height, width, planes)
+        }
+        [width, height, channels] => {
+            let data = read_plane(hdu, width * height * channels)?;
+            let planes = data
+                .chunks_exact(width * height)
+                .map(|p| p.to_vec())
+                .collect();
+            (height, width, planes)
+        }
+        _ => return Err(invalid_data("Unsupported number of image dimensions")),
+    };
+    SerialImageBuffer::from_planes(width, height, planes, hdu.header.metadata())
+        .map_err(invalid_data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{FitsScaling, ImageMetaData};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn test_meta() -> ImageMetaData {
+        let mut meta = ImageMetaData::full_builder(
+            2,
+            2,
+            10,
+            20,
+            -10.5,
+            Duration::from_micros(1_500_250),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            "Camera 'One'",
+            100,
+            10,
+            0,
+            400,
+        );
+        meta.add_extended_attrib("FILTER", "Ha");
+        meta.add_extended_attrib("OBSERVER_NAME", "It's me");
+        meta
+    }
+
+    fn test_images() -> Vec<DynamicSerialImage> {
+        let mut u8img =
+            SerialImageBuffer::from_vec(3, 2, (0..6u8).map(|x| x * 40).collect()).unwrap();
+        u8img.set_metadata(Some(test_meta()));
+        let mut u16img =
+            SerialImageBuffer::from_vec(3, 2, (0..18u16).map(|x| x * 3000).collect()).unwrap();
+        u16img.set_metadata(Some(test_meta()));
+        let mut f32img =
+            SerialImageBuffer::from_vec(2, 2, (0..16).map(|x| x as f32 * 0.25 - 1.).collect())
+                .unwrap();
+        f32img.set_metadata(Some(test_meta()));
+        vec![u8img.into(), u16img.into(), f32img.into()]
+    }
+
+    #[test]
+    fn test_fits_native() {
+        for img in test_images() {
+            for layout in [FitsLayout::Extensions, FitsLayout::Cube] {
+                let options = FitsOptions::new().layout(layout).progname("test");
+                let mut bytes = Vec::new();
+                img.write_fits(&mut bytes, &options).unwrap();
+                assert_eq!(bytes.len() % BLOCK_LEN, 0);
+                let read = DynamicSerialImage::read_fits(bytes.as_slice()).unwrap();
+                assert_eq!(read, img);
+            }
+        }
+        let img = SerialImageBuffer::from_vec(2, 1, vec![1.5f32, -2.]).unwrap();
+        let options = FitsOptions::new().scaling(FitsScaling::Quantize);
+        let mut bytes = Vec::new();
+        img.write_fits(&mut bytes, &options).unwrap();
+        let read = SerialImageBuffer::<f32>::read_fits(bytes.as_slice()).unwrap();
+        for (a, b) in read
+            .channel_data(Channel::Luma)
+            .as_ref()
+            .unwrap()
+            .iter()
+            .zip([1.5, -2.])
+        {
+            assert!((a - b).abs() < 1e-4);
+        }
+        assert!(SerialImageBuffer::<u8>::read_fits(bytes.as_slice()).is_err());
+        let options = FitsOptions::new().compression(FitsCompression::Rice);
+        assert!(img.write_fits(Vec::new(), &options).is_err());
+        assert!(DynamicSerialImage::read_fits(&b"SIMPLE"[..]).is_err());
+    }
+
+    #[test]
+    fn test_fits_native_reserved_keys() {
+        let mut meta = test_meta();
+        for key in ["END", "BZERO", "naxis1", "ZTILE2", "TTYPE1"] {
+            meta.add_extended_attrib(key, "1000");
+        }
+        let mut expected =
+            SerialImageBuffer::from_vec(3, 2, (0..6u16).map(|x| x * 3000).collect()).unwrap();
+        expected.set_metadata(Some(test_meta()));
+        let mut img = expected.clone();
+        img.set_metadata(Some(meta));
+        for layout in [FitsLayout::Extensions, FitsLayout::Cube] {
+            let mut bytes = Vec::new();
+            img.write_fits(&mut bytes, &FitsOptions::new().layout(layout))
+                .unwrap();
+            let read = SerialImageBuffer::<u16>::read_fits(bytes.as_slice()).unwrap();
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[test]
+    fn test_fits_native_long_strings() {
+        let mut meta = test_meta();
+        meta.camera_name = format!("Camera '{}'", "x".repeat(90));
+        let mut img =
+            SerialImageBuffer::from_vec(3, 2, (0..6u16).map(|x| x * 3000).collect()).unwrap();
+        img.set_metadata(Some(meta));
+        let mut bytes = Vec::new();
+        let options = FitsOptions::new().progname(&"p".repeat(100));
+        img.write_fits(&mut bytes, &options).unwrap();
+        // The closing quote is kept, with the quotes counted twice
+        let read = SerialImageBuffer::<u16>::read_fits(bytes.as_slice()).unwrap();
+        let camera = read.get_metadata().unwrap().camera_name;
+        assert_eq!(camera, format!("Camera '{}", "x".repeat(59)));
+        let program = format!("PROGRAM = '{}'", "p".repeat(68));
+        assert!(bytes.windows(CARD_LEN).any(|card| card == program.as_bytes()));
+    }
+
+    /// Build a FITS file from header cards and big-endian data.
+    fn raw_fits(cards: &[&str], data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for card in cards.iter().chain(&["END"]) {
+            bytes.extend(format!("{:<80}", card).bytes());
+        }
+        bytes.resize(padded(bytes.len()), b' ');
+        bytes.extend_from_slice(data);
+        bytes.resize(padded(bytes.len()), 0);
+        bytes
+    }
+
+    #[test]
+    fn test_fits_native_invalid() {
+        let image = |bitpix: i64, bzero: i64, axes: &[i64], data: &[u8]| {
+            let mut cards = vec![
+                "SIMPLE  =                    T".to_owned(),
+                format!("BITPIX  = {:>20}", bitpix),
+                format!("NAXIS   = {:>20}", axes.len()),
+            ];
+            for (i, n) in axes.iter().enumerate() {
+                cards.push(format!("NAXIS{:<3}= {:>20}", i + 1, n));
+            }
+            cards.push(format!("BZERO   = {:>20}", bzero));
+            let cards: Vec<&str> = cards.iter().map(String::as_str).collect();
+            raw_fits(&cards, data)
+        };
+
+        // Signed integers are read as f32
+        let img = DynamicSerialImage::read_fits(&image(16, 0, &[2, 1], &[0xff, 0xfb, 0, 7])[..])
+            .unwrap();
+        assert_eq!(img.as_f32().unwrap().get_luma().unwrap(), &vec![-5., 7.]);
+        let img = DynamicSerialImage::read_fits(&image(8, -128, &[2, 1], &[0, 255])[..]).unwrap();
+        assert_eq!(img.as_f32().unwrap().get_luma().unwrap(), &vec![-128., 127.]);
+        let img = DynamicSerialImage::read_fits(&image(8, 0, &[2, 1], &[0, 255])[..]).unwrap();
+        assert_eq!(img.as_u8().unwrap().get_luma().unwrap(), &vec![0, 255]);
+
+        // Invalid or empty axes are rejected
+        for axes in [
+            &[-1, 2][..],
+            &[0, 2, 3],
+            &[i64::MAX, i64::MAX],
+            &[1 << 62, 1 << 62, 16],
+        ] {
+            let err = DynamicSerialImage::read_fits(&image(16, 0, axes, &[])[..]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[cfg(feature = "fitsio")]
+    #[test]
+    fn test_fits_native_cfitsio() {
+        let scalings = [
+            FitsScaling::Native,
+            FitsScaling::Linear {
+                bzero: 1000.,
+                bscale: 2.,
+            },
+            FitsScaling::Quantize,
+        ];
+        for img in test_images() {
+            for layout in [FitsLayout::Extensions, FitsLayout::Cube] {
+                for scaling in scalings {
+                    let options = FitsOptions::new()
+                        .layout(layout)
+                        .scaling(scaling)
+                        .legacy_keys(true);
+                    let mut native = Vec::new();
+                    img.write_fits(&mut native, &options).unwrap();
+                    let cfitsio = img.to_fits_bytes(&options).unwrap();
+                    let from_native = DynamicSerialImage::from_fits_bytes(&native).unwrap();
+                    let from_cfitsio = DynamicSerialImage::read_fits(cfitsio.as_slice()).unwrap();
+                    assert_eq!(from_native, from_cfitsio);
+                    assert_eq!(
+                        DynamicSerialImage::read_fits(native.as_slice()).unwrap(),
+                        from_native
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+#![warn(missing_docs)]
+
+use serde::{Deserialize, Serialize};
+
+/// Layout of the channels of multi-channel images in a FITS file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FitsLayout {
+    /// The first channel (luminosity or red) is stored in the primary HDU, and the
+    /// remaining channels in image extensions named `GREEN`, `BLUE` and `ALPHA`.
+    #[default]
+    Extensions,
+    /// All channels are stored in the primary HDU as a single `NAXIS = 3` cube
+    /// (`NAXIS3` = number of channels), in the order of [`SerialImageBuffer::channels`](crate::SerialImageBuffer::channels).
+    /// This is the layout expected by most astronomy software for color images.
+    Cube,
+}
+
+/// Storage of the samples in a FITS file.
+///
+/// Scaled samples are stored as 16-bit signed integers (`BITPIX = 16`), with the
+/// physical values given by `BZERO + BSCALE * stored`. The `BZERO` and `BSCALE` keys are
+/// written to every image HDU, and applied when reading the file back.
+///
+/// Scaled images are read back as [`f32`] samples, whatever the sample type of the saved image,
+/// e.g. a [`u8`] image saved with [`FitsScaling::Linear`] is opened with
+/// [`DynamicSerialImage::open_fits`](crate::DynamicSerialImage::open_fits) or
+/// `SerialImageBuffer::<f32>::open_fits`, and can be converted back with
+/// [`SerialImageBuffer::convert`](crate::SerialImageBuffer::convert). The only exception is
+/// `BZERO = 32768` with `BSCALE = 1`, the FITS convention for unsigned integers, which is read
+/// as [`u16`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FitsScaling {
+    /// The samples are stored in their native type. [`u8`] samples are stored as `BITPIX = 8`
+    /// and [`f32`] samples as `BITPIX = -32`. [`u16`] samples are stored as `BITPIX = 16` with
+    /// `BZERO = 32768` and `BSCALE = 1`, the FITS convention for unsigned integers.
+    ///
+    /// The round-trip is bit-exact.
+    #[default]
+    Native,
+    /// The samples are stored as `round((value - bzero) / bscale)`, saturated to the range of [`i16`].
+    ///
+    /// Samples in the range `bzero + bscale * [-32768, 32767]` are recovered within `bscale / 2`;
+    /// integer samples are recovered exactly with `bscale = 1`. The samples are read back as [`f32`].
+    Linear {
+        /// Offset of the physical values (`BZERO`).
+        bzero: f64,
+        /// Scale of the physical values (`BSCALE`), must be non-zero.
+        bscale: f64,
+    },
+    /// The samples are quantized to 65536 levels spanning the range of the image
+    /// (over all channels), with `BSCALE = (max - min) / 65535`.
+    ///
+    /// The samples are recovered within `(max - min) / 131070` (half a quantization step),
+    /// plus the rounding error of [`f32`]. Images with non-finite samples can not be quantized.
+    /// The samples are read back as [`f32`].
+    Quantize,
+}
+
+/// Tile compression algorithm of FITS images.
+///
+/// Compression requires the `fitsio` feature.
+///
+/// Compressed images are stored in binary table extensions following the FITS tiled image
+/// compression convention, so the primary HDU of a compressed file is empty. Integer images
+/// are compressed losslessly by all algorithms except [`FitsCompression::Hcompress`] with a
+/// non-zero scale. Floating point images are quantized before compression, see
+/// [`FitsOptions::quantize_level`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FitsCompression {
+    /// The image is not compressed.
+    #[default]
+    None,
+    /// Rice compression, the fastest algorithm, and the default of `cfitsio`.
+    Rice,
+    /// GZIP compression.
+    Gzip1,
+    /// GZIP compression, with the bytes of the samples shuffled before compression.
+    /// Usually compresses floating point and 16-bit images better than [`FitsCompression::Gzip1`].
+    Gzip2,
+    /// H-compress compression, with the given scale. A scale of `0` is lossless; larger
+    /// scales discard noise, up to `scale` times the RMS noise of each tile.
+    /// The tiles must be at least 4 pixels wide and high.
+    Hcompress(f32),
+    /// IRAF pixel list compression, for masks and other integer images with few distinct values.
+    ///
+    /// The stored values must be between 0 and 2^24, which excludes [`u16`] images stored with
+    /// [`FitsScaling::Native`] (`BZERO = 32768`). Such images can be stored with
+    /// `FitsScaling::Linear { bzero: 0., bscale: 1. }` if all samples are below 32768.
+    Plio,
+}
+
+/// Options for writing FITS files.
+///
+/// The default values are:
+/// * `layout` - [`FitsLayout::Extensions`]
+/// * `legacy_keys` - `false`
+/// * `progname` - `None`
+/// * `overwrite` - `false`
+/// * `scaling` - [`FitsScaling::Native`]
+/// * `compression` - [`FitsCompression::None`]
+/// * `tile_size` - `None`, chosen by `cfitsio`
+/// * `quantize_level` - `None`, the `cfitsio` default of `4`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FitsOptions {
+    pub(crate) layout: FitsLayout,
+    pub(crate) legacy_keys: bool,
+    pub(crate) scaling: FitsScaling,
+    pub(crate) progname: Option<String>,
+    pub(crate) overwrite: bool,
+    pub(crate) compression: FitsCompression,
+    pub(crate) tile_size: Option<(usize, usize)>,
+    pub(crate) quantize_level: Option<f32>,
+}
+
+impl FitsOptions {
+    /// Create the default FITS options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the layout of the channels of multi-channel images.
+    pub fn layout(mut self, layout: FitsLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Also write the header keys used by earlier versions of this crate
+    /// (`CAMERA`, `TIMESTAMP`, `TEMPERATURE`, `EXPOSURE_US`, `ORIGIN_X`, `ORIGIN_Y`, `BIN_X` and `BIN_Y`),
+    /// for compatibility with existing readers.
+    ///
+    /// Keys longer than eight characters are written using the HIERARCH convention.
+    pub fn legacy_keys(mut self, legacy_keys: bool) -> Self {
+        self.legacy_keys = legacy_keys;
+        self
+    }
+
+    /// Set the name of the program that generated the image, written to the `PROGRAM` key.
+    pub fn progname(mut self, progname: &str) -> Self {
+        self.progname = Some(progname.to_owned());
+        self
+    }
+
+    /// Set whether to overwrite the file if it already exists.
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Set how the samples are stored, see [`FitsScaling`].
+    pub fn scaling(mut self, scaling: FitsScaling) -> Self {
+        self.scaling = scaling;
+        self
+    }
+
+    /// Set the tile compression algorithm, see [`FitsCompression`].
+    pub fn compression(mut self, compression: FitsCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the `(width, height)` of the compression tiles, in pixels. Channels are always
+    /// compressed separately. By default, `cfitsio` compresses every row of the image as
+    /// a tile, or blocks of rows for [`FitsCompression::Hcompress`].
+    pub fn tile_size(mut self, width: usize, height: usize) -> Self {
+        self.tile_size = Some((width, height));
+        self
+    }
+
+    /// Set the quantization level of compressed floating point images.
+    ///
+    /// A positive level `q` quantizes the samples of each tile with a step of `sigma / q`, where
+    /// `sigma` is the RMS noise of the tile, so larger levels preserve more precision. A negative
+    /// level `-d` quantizes the samples with an absolute step of `d`. A level of `0` disables the
+    /// quantization, in which case the samples are compressed losslessly with GZIP.
+    ///
+    /// Integer images, including images stored with [`FitsScaling::Linear`] or
+    /// [`FitsScaling::Quantize`], are not affected.
+    pub fn quantize_level(mut self, quantize_level: f32) -> Self {
+        self.quantize_level = Some(quantize_level);
+        self
+    }
+}
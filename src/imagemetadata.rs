@@ -4,6 +4,7 @@ use std::{
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 
 
@@ -167,4 +168,48 @@ impl ImageMetaData {
     pub fn get_extended_data(&self) -> &Vec<(String, String)> {
         &self.extended_metadata
     }
-}
\ No newline at end of file
+}
+
+/// Convert a timestamp to a UTC date, falling back to the Unix epoch for timestamps outside
+/// the range of [`chrono`].
+pub(crate) fn utc_datetime(timestamp: SystemTime) -> DateTime<Utc> {
+    let (secs, nanos) = match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(since) => (i64::try_from(since.as_secs()).ok(), since.subsec_nanos()),
+        Err(err) => {
+            let before = err.duration();
+            let secs = i64::try_from(before.as_secs()).ok().map(|secs| -secs);
+            match before.subsec_nanos() {
+                0 => (secs, 0),
+                nanos => (secs.map(|secs| secs - 1), 1_000_000_000 - nanos),
+            }
+        }
+    };
+    secs.and_then(|secs| Utc.timestamp_opt(secs, nanos).single())
+        .unwrap_or_else(|| DateTime::<Utc>::from(UNIX_EPOCH))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_utc_datetime() {
+        let date = |timestamp| {
+            utc_datetime(timestamp)
+                .format("%Y-%m-%dT%H:%M:%S%.3f")
+                .to_string()
+        };
+        assert_eq!(
+            date(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            "2023-11-14T22:13:20.123"
+        );
+        assert_eq!(
+            date(UNIX_EPOCH - Duration::from_millis(1_500)),
+            "1969-12-31T23:59:58.500"
+        );
+        // Out of the range of chrono
+        let far = Duration::from_secs(1 << 60);
+        assert_eq!(date(UNIX_EPOCH + far), "1970-01-01T00:00:00.000");
+        assert_eq!(date(UNIX_EPOCH - far), "1970-01-01T00:00:00.000");
+    }
+}
@@ -46,6 +46,76 @@ Now `img` can be sent on its merry way with full serialization
 `DynamicSerialImage` and `SerialImageBuffer` implements the `TryFrom` and `TryInto` traits 
 for `image::DynamicImage` and `image::ImageBuffer`.
 
+`SerialImageBuffer` and `DynamicSerialImage` also implement the `Add`, `Sub`, `Mul` and `Div` 
+operators (and their `*Assign` counterparts) with other images and with scalars, with saturating 
+semantics for integer pixel types. See the `PixelArithmetic` trait for details.
+
+## File Names
+A `FilenameTemplate` builds file names from the image metadata, e.g. 
+`{camera}_{timestamp}_{exposure_ms}ms_{seq:4}.png`. The timestamp has millisecond resolution by 
+default, and the sequence counter is incremented for every file. `DynamicSerialImage::save_template()` 
+(and `savefits_template()` with the `fitsio` feature) saves an image using a template, appending 
+`_1`, `_2`, ... to the file name instead of overwriting an existing file. The file is created 
+atomically, so concurrent saves never write to the same file. Use `savefits_to()` to save a FITS 
+image at an exact path.
+
+## TIFF
+`save()` writes images through the `image` crate, and the metadata is lost. `save_tiff()` and 
+`write_tiff()` write 8-bit, 16-bit and 32-bit floating point TIFF images, with the full metadata stored 
+as JSON in the `ImageDescription` tag, and the exposure time and timestamp in the standard EXIF 
+`ExposureTime` and `DateTimeOriginal` tags. `open_tiff()` and `read_tiff()` restore the image with its 
+metadata, falling back to the standard tags for TIFF files written by other programs.
+
+## PNG
+`save_png()` and `write_png()` write 8-bit and 16-bit grayscale and RGB(A) PNG images, with the full 
+metadata, including the extended attributes, stored as JSON in an iTXt chunk (keyword `ImageMetaData`), 
+and the camera name and timestamp in the standard `Source` and `Creation Time` text chunks. 
+`open_png()` and `read_png()` restore the image with its metadata.
+
+## Loading
+`DynamicSerialImage::open()` detects the format from the contents of the file, and reads PNG, TIFF, 
+FITS (with the `fitsio` or `fits-native` feature), XISF (with the `xisf` feature) and JSON files with 
+their metadata, and the other formats of the `image` crate without it. `save_with_sidecar()` saves the 
+image like `save()`, with the metadata in a `<file>.meta.json` sidecar that `open()` picks up.
+
+## Encoding
+`save_with()` encodes the image in any format of the `image` crate to a writer, with `EncoderOptions` 
+for the JPEG quality, the PNG compression level and row filter, and the TIFF compression (LZW, Deflate 
+or PackBits). The samples are converted to the deepest type the format stores, e.g. 32-bit floating 
+point images are saved as 16-bit PNG and 8-bit JPEG images. PNG and TIFF images keep their metadata.
+
+## SER
+`SerWriter` writes `u8` and `u16` grayscale or RGB frames to a SER video file, with the timestamp 
+of each frame taken from its metadata, and the observer, instrument, telescope and Bayer pattern 
+(`SerColorId`) from `SerOptions` or the metadata of the first frame. `SerReader` reads the header and 
+returns the frames on demand (`read_frame()`, or as an iterator), with the header fields mapped to the 
+frame metadata.
+
+## Sequences
+`SerialImageSequence` holds frames of the same dimensions and sample type, e.g. a time-lapse or burst 
+capture. The metadata shared by the frames is stored once, and only the timestamp, exposure and 
+temperature of each frame (`FrameMetaData`), so that the serialized sequence stays compact. Frames are 
+appended with `push()`, read with `get()` or `iter()`, which clone the image data, or borrowed with `image()` 
+and `metadata()`, and converted to and from `Vec<DynamicSerialImage>`.
+
+## Streaming
+`write_stream()` and `read_stream()` write and read images with their metadata in the binary format 
+of this crate, to and from any `io::Write` and `io::Read`, one band of rows at a time (`StreamOptions`), 
+so that very large images are not serialized to an intermediate string. `StreamWriter` accepts the rows 
+as they are produced (`write_rows()`), `StreamReader` returns the image band by band (`read_band()`), 
+and both report their progress through a callback (`on_progress()`). `open()` recognizes the format.
+
+`write_tiled()` stores the channels in tiles (`StreamOptions::tile_size()`) with an index of the tiles 
+after the header, and `read_region()` reads a region of the image from any `io::Read + io::Seek`, 
+decoding only the tiles covering it. Tiles and row bands are compressed with `StreamOptions::compression()` 
+(`StreamCompression::Zlib` or `Lz4`) with the `compression` feature flag.
+
+## Transport
+`FrameWriter` and `FrameReader` send and receive images over any `io::Write` and `io::Read`, e.g. TCP or 
+Unix sockets, as length-prefixed, versioned frames (`Frame`) holding an application-defined message type 
+and the image in the binary format of this crate. With the `tokio` feature flag, `FrameCodec` implements 
+the `tokio_util::codec` `Encoder` and `Decoder` traits for the same frames.
+
 ## Optional Features
 Additionally, the `Serial` image types optionally support saving as FITS images (method `savefits()`). 
 This feature is not enabled by default, and is available behind the `fitsio` feature flag. 
@@ -58,22 +128,150 @@ serialimage = { version = "3.0.0", features = ["fitsio"] }
 ```
 
 The FITS I/O is hidden behind a feature flag to avoid compilation errors on `wasm` targets.
+
+Color images are saved with one image extension per channel by default. Use `savefits_to()` 
+with `FitsOptions::new().layout(FitsLayout::Cube)` to save them as a single `NAXIS = 3` cube instead. 
+FITS files in either layout can be read back with `open_fits()`.
+
+The image metadata is stored using the usual FITS keywords (`DATE-OBS`, `EXPTIME`, `CCD-TEMP`, 
+`XBINNING`/`YBINNING`, `XORGSUBF`/`YORGSUBF`, `INSTRUME`, `GAIN` and `OFFSET`). The keys written by 
+earlier versions of this crate can be added with `FitsOptions::legacy_keys()`.
+
+`u16` images are stored with `BZERO = 32768`, and the round-trip is bit-exact. `FitsOptions::scaling()` 
+stores the samples as 16-bit integers with explicit `BZERO`/`BSCALE` keys instead, either with a linear 
+scale (`FitsScaling::Linear`) or quantized over the range of the image (`FitsScaling::Quantize`, 
+error at most half a quantization step). `open_fits()` applies the scaling when reading, and returns 
+`f32` samples for scaled images, whatever the sample type of the saved image.
+
+Images are tile-compressed with `FitsOptions::compression()` (`FitsCompression::Rice`, `Gzip1`, `Gzip2`, 
+`Hcompress` or `Plio`), with the tile size and the quantization level of floating point images set by 
+`FitsOptions::tile_size()` and `FitsOptions::quantize_level()`. The `compress` argument of `savefits()` 
+uses Rice compression with the default parameters of `cfitsio`.
+
+FITS files can also be encoded and decoded in memory with `to_fits_bytes()` and `from_fits_bytes()`, 
+with the same headers as the files written by `savefits_to()`.
+
+The `fits-native` feature flag implements FITS I/O in pure Rust, without `cfitsio`, e.g. for `wasm` 
+targets. `write_fits()` writes uncompressed images to any `std::io::Write`, with the same layouts, 
+scaling and header keys as `savefits_to()`, and `read_fits()` reads uncompressed images with `BITPIX` 
+8, 16, 32, -32 or -64 from any `std::io::Read`. `FitsOptions` is available with either feature.
+
+The `xisf` feature flag enables reading and writing monolithic [XISF](https://pixinsight.com/xisf/) 
+files, the native format of PixInsight, with `save_xisf()`/`write_xisf()` and `open_xisf()`/`read_xisf()`. 
+`u8`, `u16` and `f32` images are stored as `UInt8`, `UInt16` and `Float32` samples, uncompressed or 
+compressed with zlib or LZ4 (`XisfOptions::compression()`), optionally with byte shuffling. The metadata 
+is stored both as standard XISF properties (`Observation:Time:Start`, `Instrument:ExposureTime`, ...) and 
+as the same FITS keywords as the FITS files written by this crate, so that PixInsight shows it directly.
+
+The `compression` feature flag enables zlib and LZ4 compression of images in the binary format of 
+this crate (`StreamOptions::compression()`).
+
+The `tokio` feature flag enables asynchronous loading and saving of images: `save_async()`, 
+`savefits_async()` (with the `fitsio` feature flag), `open_async()` and `write_async()` to any tokio 
+`AsyncWrite`. The files are encoded and decoded on the blocking thread pool of the runtime, so that large 
+images do not block the reactor; the documentation of each method describes its cancellation safety. 
+It also enables `FrameCodec`, the [`tokio-util`](https://crates.io/crates/tokio-util) codec of the 
+transport frames.
+
+The `ndarray` feature flag enables zero-copy [`ndarray`](https://crates.io/crates/ndarray) views of the 
+individual channels of a `SerialImageBuffer` (methods `view()` and `view_mut()`), and conversions to and 
+from owned `Array2` (grayscale) and `Array3` (planar or interleaved channels) arrays.
+
+The `rayon` feature flag parallelizes the conversions between interleaved and planar pixel data, the 
+grayscale conversions and the per-pixel arithmetic and sample type conversions using 
+[`rayon`](https://crates.io/crates/rayon). The results are bit-identical to the serial implementation.
+
+Grayscale conversion uses the ITU-R BT.709 weights by default (`into_luma()`). Other weights 
+(`LumaWeights::Rec601`, `Equal` or `Custom`) and output sample types, including `f32` luminance in 
+`[0, 1]`, are available through `into_luma_with()`.
+`into_luma()` promotes 8-bit samples by a shift of 8 bits (`255` becomes `65280`), as in earlier 
+versions, while `into_luma_with()` scales them to the full range of the output type (`255` becomes `65535`).
  
 */
 
+mod arithmetic;
+#[cfg(feature = "tokio")]
+mod asyncio;
+mod convert;
 mod dynamicserialimage;
+mod encoder;
+mod filename;
+#[cfg(any(feature = "fitsio", feature = "fits-native", feature = "xisf"))]
+mod fitscommon;
+#[cfg(feature = "fitsio")]
+mod fitsimage;
+#[cfg(feature = "fits-native")]
+mod fitsnative;
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+mod fitsoptions;
 mod imagemetadata;
+mod load;
+mod luma;
+#[cfg(feature = "ndarray")]
+mod ndarrayinterop;
+mod ser;
+mod sequence;
 mod serialimage;
+mod stream;
+mod tiled;
+mod transport;
 mod optimalexposure;
+mod parallel;
+mod pixel;
+mod pngimage;
+mod tiffimage;
+#[cfg(feature = "xisf")]
+mod xisf;
 
 pub use serialimage::*;
 
+pub use ser::*;
+
+pub use sequence::*;
+
+pub use stream::*;
+
+pub use transport::*;
+
+pub use arithmetic::*;
+
+pub use convert::*;
+
 pub use dynamicserialimage::*;
 
+pub use encoder::*;
+
+pub use filename::*;
+
+#[cfg(feature = "fitsio")]
+pub use fitsimage::*;
+
+#[cfg(feature = "fits-native")]
+pub use fitsnative::*;
+
+#[cfg(any(feature = "fitsio", feature = "fits-native"))]
+pub use fitsoptions::*;
+
 pub use imagemetadata::*;
 
+pub use luma::*;
+
 pub use optimalexposure::*;
 
+pub use parallel::ParallelSample;
+
+pub use pixel::*;
+
+#[cfg(feature = "xisf")]
+pub use xisf::*;
+
+pub use pngimage::*;
+
+pub use tiffimage::*;
+
+#[cfg(feature = "ndarray")]
+pub use ndarrayinterop::*;
+
 #[cfg(test)]
 mod tests {
     #[cfg_attr(not(feature = "fitsio"), ignore)]
@@ -0,0 +1,249 @@
+#![warn(missing_docs)]
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+};
+
+use image::{
+    error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind},
+    ColorType, DynamicImage, ImageError, ImageResult,
+};
+
+use crate::{DynamicSerialImage, ImageMetaData};
+
+/// Suffix appended to the file name of metadata sidecar files.
+const SIDECAR_SUFFIX: &str = ".meta.json";
+
+/// Path of the metadata sidecar of the file at `path`, e.g. `image.png.meta.json`.
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(SIDECAR_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn decoding_error(
+    format: &str,
+    err: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name(format.to_owned()),
+        err,
+    ))
+}
+
+#[cfg_attr(
+    all(any(feature = "fitsio", feature = "fits-native"), feature = "xisf"),
+    allow(dead_code)
+)]
+fn unsupported(format: &str, feature: &str) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormatHint::Name(format.to_owned()),
+        UnsupportedErrorKind::GenericFeature(feature.to_owned()),
+    ))
+}
+
+impl DynamicSerialImage {
+    /// Open an image, detecting the format from the contents of the file.
+    ///
+    /// PNG, TIFF, JSON files (as serialized by this crate) and files in the binary format of
+    /// this crate (see [`DynamicSerialImage::write_stream`]) are read with their metadata,
+    /// as are FITS files with the `fitsio` or `fits-native` feature, and XISF files with the
+    /// `xisf` feature. Other formats supported by the [`image`] crate are read without metadata.
+    ///
+    /// If a metadata sidecar written by [`DynamicSerialImage::save_with_sidecar`] exists next to
+    /// the file, its metadata replaces the metadata read from the file.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. if the format is not recognized,
+    ///    or the color type of the image is not supported.
+    pub fn open(path: &Path) -> ImageResult<Self> {
+        let bytes = fs::read(path).map_err(ImageError::IoError)?;
+        let mut img = Self::decode(&bytes)?;
+        match fs::read(sidecar_path(path)) {
+            Ok(json) => {
+                let meta: ImageMetaData =
+                    serde_json::from_slice(&json).map_err(|err| decoding_error("JSON", err))?;
+                img.set_metadata(meta);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(ImageError::IoError(err)),
+        }
+        Ok(img)
+    }
+
+    /// Decode an image, detecting the format from the magic bytes.
+    fn decode(bytes: &[u8]) -> ImageResult<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Self::read_png(Cursor::new(bytes));
+        }
+        if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            return Self::read_tiff(Cursor::new(bytes));
+        }
+        if bytes.starts_with(b"SIMPLE  =") {
+            return Self::decode_fits(bytes);
+        }
+        if bytes.starts_with(crate::stream::MAGIC) {
+            return Self::read_stream(bytes).map_err(ImageError::IoError);
+        }
+        if bytes.starts_with(b"XISF0100") {
+            return Self::decode_xisf(bytes);
+        }
+        if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+            return serde_json::from_slice(bytes).map_err(|err| decoding_error("JSON", err));
+        }
+        let img = image::load_from_memory(bytes)?;
+        match img.color() {
+            ColorType::L8
+            | ColorType::La8
+            | ColorType::Rgb8
+            | ColorType::Rgba8
+            | ColorType::L16
+            | ColorType::La16
+            | ColorType::Rgb16
+            | ColorType::Rgba16
+            | ColorType::Rgb32F
+            | ColorType::Rgba32F => Ok(img.into()),
+            color => Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Unknown,
+                    UnsupportedErrorKind::Color(color.into()),
+                ),
+            )),
+        }
+    }
+
+    #[cfg(feature = "fitsio")]
+    fn decode_fits(bytes: &[u8]) -> ImageResult<Self> {
+        Self::from_fits_bytes(bytes).map_err(|err| decoding_error("FITS", err))
+    }
+
+    #[cfg(all(feature = "fits-native", not(feature = "fitsio")))]
+    fn decode_fits(bytes: &[u8]) -> ImageResult<Self> {
+        Self::read_fits(bytes).map_err(ImageError::IoError)
+    }
+
+    #[cfg(not(any(feature = "fitsio", feature = "fits-native")))]
+    fn decode_fits(_bytes: &[u8]) -> ImageResult<Self> {
+        Err(unsupported(
+            "FITS",
+            "FITS files, which require the `fitsio` or `fits-native` feature",
+        ))
+    }
+
+    #[cfg(feature = "xisf")]
+    fn decode_xisf(bytes: &[u8]) -> ImageResult<Self> {
+        Self::read_xisf(bytes).map_err(ImageError::IoError)
+    }
+
+    #[cfg(not(feature = "xisf"))]
+    fn decode_xisf(_bytes: &[u8]) -> ImageResult<Self> {
+        Err(unsupported(
+            "XISF",
+            "XISF files, which require the `xisf` feature",
+        ))
+    }
+
+    /// Save the image to a file at `path` as [`DynamicSerialImage::save`] does, and the image
+    /// metadata as JSON to a sidecar file named after the image file (e.g. `image.jpg.meta.json`),
+    /// so that [`DynamicSerialImage::open`] restores the metadata of formats that can not embed it.
+    ///
+    /// If the image has no metadata, an existing sidecar file is removed.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn save_with_sidecar(&self, path: &Path) -> ImageResult<()> {
+        let img: DynamicImage = self.into();
+        img.save(path)?;
+        let sidecar = sidecar_path(path);
+        match self.get_metadata() {
+            Some(meta) => {
+                let json = serde_json::to_vec(&meta).map_err(|err| {
+                    ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, err))
+                })?;
+                fs::write(sidecar, json).map_err(ImageError::IoError)
+            }
+            None => match fs::remove_file(sidecar) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(ImageError::IoError(err)),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SerialImageBuffer;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_open() {
+        let dir = std::env::temp_dir().join(format!("serialimage_open_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        meta.timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        meta.add_extended_attrib("FILTER", "L");
+        let mut img: DynamicSerialImage =
+            SerialImageBuffer::from_vec(3, 2, (0..6u16).map(|x| x * 10000).collect())
+                .unwrap()
+                .into();
+        img.set_metadata(meta.clone());
+
+        let path = dir.join("image.png");
+        img.save_png(&path).unwrap();
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), img);
+        let path = dir.join("image.tif");
+        img.save_tiff(&path).unwrap();
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), img);
+        let path = dir.join("image.json");
+        fs::write(&path, serde_json::to_string(&img).unwrap()).unwrap();
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), img);
+        let path = dir.join("image.bin");
+        img.write_stream(
+            fs::File::create(&path).unwrap(),
+            &crate::StreamOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), img);
+
+        #[cfg(feature = "fits-native")]
+        {
+            let path = dir.join("image.fits");
+            let mut bytes = Vec::new();
+            img.write_fits(&mut bytes, &crate::FitsOptions::new())
+                .unwrap();
+            fs::write(&path, bytes).unwrap();
+            assert_eq!(DynamicSerialImage::open(&path).unwrap().width(), 3);
+        }
+        #[cfg(feature = "xisf")]
+        {
+            let path = dir.join("image.xisf");
+            img.save_xisf(&path, &crate::XisfOptions::new()).unwrap();
+            assert_eq!(DynamicSerialImage::open(&path).unwrap(), img);
+        }
+
+        // Metadata of formats that can not embed it
+        let rgb: DynamicSerialImage =
+            SerialImageBuffer::from_vec(2, 1, vec![0u8, 128, 255, 64, 32, 16])
+                .unwrap()
+                .into();
+        let mut with_meta = rgb.clone();
+        with_meta.set_metadata(meta);
+        let path = dir.join("image.bmp");
+        with_meta.save_with_sidecar(&path).unwrap();
+        assert!(sidecar_path(&path).exists());
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), with_meta);
+        rgb.save_with_sidecar(&path).unwrap();
+        assert!(!sidecar_path(&path).exists());
+        assert_eq!(DynamicSerialImage::open(&path).unwrap(), rgb);
+
+        let path = dir.join("image.bin");
+        fs::write(&path, b"not an image").unwrap();
+        assert!(DynamicSerialImage::open(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+#![warn(missing_docs)]
+
+use serde::{Deserialize, Serialize};
+
+use crate::parallel::{map, map3};
+use crate::{DynamicSerialImage, PixelArithmetic, SampleType, SerialImageBuffer};
+
+/// Weights used to compute the luminance of color images.
+///
+/// The luminance is computed as `r * red + g * green + b * blue` on samples normalized to
+/// the full scale of their type (`255` for [`u8`], `65535` for [`u16`] and `1.0` for [`f32`]),
+/// so the result does not depend on the sample type of the source image.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LumaWeights {
+    /// ITU-R BT.709 (sRGB) weights, `0.2126 R + 0.7152 G + 0.0722 B`.
+    #[default]
+    Rec709,
+    /// ITU-R BT.601 weights, `0.299 R + 0.587 G + 0.114 B`.
+    Rec601,
+    /// Equal weights, i.e. the mean of the color channels.
+    Equal,
+    /// Custom `(r, g, b)` weights. The weights are applied as given, and integer outputs saturate.
+    Custom(f32, f32, f32),
+}
+
+impl LumaWeights {
+    /// Get the `(r, g, b)` weights.
+    pub fn coefficients(&self) -> (f32, f32, f32) {
+        match *self {
+            LumaWeights::Rec709 => (0.2126, 0.7152, 0.0722),
+            LumaWeights::Rec601 => (0.299, 0.587, 0.114),
+            LumaWeights::Equal => (1. / 3., 1. / 3., 1. / 3.),
+            LumaWeights::Custom(r, g, b) => (r, g, b),
+        }
+    }
+}
+
+/// Sample type of the luminance image returned by [`SerialImageBuffer::into_luma_with`].
+pub type OutputType = SampleType;
+
+fn full_scale<T: PixelArithmetic>() -> f32 {
+    T::DEFAULT_MAX_VALUE.to_f32_sample()
+}
+
+fn rescale<T: PixelArithmetic, U: PixelArithmetic>(src: &[T], scale: f32) -> Vec<U> {
+    map(src, |x| U::from_f32_sample(x.to_f32_sample() * scale))
+}
+
+impl<T: PixelArithmetic> SerialImageBuffer<T> {
+    /// Compute the luminance of the image, optionally keeping the alpha channel,
+    /// scaled to the full scale of the output type.
+    pub(crate) fn luma_as<U: PixelArithmetic>(
+        &self,
+        weights: LumaWeights,
+        keep_alpha: bool,
+    ) -> Result<SerialImageBuffer<U>, &'static str> {
+        self.luma_scaled(weights, keep_alpha, full_scale::<U>() / full_scale::<T>())
+    }
+
+    /// Compute the luminance of the image, optionally keeping the alpha channel,
+    /// with the samples multiplied by `scale`.
+    pub(crate) fn luma_scaled<U: PixelArithmetic>(
+        &self,
+        weights: LumaWeights,
+        keep_alpha: bool,
+        scale: f32,
+    ) -> Result<SerialImageBuffer<U>, &'static str> {
+        let (wr, wg, wb) = weights.coefficients();
+        if !(wr.is_finite() && wg.is_finite() && wb.is_finite()) {
+            return Err("Luminance weights must be finite");
+        }
+        let luma = match (
+            &self.data.luma,
+            &self.data.red,
+            &self.data.green,
+            &self.data.blue,
+        ) {
+            (Some(luma), _, _, _) => rescale(luma, scale),
+            (None, Some(red), Some(green), Some(blue)) => {
+                let (wr, wg, wb) = (wr * scale, wg * scale, wb * scale);
+                map3(red, green, blue, |r, g, b| {
+                    U::from_f32_sample(
+                        wr * r.to_f32_sample() + wg * g.to_f32_sample() + wb * b.to_f32_sample(),
+                    )
+                })
+            }
+            _ => return Err("Cannot convert image"),
+        };
+        let mut planes = vec![luma];
+        if keep_alpha {
+            if let Some(alpha) = &self.data.alpha {
+                planes.push(rescale(alpha, scale));
+            }
+        }
+        SerialImageBuffer::from_planes(self.width, self.height, planes, self.meta.clone())
+    }
+
+    /// Convert the image to grayscale using the given [`LumaWeights`], while discarding the alpha channel.
+    ///
+    /// The luminance is scaled to the full scale of the output type, e.g. `[0, 1]` for
+    /// [`OutputType::F32`]. Grayscale images are only rescaled. The metadata is preserved.
+    ///
+    /// # Errors
+    ///  - If the weights are not finite.
+    pub fn into_luma_with(
+        &self,
+        weights: LumaWeights,
+        output: OutputType,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        self.luma_dynamic(weights, output, false)
+    }
+
+    /// Convert the image to grayscale using the given [`LumaWeights`], while preserving the alpha channel.
+    ///
+    /// See [`SerialImageBuffer::into_luma_with`] for details.
+    ///
+    /// # Errors
+    ///  - If the weights are not finite.
+    pub fn into_luma_alpha_with(
+        &self,
+        weights: LumaWeights,
+        output: OutputType,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        self.luma_dynamic(weights, output, true)
+    }
+
+    fn luma_dynamic(
+        &self,
+        weights: LumaWeights,
+        output: OutputType,
+        keep_alpha: bool,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        Ok(match output {
+            SampleType::U8 => self.luma_as::<u8>(weights, keep_alpha)?.into(),
+            SampleType::U16 => self.luma_as::<u16>(weights, keep_alpha)?.into(),
+            SampleType::F32 => self.luma_as::<f32>(weights, keep_alpha)?.into(),
+        })
+    }
+}
+
+impl DynamicSerialImage {
+    /// Convert the image to grayscale using the given [`LumaWeights`], while discarding the alpha channel.
+    ///
+    /// See [`SerialImageBuffer::into_luma_with`] for details.
+    ///
+    /// # Errors
+    ///  - If the weights are not finite.
+    pub fn into_luma_with(
+        &self,
+        weights: LumaWeights,
+        output: OutputType,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        match self {
+            DynamicSerialImage::U8(value) => value.into_luma_with(weights, output),
+            DynamicSerialImage::U16(value) => value.into_luma_with(weights, output),
+            DynamicSerialImage::F32(value) => value.into_luma_with(weights, output),
+        }
+    }
+
+    /// Convert the image to grayscale using the given [`LumaWeights`], while preserving the alpha channel.
+    ///
+    /// See [`SerialImageBuffer::into_luma_with`] for details.
+    ///
+    /// # Errors
+    ///  - If the weights are not finite.
+    pub fn into_luma_alpha_with(
+        &self,
+        weights: LumaWeights,
+        output: OutputType,
+    ) -> Result<DynamicSerialImage, &'static str> {
+        match self {
+            DynamicSerialImage::U8(value) => value.into_luma_alpha_with(weights, output),
+            DynamicSerialImage::U16(value) => value.into_luma_alpha_with(weights, output),
+            DynamicSerialImage::F32(value) => value.into_luma_alpha_with(weights, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_luma_consistent() {
+        let rgb8 = SerialImageBuffer::from_vec(2, 1, vec![255u8, 255, 255, 255, 0, 0]).unwrap();
+        let rgb16 = rgb8.convert::<u16>(crate::ScalePolicy::Normalize).unwrap();
+        let rgbf = rgb8.convert::<f32>(crate::ScalePolicy::Normalize).unwrap();
+
+        let l8 = rgb8.into_luma_with(LumaWeights::Rec709, OutputType::U16).unwrap();
+        assert_eq!(l8, rgb16.into_luma().into());
+        assert_eq!(l8, rgbf.into_luma().into());
+        assert_eq!(l8.as_u16().unwrap().get_luma().unwrap(), &vec![65535, 13933]);
+
+        // into_luma() keeps promoting 8-bit samples by a shift of 8 bits
+        let l8 = SerialImageBuffer::from_vec(2, 1, vec![255u8, 128, 255, 0]).unwrap();
+        let l16 = l8.into_luma_alpha();
+        assert_eq!(l16.get_luma().unwrap(), &vec![65280, 65280]);
+        assert_eq!(l16.get_alpha().unwrap(), &vec![32768, 0]);
+
+        let lf = rgbf
+            .into_luma_with(LumaWeights::Rec601, OutputType::F32)
+            .unwrap();
+        let lf = lf.as_f32().unwrap().get_luma().unwrap();
+        assert!((lf[0] - 1.0).abs() < 1e-6);
+        assert!((lf[1] - 0.299).abs() < 1e-6);
+
+        let eq = rgb8
+            .into_luma_with(LumaWeights::Equal, OutputType::U8)
+            .unwrap();
+        assert_eq!(eq.as_u8().unwrap().get_luma().unwrap(), &vec![255, 85]);
+        assert!(rgb8
+            .into_luma_with(LumaWeights::Custom(f32::NAN, 0., 0.), OutputType::U8)
+            .is_err());
+    }
+
+    #[test]
+    fn test_luma_alpha() {
+        let img = SerialImageBuffer::from_vec(1, 1, vec![0u8, 255, 0, 128]).unwrap();
+        let la = img.into_luma_alpha();
+        assert_eq!(la.get_luma().unwrap(), &vec![46688]);
+        assert_eq!(la.get_alpha().unwrap(), &vec![32768]);
+        assert!(img.into_luma().get_alpha().is_none());
+    }
+}
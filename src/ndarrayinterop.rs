@@ -0,0 +1,167 @@
+#![warn(missing_docs)]
+
+use ndarray::{Array, Array2, Array3, ArrayView2, ArrayViewMut2, Axis, Dimension};
+
+use crate::{Channel, ImageMetaData, ParallelSample, Primitive, SerialImageBuffer};
+
+/// Memory layout of a three-dimensional image array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayLayout {
+    /// Height x width x channels, i.e. interleaved pixels.
+    Hwc,
+    /// Channels x height x width, i.e. planar channels.
+    Chw,
+}
+
+fn into_standard_vec<T: Clone, D: Dimension>(array: Array<T, D>) -> Vec<T> {
+    if array.is_standard_layout() {
+        let len = array.len();
+        let (mut data, offset) = array.into_raw_vec_and_offset();
+        let offset = offset.unwrap_or(0);
+        if offset > 0 {
+            data.drain(..offset);
+        }
+        data.truncate(len);
+        data
+    } else {
+        array.iter().cloned().collect()
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "ndarray")))]
+impl<T: Primitive + ParallelSample> SerialImageBuffer<T> {
+    /// Get a zero-copy two-dimensional view (`height x width`) of a channel.
+    ///
+    /// Returns `None` if the channel is not present in the image.
+    pub fn view(&self, channel: Channel) -> Option<ArrayView2<'_, T>> {
+        let shape = (self.height, self.width);
+        self.channel_data(channel)
+            .as_ref()
+            .map(|data| ArrayView2::from_shape(shape, data).unwrap())
+    }
+
+    /// Get a zero-copy mutable two-dimensional view (`height x width`) of a channel.
+    ///
+    /// Returns `None` if the channel is not present in the image.
+    pub fn view_mut(&mut self, channel: Channel) -> Option<ArrayViewMut2<'_, T>> {
+        let shape = (self.height, self.width);
+        self.channel_data_mut(channel)
+            .as_mut()
+            .map(|data| ArrayViewMut2::from_shape(shape, data).unwrap())
+    }
+
+    /// Consume a grayscale image and return the luminosity data as a `height x width` array, along with the image metadata.
+    ///
+    /// The data is not copied.
+    ///
+    /// # Errors
+    ///  - If the image is not grayscale.
+    pub fn into_array2(self) -> Result<(Array2<T>, Option<ImageMetaData>), &'static str> {
+        if !self.is_luma() {
+            return Err("Image must have one element per pixel");
+        }
+        let shape = (self.height, self.width);
+        let luma = self.data.luma.unwrap();
+        Ok((Array2::from_shape_vec(shape, luma).unwrap(), self.meta))
+    }
+
+    /// Create a grayscale image from a `height x width` array, with optional metadata.
+    ///
+    /// The data is not copied if the array is in standard (row-major) layout.
+    ///
+    /// # Errors
+    ///  - If the array is empty.
+    pub fn from_array2(
+        array: Array2<T>,
+        meta: Option<ImageMetaData>,
+    ) -> Result<Self, &'static str> {
+        let (height, width) = array.dim();
+        Self::from_planes(width, height, vec![into_standard_vec(array)], meta)
+    }
+
+    /// Return the image data as a three-dimensional array in the specified layout, along with the image metadata.
+    ///
+    /// The channels are ordered as in [`SerialImageBuffer::from_vec`], i.e. luma (and alpha) for grayscale images
+    /// and red, green, blue (and alpha) for color images.
+    pub fn to_array3(&self, layout: ArrayLayout) -> (Array3<T>, Option<ImageMetaData>) {
+        let (height, width) = (self.height, self.width);
+        let channels = self.data.pixel_elems as usize;
+        let array = match layout {
+            ArrayLayout::Hwc => {
+                Array3::from_shape_vec((height, width, channels), self.to_interleaved())
+            }
+            ArrayLayout::Chw => {
+                let mut data = Vec::with_capacity(height * width * channels);
+                for channel in self.channels() {
+                    data.extend_from_slice(self.channel_data(*channel).as_ref().unwrap());
+                }
+                Array3::from_shape_vec((channels, height, width), data)
+            }
+        };
+        (array.unwrap(), self.meta.clone())
+    }
+
+    /// Create an image from a three-dimensional array in the specified layout, with optional metadata.
+    ///
+    /// The number of channels must be between 1 and 4, ordered as in [`SerialImageBuffer::from_vec`].
+    ///
+    /// # Errors
+    ///  - If the array is empty.
+    ///  - If the number of channels is not in `[1..=4]`.
+    pub fn from_array3(
+        array: Array3<T>,
+        layout: ArrayLayout,
+        meta: Option<ImageMetaData>,
+    ) -> Result<Self, &'static str> {
+        let (height, width, planes) = match layout {
+            ArrayLayout::Hwc => {
+                let (height, width, channels) = array.dim();
+                let planes = (0..channels)
+                    .map(|c| array.index_axis(Axis(2), c).iter().copied().collect())
+                    .collect();
+                (height, width, planes)
+            }
+            ArrayLayout::Chw => {
+                let (_, height, width) = array.dim();
+                let planes = array
+                    .axis_iter(Axis(0))
+                    .map(|plane| plane.iter().copied().collect())
+                    .collect();
+                (height, width, planes)
+            }
+        };
+        Self::from_planes(width, height, planes, meta)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ndarray_roundtrip() {
+        let data: Vec<u16> = (0..24).collect();
+        let mut img = SerialImageBuffer::from_vec(3, 2, data).unwrap();
+        img.set_metadata(Some(ImageMetaData::default()));
+
+        let red = img.view(Channel::Red).unwrap();
+        assert_eq!(red.dim(), (2, 3));
+        assert_eq!(red[[1, 0]], 12);
+        assert!(img.view(Channel::Luma).is_none());
+
+        img.view_mut(Channel::Alpha).unwrap()[[0, 0]] = 100;
+        assert_eq!(img.get_alpha().unwrap()[0], 100);
+
+        for layout in [ArrayLayout::Hwc, ArrayLayout::Chw] {
+            let (array, meta) = img.to_array3(layout);
+            let back = SerialImageBuffer::from_array3(array, layout, meta).unwrap();
+            assert_eq!(back, img);
+        }
+
+        let luma = img.get_red().unwrap().clone();
+        let img = SerialImageBuffer::from_vec(3, 2, luma).unwrap();
+        let (array, meta) = img.clone().into_array2().unwrap();
+        assert_eq!(array[[1, 2]], 20);
+        assert_eq!(SerialImageBuffer::from_array2(array, meta).unwrap(), img);
+    }
+}
@@ -0,0 +1,263 @@
+//! Data-parallel building blocks for the per-sample loops of the crate.
+//!
+//! With the `rayon` feature enabled, the helpers split their work into chunks
+//! that are processed on the rayon thread pool. Without it, the same per-sample
+//! operations are performed serially, so the results are bit-identical.
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::Primitive;
+
+/// Bounds required of the sample types by the parallel conversions: [`Send`] and [`Sync`].
+///
+/// The bounds are the same with and without the `rayon` feature, so that enabling it does not
+/// change the types accepted by the crate. The trait is implemented for all types meeting them.
+pub trait ParallelSample: Send + Sync {}
+
+impl<T: Send + Sync> ParallelSample for T {}
+
+/// Number of pixels processed by a single parallel task.
+#[cfg(feature = "rayon")]
+const CHUNK: usize = 1 << 14;
+
+/// Split interleaved pixel data with `elems` elements per pixel into separate planes.
+pub(crate) fn deinterleave<T: Primitive + ParallelSample>(data: &[T], elems: usize) -> Vec<Vec<T>> {
+    let size = data.len() / elems;
+    let mut planes = vec![vec![T::zero(); size]; elems];
+    #[cfg(feature = "rayon")]
+    {
+        let tasks = split_planes(&mut planes);
+        tasks
+            .into_par_iter()
+            .zip(data.par_chunks(CHUNK * elems))
+            .for_each(|(mut dst, src)| deinterleave_chunk(src, &mut dst));
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        let mut dst: Vec<&mut [T]> = planes.iter_mut().map(|p| p.as_mut_slice()).collect();
+        deinterleave_chunk(data, &mut dst);
+    }
+    planes
+}
+
+/// Split the planes into chunks of [`CHUNK`] pixels, grouped by chunk index.
+#[cfg(feature = "rayon")]
+fn split_planes<T>(planes: &mut [Vec<T>]) -> Vec<Vec<&mut [T]>> {
+    let mut tasks: Vec<Vec<&mut [T]>> = Vec::new();
+    for plane in planes.iter_mut() {
+        for (i, chunk) in plane.chunks_mut(CHUNK).enumerate() {
+            if tasks.len() <= i {
+                tasks.push(Vec::with_capacity(4));
+            }
+            tasks[i].push(chunk);
+        }
+    }
+    tasks
+}
+
+/// De-interleave `src` into the planes in `dst` in a single pass.
+///
+/// The loops for 2, 3 and 4 elements per pixel iterate over fixed-size pixel chunks
+/// without bounds checks, which allows the compiler to vectorize them.
+fn deinterleave_chunk<T: Copy>(src: &[T], dst: &mut [&mut [T]]) {
+    match dst {
+        [a, b] => {
+            for ((px, a), b) in src.chunks_exact(2).zip(a.iter_mut()).zip(b.iter_mut()) {
+                *a = px[0];
+                *b = px[1];
+            }
+        }
+        [r, g, b] => {
+            for (((px, r), g), b) in src
+                .chunks_exact(3)
+                .zip(r.iter_mut())
+                .zip(g.iter_mut())
+                .zip(b.iter_mut())
+            {
+                *r = px[0];
+                *g = px[1];
+                *b = px[2];
+            }
+        }
+        [r, g, b, a] => {
+            for ((((px, r), g), b), a) in src
+                .chunks_exact(4)
+                .zip(r.iter_mut())
+                .zip(g.iter_mut())
+                .zip(b.iter_mut())
+                .zip(a.iter_mut())
+            {
+                *r = px[0];
+                *g = px[1];
+                *b = px[2];
+                *a = px[3];
+            }
+        }
+        _ => {
+            let elems = dst.len();
+            for (c, plane) in dst.iter_mut().enumerate() {
+                for (d, px) in plane.iter_mut().zip(src.chunks_exact(elems)) {
+                    *d = px[c];
+                }
+            }
+        }
+    }
+}
+
+/// Interleave planes of equal length into pixel data with one element per plane.
+pub(crate) fn interleave<T: Primitive + ParallelSample>(planes: &[&[T]]) -> Vec<T> {
+    let elems = planes.len();
+    let size = planes.first().map(|p| p.len()).unwrap_or(0);
+    let mut data = vec![T::zero(); size * elems];
+    #[cfg(feature = "rayon")]
+    data.par_chunks_mut(CHUNK * elems)
+        .enumerate()
+        .for_each(|(i, dst)| {
+            let end = (i * CHUNK + CHUNK).min(size);
+            let src: Vec<&[T]> = planes.iter().map(|p| &p[i * CHUNK..end]).collect();
+            interleave_chunk(&src, dst);
+        });
+    #[cfg(not(feature = "rayon"))]
+    interleave_chunk(planes, &mut data);
+    data
+}
+
+/// Interleave the planes in `src` into `dst` in a single pass.
+fn interleave_chunk<T: Copy>(src: &[&[T]], dst: &mut [T]) {
+    match src {
+        [a, b] => {
+            for ((px, a), b) in dst.chunks_exact_mut(2).zip(a.iter()).zip(b.iter()) {
+                px[0] = *a;
+                px[1] = *b;
+            }
+        }
+        [r, g, b] => {
+            for (((px, r), g), b) in dst
+                .chunks_exact_mut(3)
+                .zip(r.iter())
+                .zip(g.iter())
+                .zip(b.iter())
+            {
+                px[0] = *r;
+                px[1] = *g;
+                px[2] = *b;
+            }
+        }
+        [r, g, b, a] => {
+            for ((((px, r), g), b), a) in dst
+                .chunks_exact_mut(4)
+                .zip(r.iter())
+                .zip(g.iter())
+                .zip(b.iter())
+                .zip(a.iter())
+            {
+                px[0] = *r;
+                px[1] = *g;
+                px[2] = *b;
+                px[3] = *a;
+            }
+        }
+        _ => {
+            let elems = src.len();
+            for (c, plane) in src.iter().enumerate() {
+                for (px, s) in dst.chunks_exact_mut(elems).zip(plane.iter()) {
+                    px[c] = *s;
+                }
+            }
+        }
+    }
+}
+
+/// Apply `f` to every element of `data` in place.
+pub(crate) fn for_each_mut<T: Send>(data: &mut [T], f: impl Fn(&mut T) + Send + Sync) {
+    #[cfg(feature = "rayon")]
+    data.par_chunks_mut(CHUNK)
+        .for_each(|chunk| chunk.iter_mut().for_each(&f));
+    #[cfg(not(feature = "rayon"))]
+    data.iter_mut().for_each(f);
+}
+
+/// Apply `f` to every element of `dst` in place, along with the corresponding element of `src`.
+pub(crate) fn zip_for_each_mut<T: Send, U: Sync>(
+    dst: &mut [T],
+    src: &[U],
+    f: impl Fn(&mut T, &U) + Send + Sync,
+) {
+    #[cfg(feature = "rayon")]
+    dst.par_chunks_mut(CHUNK)
+        .zip(src.par_chunks(CHUNK))
+        .for_each(|(d, s)| d.iter_mut().zip(s.iter()).for_each(|(d, s)| f(d, s)));
+    #[cfg(not(feature = "rayon"))]
+    dst.iter_mut().zip(src.iter()).for_each(|(d, s)| f(d, s));
+}
+
+/// Collect `f` applied to every element of `src`.
+pub(crate) fn map<T: Sync, U: Send>(src: &[T], f: impl Fn(&T) -> U + Send + Sync) -> Vec<U> {
+    #[cfg(feature = "rayon")]
+    let out = src.par_iter().with_min_len(CHUNK).map(f).collect();
+    #[cfg(not(feature = "rayon"))]
+    let out = src.iter().map(f).collect();
+    out
+}
+
+/// Collect `f` applied to every triplet of corresponding elements of `a`, `b` and `c`.
+pub(crate) fn map3<T: Sync, U: Send>(
+    a: &[T],
+    b: &[T],
+    c: &[T],
+    f: impl Fn(&T, &T, &T) -> U + Send + Sync,
+) -> Vec<U> {
+    #[cfg(feature = "rayon")]
+    let out = a
+        .par_iter()
+        .zip(b.par_iter())
+        .zip(c.par_iter())
+        .with_min_len(CHUNK)
+        .map(|((a, b), c)| f(a, b, c))
+        .collect();
+    #[cfg(not(feature = "rayon"))]
+    let out = a
+        .iter()
+        .zip(b.iter())
+        .zip(c.iter())
+        .map(|((a, b), c)| f(a, b, c))
+        .collect();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_interleave_roundtrip() {
+        let size = 100_003;
+        for elems in 1..=4 {
+            let data: Vec<u16> = (0..size * elems).map(|x| (x % 65521) as u16).collect();
+            let planes = deinterleave(&data, elems);
+            for (c, plane) in planes.iter().enumerate() {
+                assert!(plane
+                    .iter()
+                    .enumerate()
+                    .all(|(i, x)| *x == data[i * elems + c]));
+            }
+            let planes: Vec<&[u16]> = planes.iter().map(|p| p.as_slice()).collect();
+            assert_eq!(interleave(&planes), data);
+        }
+    }
+
+    #[test]
+    fn test_map() {
+        let a: Vec<f32> = (0..50_000).map(|x| x as f32).collect();
+        let b = map(&a, |x| x * 0.5);
+        assert!(b.iter().zip(a.iter()).all(|(b, a)| *b == a * 0.5));
+        let c = map3(&a, &b, &a, |x, y, z| x + y + z);
+        assert!(c.iter().zip(a.iter()).all(|(c, a)| *c == a + a * 0.5 + a));
+
+        let mut d = a.clone();
+        zip_for_each_mut(&mut d, &b, |d, b| *d -= b);
+        for_each_mut(&mut d, |d| *d *= 2.0);
+        assert_eq!(d, a);
+    }
+}
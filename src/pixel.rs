@@ -0,0 +1,295 @@
+#![warn(missing_docs)]
+
+use crate::{serialimage::channels_of, Channel, Primitive, SerialImageBuffer};
+
+/// A single pixel of a [`SerialImageBuffer`].
+///
+/// The pixel elements are stored in the same order as the channels of the image
+/// (see [`SerialImageBuffer::channels`]): luma (and alpha) for grayscale pixels,
+/// and red, green, blue (and alpha) for color pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerialPixel<T: Primitive> {
+    elems: [T; 4],
+    len: u8,
+}
+
+impl<T: Primitive> SerialPixel<T> {
+    /// Create a grayscale pixel.
+    pub fn luma(luma: T) -> Self {
+        Self {
+            elems: [luma, T::zero(), T::zero(), T::zero()],
+            len: 1,
+        }
+    }
+
+    /// Create a grayscale pixel with alpha channel.
+    pub fn luma_alpha(luma: T, alpha: T) -> Self {
+        Self {
+            elems: [luma, alpha, T::zero(), T::zero()],
+            len: 2,
+        }
+    }
+
+    /// Create a color pixel.
+    pub fn rgb(red: T, green: T, blue: T) -> Self {
+        Self {
+            elems: [red, green, blue, T::zero()],
+            len: 3,
+        }
+    }
+
+    /// Create a color pixel with alpha channel.
+    pub fn rgba(red: T, green: T, blue: T, alpha: T) -> Self {
+        Self {
+            elems: [red, green, blue, alpha],
+            len: 4,
+        }
+    }
+
+    /// Create a pixel from a slice of pixel elements.
+    ///
+    /// Returns `None` if the length of the slice is not in `[1..=4]`.
+    pub fn from_slice(elems: &[T]) -> Option<Self> {
+        if elems.is_empty() || elems.len() > 4 {
+            return None;
+        }
+        let mut pixel = Self {
+            elems: [T::zero(); 4],
+            len: elems.len() as u8,
+        };
+        pixel.elems[..elems.len()].copy_from_slice(elems);
+        Some(pixel)
+    }
+
+    /// Get the pixel elements.
+    pub fn as_slice(&self) -> &[T] {
+        &self.elems[..self.len as usize]
+    }
+
+    /// Get a mutable reference to the pixel elements.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.elems[..self.len as usize]
+    }
+
+    /// Get the number of pixel elements.
+    pub fn pixel_elems(&self) -> u8 {
+        self.len
+    }
+
+    /// Get the channels of the pixel, in pixel element order.
+    pub fn channels(&self) -> &'static [Channel] {
+        channels_of(self.len)
+    }
+
+    /// Get the value of a channel, or `None` if the channel is not present in the pixel.
+    pub fn get(&self, channel: Channel) -> Option<T> {
+        self.channels()
+            .iter()
+            .position(|c| *c == channel)
+            .map(|idx| self.elems[idx])
+    }
+}
+
+/// A row of a [`SerialImageBuffer`], with access to all channels.
+#[derive(Debug, Clone, Copy)]
+pub struct SerialImageRow<'a, T: Primitive> {
+    y: usize,
+    channels: &'static [Channel],
+    slices: [&'a [T]; 4],
+}
+
+impl<'a, T: Primitive> SerialImageRow<'a, T> {
+    /// Get the index of the row.
+    pub fn y(&self) -> usize {
+        self.y
+    }
+
+    /// Get the number of pixels in the row.
+    pub fn width(&self) -> usize {
+        self.slices[0].len()
+    }
+
+    /// Get the row data of a channel, or `None` if the channel is not present in the image.
+    pub fn channel(&self, channel: Channel) -> Option<&'a [T]> {
+        self.channels
+            .iter()
+            .position(|c| *c == channel)
+            .map(|idx| self.slices[idx])
+    }
+
+    /// Get the pixel at column `x`, or `None` if `x` is out of bounds.
+    pub fn get_pixel(&self, x: usize) -> Option<SerialPixel<T>> {
+        if x >= self.width() {
+            return None;
+        }
+        let mut pixel = SerialPixel {
+            elems: [T::zero(); 4],
+            len: self.channels.len() as u8,
+        };
+        for (idx, slice) in self.slices[..self.channels.len()].iter().enumerate() {
+            pixel.elems[idx] = slice[x];
+        }
+        Some(pixel)
+    }
+
+    /// Iterate over the pixels of the row.
+    pub fn pixels(&self) -> impl Iterator<Item = SerialPixel<T>> + 'a {
+        let row = *self;
+        (0..row.width()).map(move |x| row.get_pixel(x).unwrap())
+    }
+}
+
+impl<T: Primitive> SerialImageBuffer<T> {
+    /// Get the pixel at `(x, y)`, or `None` if the coordinates are out of bounds.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<SerialPixel<T>> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let idx = y * self.width + x;
+        let mut pixel = SerialPixel {
+            elems: [T::zero(); 4],
+            len: self.data.pixel_elems,
+        };
+        for (elem, channel) in pixel.elems.iter_mut().zip(self.channels()) {
+            *elem = self.channel_data(*channel).as_ref().unwrap()[idx];
+        }
+        Some(pixel)
+    }
+
+    /// Set the pixel at `(x, y)`.
+    ///
+    /// # Errors
+    ///  - If the coordinates are out of bounds.
+    ///  - If the number of pixel elements does not match the image.
+    pub fn put_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        pixel: SerialPixel<T>,
+    ) -> Result<(), &'static str> {
+        if x >= self.width || y >= self.height {
+            return Err("Pixel coordinates out of bounds");
+        }
+        if pixel.len != self.data.pixel_elems {
+            return Err("Number of pixel elements does not match the image");
+        }
+        let idx = y * self.width + x;
+        for (elem, channel) in pixel.elems.iter().zip(self.channels()) {
+            self.channel_data_mut(*channel).as_mut().unwrap()[idx] = *elem;
+        }
+        Ok(())
+    }
+
+    /// Iterate over the rows of the image.
+    pub fn rows(&self) -> impl Iterator<Item = SerialImageRow<'_, T>> + '_ {
+        let channels = self.channels();
+        (0..self.height).map(move |y| {
+            let mut slices: [&[T]; 4] = [&[]; 4];
+            let range = y * self.width..(y + 1) * self.width;
+            for (slice, channel) in slices.iter_mut().zip(channels) {
+                *slice = &self.channel_data(*channel).as_ref().unwrap()[range.clone()];
+            }
+            SerialImageRow {
+                y,
+                channels,
+                slices,
+            }
+        })
+    }
+
+    /// Iterate over the pixels of the image in row-major order.
+    pub fn pixels(&self) -> impl Iterator<Item = SerialPixel<T>> + '_ {
+        self.rows().flat_map(|row| row.pixels())
+    }
+
+    /// Iterate over the pixels of the image in row-major order, along with their `(x, y)` coordinates.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, SerialPixel<T>)> + '_ {
+        self.rows().flat_map(|row| {
+            let y = row.y();
+            row.pixels()
+                .enumerate()
+                .map(move |(x, pixel)| (x, y, pixel))
+        })
+    }
+
+    /// Create a new image by applying `f` to every channel of the image.
+    ///
+    /// The closure receives the channel and its data, and must return the new channel data.
+    /// The metadata is preserved.
+    ///
+    /// # Errors
+    ///  - If the length of the returned channel data is not equal to `width * height`.
+    pub fn map_channels<U: Primitive>(
+        &self,
+        mut f: impl FnMut(Channel, &[T]) -> Vec<U>,
+    ) -> Result<SerialImageBuffer<U>, &'static str> {
+        let planes = self
+            .channels()
+            .iter()
+            .map(|&channel| f(channel, self.channel_data(channel).as_ref().unwrap()))
+            .collect();
+        SerialImageBuffer::from_planes(self.width, self.height, planes, self.meta.clone())
+    }
+
+    /// Apply `f` to every channel of the image in place.
+    ///
+    /// The closure receives the channel and a mutable reference to its data.
+    pub fn map_channels_in_place(&mut self, mut f: impl FnMut(Channel, &mut [T])) {
+        for &channel in self.channels() {
+            f(channel, self.channel_data_mut(channel).as_mut().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pixel_access() {
+        let data: Vec<u8> = (0..24).collect();
+        let mut img = SerialImageBuffer::from_vec(3, 2, data).unwrap();
+        assert_eq!(
+            img.channels(),
+            &[Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha]
+        );
+        assert!(img.channel(Channel::Luma).is_none());
+        assert_eq!(img.channel(Channel::Green), img.get_green());
+
+        let pixel = img.get_pixel(1, 1).unwrap();
+        assert_eq!(pixel.as_slice(), &[16, 17, 18, 19]);
+        assert_eq!(pixel.get(Channel::Alpha), Some(19));
+        assert!(img.get_pixel(3, 0).is_none());
+
+        img.put_pixel(0, 1, SerialPixel::rgba(1, 2, 3, 4)).unwrap();
+        assert_eq!(img.get_pixel(0, 1), Some(SerialPixel::rgba(1, 2, 3, 4)));
+        assert!(img.put_pixel(0, 0, SerialPixel::luma(1)).is_err());
+
+        let rows: Vec<_> = img.rows().collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].channel(Channel::Blue), Some(&[3, 18, 22][..]));
+
+        let pixels: Vec<_> = img.enumerate_pixels().collect();
+        assert_eq!(pixels.len(), 6);
+        assert_eq!(pixels[4], (1, 1, img.get_pixel(1, 1).unwrap()));
+        assert_eq!(img.pixels().count(), 6);
+    }
+
+    #[test]
+    fn test_map_channels() {
+        let mut img = SerialImageBuffer::from_vec(2, 1, vec![10u8, 255, 20, 128]).unwrap();
+        let wide = img
+            .map_channels(|_, data| data.iter().map(|x| *x as u16 * 2).collect())
+            .unwrap();
+        assert_eq!(wide.channel(Channel::Luma), Some(&vec![20, 40]));
+        assert_eq!(wide.channel(Channel::Alpha), Some(&vec![510, 256]));
+        assert!(img.map_channels(|_, _| vec![0u8]).is_err());
+
+        img.map_channels_in_place(|channel, data| {
+            if channel != Channel::Alpha {
+                data.iter_mut().for_each(|x| *x += 1);
+            }
+        });
+        assert_eq!(img.into_vec(), vec![11, 255, 21, 128]);
+    }
+}
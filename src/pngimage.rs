@@ -0,0 +1,402 @@
+#![warn(missing_docs)]
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, Write},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use chrono::{DateTime, Utc};
+use image::{
+    error::{
+        DecodingError, EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind,
+    },
+    ImageError, ImageFormat, ImageResult,
+};
+use png::{BitDepth, ColorType, Compression, Decoder, Encoder, Filter, Transformations};
+
+use crate::{
+    imagemetadata::utc_datetime, DynamicSerialImage, ImageMetaData, PixelArithmetic,
+    PngCompression, PngFilter, SerialImageBuffer,
+};
+
+/// Keyword of the iTXt chunk holding the image metadata as JSON.
+const METADATA_KEYWORD: &str = "ImageMetaData";
+
+/// Sample types that can be stored in and read from PNG images.
+pub trait PngPrimitive: PixelArithmetic {
+    /// Bit depth of the PNG images holding the samples.
+    #[doc(hidden)]
+    const BIT_DEPTH: BitDepth;
+
+    /// Convert the samples to big-endian bytes.
+    #[doc(hidden)]
+    fn to_be_bytes(data: &[Self]) -> Vec<u8>;
+
+    /// Convert big-endian bytes to samples.
+    #[doc(hidden)]
+    fn from_be_bytes(data: &[u8]) -> Vec<Self>;
+}
+
+impl PngPrimitive for u8 {
+    const BIT_DEPTH: BitDepth = BitDepth::Eight;
+
+    fn to_be_bytes(data: &[Self]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn from_be_bytes(data: &[u8]) -> Vec<Self> {
+        data.to_vec()
+    }
+}
+
+impl PngPrimitive for u16 {
+    const BIT_DEPTH: BitDepth = BitDepth::Sixteen;
+
+    fn to_be_bytes(data: &[Self]) -> Vec<u8> {
+        data.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+
+    fn from_be_bytes(data: &[u8]) -> Vec<Self> {
+        data.chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .collect()
+    }
+}
+
+fn encoding_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        err,
+    ))
+}
+
+fn decoding_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Png),
+        err,
+    ))
+}
+
+/// Decoded PNG image, with the interleaved samples as big-endian bytes.
+struct PngImage {
+    width: usize,
+    height: usize,
+    depth: BitDepth,
+    data: Vec<u8>,
+    meta: Option<ImageMetaData>,
+}
+
+impl PngImage {
+    fn read<R: BufRead + Seek>(reader: R) -> ImageResult<Self> {
+        let mut decoder = Decoder::new(reader);
+        // Palette and low bit depth images are expanded to 8-bit samples
+        decoder.set_transformations(Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(decoding_error)?;
+        let size = reader
+            .output_buffer_size()
+            .ok_or_else(|| decoding_error("Image is too large"))?;
+        let mut data = vec![0; size];
+        let info = reader.next_frame(&mut data).map_err(decoding_error)?;
+        data.truncate(info.buffer_size());
+        // Text chunks may follow the image data
+        reader.finish().map_err(decoding_error)?;
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            depth: info.bit_depth,
+            data,
+            meta: read_metadata(reader.info()),
+        })
+    }
+
+    fn into_buffer<T: PngPrimitive>(self) -> ImageResult<SerialImageBuffer<T>> {
+        if self.depth != T::BIT_DEPTH {
+            return Err(decoding_error("Image sample type does not match"));
+        }
+        let data = T::from_be_bytes(&self.data);
+        let mut img =
+            SerialImageBuffer::from_vec(self.width, self.height, data).map_err(decoding_error)?;
+        img.set_metadata(self.meta);
+        Ok(img)
+    }
+}
+
+/// Restore the image metadata from the iTXt chunk written by this crate, or
+/// from the standard `Source` and `Creation Time` keywords.
+fn read_metadata(info: &png::Info) -> Option<ImageMetaData> {
+    let text = |keyword: &str| {
+        info.utf8_text
+            .iter()
+            .filter(|chunk| chunk.keyword == keyword)
+            .find_map(|chunk| chunk.get_text().ok())
+            .or_else(|| {
+                info.uncompressed_latin1_text
+                    .iter()
+                    .find(|chunk| chunk.keyword == keyword)
+                    .map(|chunk| chunk.text.clone())
+            })
+    };
+    if let Some(meta) = text(METADATA_KEYWORD).and_then(|json| serde_json::from_str(&json).ok()) {
+        return Some(meta);
+    }
+    let camera = text("Source");
+    let timestamp = text("Creation Time")
+        .and_then(|date| DateTime::parse_from_rfc2822(date.trim()).ok())
+        .map(|date| date.with_timezone(&Utc).into());
+    if camera.is_none() && timestamp.is_none() {
+        return None;
+    }
+    let mut meta = ImageMetaData::default();
+    meta.camera_name = camera.unwrap_or_default();
+    meta.timestamp = timestamp.unwrap_or(UNIX_EPOCH);
+    Some(meta)
+}
+
+impl<T: PngPrimitive> SerialImageBuffer<T> {
+    /// Save the image data to a PNG file at `path`. See [`SerialImageBuffer::write_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn save_png(&self, path: &Path) -> ImageResult<()> {
+        let file = File::create(path).map_err(ImageError::IoError)?;
+        self.write_png(BufWriter::new(file))
+    }
+
+    /// Write the image data as an 8-bit or 16-bit PNG image, grayscale or RGB with an optional alpha channel.
+    ///
+    /// The image metadata, including the extended attributes, is stored as JSON in an iTXt chunk
+    /// with the keyword `ImageMetaData`. The camera name and the timestamp are also stored with
+    /// the standard `Source` and `Creation Time` keywords.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn write_png<W: Write>(&self, writer: W) -> ImageResult<()> {
+        self.write_png_with(writer, PngCompression::default(), None)
+    }
+
+    /// Write the image data as a PNG image with the given compression level, and the given
+    /// filter or the filter chosen for the compression level.
+    pub(crate) fn write_png_with<W: Write>(
+        &self,
+        writer: W,
+        compression: PngCompression,
+        filter: Option<PngFilter>,
+    ) -> ImageResult<()> {
+        let mut encoder = Encoder::new(writer, self.width() as u32, self.height() as u32);
+        encoder.set_color(match self.channels().len() {
+            1 => ColorType::Grayscale,
+            2 => ColorType::GrayscaleAlpha,
+            3 => ColorType::Rgb,
+            _ => ColorType::Rgba,
+        });
+        encoder.set_depth(T::BIT_DEPTH);
+        encoder.set_compression(match compression {
+            PngCompression::Default => Compression::Balanced,
+            PngCompression::Fast => Compression::Fast,
+            PngCompression::Best => Compression::High,
+            PngCompression::None => Compression::NoCompression,
+        });
+        if let Some(filter) = filter {
+            encoder.set_filter(match filter {
+                PngFilter::NoFilter => Filter::NoFilter,
+                PngFilter::Sub => Filter::Sub,
+                PngFilter::Up => Filter::Up,
+                PngFilter::Avg => Filter::Avg,
+                PngFilter::Paeth => Filter::Paeth,
+                PngFilter::Adaptive => Filter::Adaptive,
+            });
+        }
+        encoder
+            .add_text_chunk(
+                "Software".to_owned(),
+                concat!("serialimage ", env!("CARGO_PKG_VERSION")).to_owned(),
+            )
+            .map_err(encoding_error)?;
+        if let Some(meta) = self.get_metadata() {
+            let timestamp = utc_datetime(meta.timestamp.max(UNIX_EPOCH));
+            encoder
+                .add_text_chunk("Creation Time".to_owned(), timestamp.to_rfc2822())
+                .map_err(encoding_error)?;
+            encoder
+                .add_itxt_chunk("Source".to_owned(), meta.camera_name.clone())
+                .map_err(encoding_error)?;
+            let json = serde_json::to_string(&meta).map_err(encoding_error)?;
+            encoder
+                .add_itxt_chunk(METADATA_KEYWORD.to_owned(), json)
+                .map_err(encoding_error)?;
+        }
+        let mut writer = encoder.write_header().map_err(encoding_error)?;
+        writer
+            .write_image_data(&T::to_be_bytes(&self.to_interleaved()))
+            .map_err(encoding_error)?;
+        writer.finish().map_err(encoding_error)
+    }
+
+    /// Open a PNG image. See [`SerialImageBuffer::read_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn open_png(path: &Path) -> ImageResult<Self> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        Self::read_png(BufReader::new(file))
+    }
+
+    /// Read a PNG image. Palette images and images with less than 8 bits per sample are expanded.
+    ///
+    /// The image metadata is restored from the iTXt chunk written by [`SerialImageBuffer::write_png`].
+    /// For other files, the camera name and timestamp are restored from the standard `Source` and
+    /// `Creation Time` keywords, if present.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. if the file does not contain an image
+    ///    of this sample type.
+    pub fn read_png<R: BufRead + Seek>(reader: R) -> ImageResult<Self> {
+        PngImage::read(reader)?.into_buffer()
+    }
+}
+
+impl DynamicSerialImage {
+    /// Save the image data to a PNG file at `path`, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. for [`f32`] images, which must be
+    ///    converted to [`u16`] first.
+    pub fn save_png(&self, path: &Path) -> ImageResult<()> {
+        let file = File::create(path).map_err(ImageError::IoError)?;
+        self.write_png(BufWriter::new(file))
+    }
+
+    /// Write the image data as a PNG image, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. for [`f32`] images, which must be
+    ///    converted to [`u16`] first.
+    pub fn write_png<W: Write>(&self, writer: W) -> ImageResult<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_png(writer),
+            DynamicSerialImage::U16(value) => value.write_png(writer),
+            DynamicSerialImage::F32(_) => Err(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Exact(ImageFormat::Png),
+                    UnsupportedErrorKind::GenericFeature(
+                        "32-bit floating point samples".to_owned(),
+                    ),
+                ),
+            )),
+        }
+    }
+
+    /// Open a PNG image. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn open_png(path: &Path) -> ImageResult<Self> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        Self::read_png(BufReader::new(file))
+    }
+
+    /// Read a PNG image. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_png`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn read_png<R: BufRead + Seek>(reader: R) -> ImageResult<Self> {
+        let img = PngImage::read(reader)?;
+        match img.depth {
+            BitDepth::Sixteen => Ok(img.into_buffer::<u16>()?.into()),
+            _ => Ok(img.into_buffer::<u8>()?.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{io::Cursor, time::Duration};
+
+    #[test]
+    fn test_png() {
+        let mut meta = ImageMetaData::full_builder(
+            2,
+            2,
+            10,
+            20,
+            -10.5,
+            Duration::from_micros(1_500_250),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            "Caméra",
+            100,
+            10,
+            0,
+            400,
+        );
+        meta.add_extended_attrib("FILTER", "Hα");
+        let mut images: Vec<DynamicSerialImage> = vec![
+            SerialImageBuffer::from_vec(3, 2, (0..6u16).map(|x| x * 10000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(3, 2, (0..12u16).map(|x| x * 5000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(2, 2, (0..16u16).map(|x| x * 4000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(2, 1, vec![0u8, 128, 255, 64, 32, 16])
+                .unwrap()
+                .into(),
+        ];
+        for img in images.iter_mut() {
+            img.set_metadata(meta.clone());
+            let mut bytes = Vec::new();
+            img.write_png(&mut bytes).unwrap();
+            assert_eq!(
+                &DynamicSerialImage::read_png(Cursor::new(&bytes)).unwrap(),
+                img
+            );
+            // The image crate reads the same file
+            let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::Png).unwrap();
+            assert_eq!(decoded.width(), img.width() as u32);
+        }
+
+        let mut bytes = Vec::new();
+        images[3].write_png(&mut bytes).unwrap();
+        assert!(SerialImageBuffer::<u16>::read_png(Cursor::new(&bytes)).is_err());
+        let img =
+            DynamicSerialImage::from(SerialImageBuffer::from_vec(1, 1, vec![0.5f32]).unwrap());
+        assert!(img.write_png(Vec::new()).is_err());
+
+        // Metadata from the standard keywords
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut bytes, 1, 1);
+            encoder.set_color(ColorType::Grayscale);
+            encoder
+                .add_text_chunk("Source".to_owned(), "Other".to_owned())
+                .unwrap();
+            encoder
+                .add_text_chunk(
+                    "Creation Time".to_owned(),
+                    "Tue, 14 Nov 2023 22:13:20 +0000".to_owned(),
+                )
+                .unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[7]).unwrap();
+        }
+        let img = SerialImageBuffer::<u8>::read_png(Cursor::new(&bytes)).unwrap();
+        let meta = img.get_metadata().unwrap();
+        assert_eq!(meta.camera_name, "Other");
+        assert_eq!(
+            meta.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+}
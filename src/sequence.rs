@@ -0,0 +1,447 @@
+#![warn(missing_docs)]
+
+use std::{
+    borrow::Cow,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DynamicSerialImage, ImageMetaData, SampleType};
+
+/// Metadata that changes from frame to frame of a [`SerialImageSequence`].
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FrameMetaData {
+    /// Timestamp of the frame
+    pub timestamp: SystemTime,
+    /// Exposure time
+    pub exposure: Duration,
+    /// Camera temperature (C)
+    pub temperature: f32,
+}
+
+impl From<&ImageMetaData> for FrameMetaData {
+    fn from(meta: &ImageMetaData) -> Self {
+        Self {
+            timestamp: meta.timestamp,
+            exposure: meta.exposure,
+            temperature: meta.temperature,
+        }
+    }
+}
+
+impl FrameMetaData {
+    /// Apply the frame metadata to the common metadata of the sequence.
+    fn apply(&self, common: &ImageMetaData) -> ImageMetaData {
+        let mut meta = common.clone();
+        meta.timestamp = self.timestamp;
+        meta.exposure = self.exposure;
+        meta.temperature = self.temperature;
+        meta
+    }
+}
+
+/// Metadata of a frame, relative to the common metadata of the sequence.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+enum FrameMeta {
+    /// The frame has no metadata.
+    None,
+    /// The frame metadata is the common metadata with a different timestamp, exposure and temperature.
+    Delta(FrameMetaData),
+    /// The frame metadata differs from the common metadata in other fields.
+    Full(ImageMetaData),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct SequenceFrame {
+    /// Image data, without metadata.
+    image: DynamicSerialImage,
+    meta: FrameMeta,
+}
+
+/// A sequence of frames with the same dimensions and sample type, e.g. a time-lapse or burst capture.
+///
+/// The metadata shared by the frames (camera name, gain, offset, binning, extended attributes etc.)
+/// is stored once, and only the timestamp, exposure and temperature are stored per frame, which
+/// keeps the serialized sequence compact. Frames whose metadata differs in other fields are stored
+/// with their full metadata.
+///
+/// # Traits
+/// [`SerialImageSequence`] implements the [`std::clone::Clone`], [`std::fmt::Debug`], [`serde::Serialize`]
+/// and [`serde::Deserialize`] traits, [`std::convert::TryFrom`] a [`Vec<DynamicSerialImage>`],
+/// and [`std::convert::From`] a sequence to a [`Vec<DynamicSerialImage>`]. Deserialization
+/// fails if the frames do not have the same dimensions, sample type and pixel elements.
+#[derive(Clone, Default, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(try_from = "SequenceData")]
+pub struct SerialImageSequence {
+    common: Option<ImageMetaData>,
+    frames: Vec<SequenceFrame>,
+}
+
+/// Serialized form of a [`SerialImageSequence`], validated before use.
+#[derive(Deserialize)]
+struct SequenceData {
+    common: Option<ImageMetaData>,
+    frames: Vec<SequenceFrame>,
+}
+
+impl TryFrom<SequenceData> for SerialImageSequence {
+    type Error = &'static str;
+
+    fn try_from(data: SequenceData) -> Result<Self, Self::Error> {
+        let mut sequence = Self {
+            common: data.common,
+            frames: Vec::with_capacity(data.frames.len()),
+        };
+        for frame in data.frames {
+            if frame.image.get_metadata().is_some() {
+                return Err("Frame images must not hold metadata");
+            }
+            sequence.check(&frame.image)?;
+            sequence.frames.push(frame);
+        }
+        Ok(sequence)
+    }
+}
+
+impl SerialImageSequence {
+    /// Create an empty sequence.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the number of frames in the sequence.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Check if the sequence has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Get the width and height of the frames, or `None` if the sequence is empty.
+    pub fn dimensions(&self) -> Option<(usize, usize)> {
+        self.frames
+            .first()
+            .map(|frame| (frame.image.width(), frame.image.height()))
+    }
+
+    /// Get the sample type of the frames, or `None` if the sequence is empty.
+    pub fn sample_type(&self) -> Option<SampleType> {
+        self.frames.first().map(|frame| frame.image.sample_type())
+    }
+
+    /// Get the metadata shared by the frames, taken from the first frame with metadata.
+    pub fn common_metadata(&self) -> Option<&ImageMetaData> {
+        self.common.as_ref()
+    }
+
+    /// Append a frame to the sequence.
+    ///
+    /// # Errors
+    ///  - If the dimensions, sample type or number of pixel elements of the frame differ from
+    ///    those of the frames in the sequence.
+    pub fn push(&mut self, mut image: DynamicSerialImage) -> Result<(), &'static str> {
+        self.check(&image)?;
+        let meta = match take_metadata(&mut image) {
+            None => FrameMeta::None,
+            Some(meta) => match &self.common {
+                None => {
+                    let delta = FrameMetaData::from(&meta);
+                    self.common = Some(meta);
+                    FrameMeta::Delta(delta)
+                }
+                Some(common) => {
+                    let delta = FrameMetaData::from(&meta);
+                    if delta.apply(common) == meta {
+                        FrameMeta::Delta(delta)
+                    } else {
+                        FrameMeta::Full(meta)
+                    }
+                }
+            },
+        };
+        self.frames.push(SequenceFrame { image, meta });
+        Ok(())
+    }
+
+    /// Check that a frame matches the frames in the sequence.
+    fn check(&self, image: &DynamicSerialImage) -> Result<(), &'static str> {
+        if let Some(first) = self.frames.first() {
+            if (first.image.width(), first.image.height()) != (image.width(), image.height()) {
+                return Err("Frame dimensions must match the dimensions of the sequence");
+            }
+            if first.image.sample_type() != image.sample_type() {
+                return Err("Frame sample type must match the sample type of the sequence");
+            }
+            if pixel_elems(&first.image) != pixel_elems(image) {
+                return Err("Frame pixel elements must match the pixel elements of the sequence");
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the image data of the frame at `index`, without metadata, or `None` if `index` is
+    /// out of bounds.
+    pub fn image(&self, index: usize) -> Option<&DynamicSerialImage> {
+        self.frames.get(index).map(|frame| &frame.image)
+    }
+
+    /// Get the metadata of the frame at `index`, or `None` if `index` is out of bounds
+    /// or the frame has no metadata.
+    ///
+    /// The metadata is borrowed from the sequence if the frame is stored with its full metadata,
+    /// and built from the common metadata otherwise.
+    pub fn metadata(&self, index: usize) -> Option<Cow<'_, ImageMetaData>> {
+        match &self.frames.get(index)?.meta {
+            FrameMeta::None => None,
+            FrameMeta::Delta(delta) => Some(Cow::Owned(
+                delta.apply(self.common.as_ref().unwrap_or(&Default::default())),
+            )),
+            FrameMeta::Full(meta) => Some(Cow::Borrowed(meta)),
+        }
+    }
+
+    /// Get the frame at `index` with its full metadata, or `None` if `index` is out of bounds.
+    ///
+    /// The image data of the frame is cloned, see [`SerialImageSequence::image`] and
+    /// [`SerialImageSequence::metadata`] to access the frame without copying it.
+    pub fn get(&self, index: usize) -> Option<DynamicSerialImage> {
+        self.frames
+            .get(index)
+            .map(|frame| self.frame(frame.clone()))
+    }
+
+    /// Get the metadata of the frame at `index`, or `None` if `index` is out of bounds
+    /// or the frame has no metadata.
+    pub fn get_metadata(&self, index: usize) -> Option<ImageMetaData> {
+        self.metadata(index).map(Cow::into_owned)
+    }
+
+    /// Get the per-frame metadata of the frame at `index`, or `None` if `index` is out of bounds
+    /// or the frame has no metadata.
+    pub fn get_frame_metadata(&self, index: usize) -> Option<FrameMetaData> {
+        self.metadata(index)
+            .map(|meta| FrameMetaData::from(meta.as_ref()))
+    }
+
+    /// Iterate over the frames of the sequence, with their full metadata.
+    ///
+    /// The image data of every frame is cloned, see [`SerialImageSequence::image`].
+    pub fn iter(&self) -> impl Iterator<Item = DynamicSerialImage> + '_ {
+        self.frames.iter().map(|frame| self.frame(frame.clone()))
+    }
+
+    fn frame(&self, frame: SequenceFrame) -> DynamicSerialImage {
+        frame.into_image(self.common.as_ref())
+    }
+}
+
+impl SequenceFrame {
+    /// Get the frame image with its full metadata.
+    fn into_image(self, common: Option<&ImageMetaData>) -> DynamicSerialImage {
+        let mut image = self.image;
+        if let Some(meta) = self.meta.resolve(common) {
+            image.set_metadata(meta);
+        }
+        image
+    }
+}
+
+impl FrameMeta {
+    /// Get the full metadata of a frame from the common metadata of the sequence.
+    fn resolve(self, common: Option<&ImageMetaData>) -> Option<ImageMetaData> {
+        match self {
+            FrameMeta::None => None,
+            FrameMeta::Delta(delta) => Some(delta.apply(common.unwrap_or(&Default::default()))),
+            FrameMeta::Full(meta) => Some(meta),
+        }
+    }
+}
+
+fn pixel_elems(image: &DynamicSerialImage) -> u8 {
+    match image {
+        DynamicSerialImage::U8(value) => value.pixel_elems(),
+        DynamicSerialImage::U16(value) => value.pixel_elems(),
+        DynamicSerialImage::F32(value) => value.pixel_elems(),
+    }
+}
+
+fn take_metadata(image: &mut DynamicSerialImage) -> Option<ImageMetaData> {
+    match image {
+        DynamicSerialImage::U8(value) => value.meta.take(),
+        DynamicSerialImage::U16(value) => value.meta.take(),
+        DynamicSerialImage::F32(value) => value.meta.take(),
+    }
+}
+
+/// Owning iterator over the frames of a [`SerialImageSequence`].
+#[derive(Debug)]
+pub struct SequenceIntoIter {
+    common: Option<ImageMetaData>,
+    frames: std::vec::IntoIter<SequenceFrame>,
+}
+
+impl Iterator for SequenceIntoIter {
+    type Item = DynamicSerialImage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames
+            .next()
+            .map(|frame| frame.into_image(self.common.as_ref()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.frames.size_hint()
+    }
+}
+
+impl ExactSizeIterator for SequenceIntoIter {}
+
+impl IntoIterator for SerialImageSequence {
+    type Item = DynamicSerialImage;
+    type IntoIter = SequenceIntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        SequenceIntoIter {
+            common: self.common,
+            frames: self.frames.into_iter(),
+        }
+    }
+}
+
+impl TryFrom<Vec<DynamicSerialImage>> for SerialImageSequence {
+    type Error = &'static str;
+
+    fn try_from(images: Vec<DynamicSerialImage>) -> Result<Self, Self::Error> {
+        let mut sequence = Self::new();
+        for image in images {
+            sequence.push(image)?;
+        }
+        Ok(sequence)
+    }
+}
+
+impl From<SerialImageSequence> for Vec<DynamicSerialImage> {
+    fn from(sequence: SerialImageSequence) -> Self {
+        sequence.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SerialImageBuffer;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_sequence() {
+        let mut meta = ImageMetaData::new(
+            UNIX_EPOCH,
+            Duration::from_millis(100),
+            -10.0,
+            2,
+            2,
+            "Camera",
+            100,
+            10,
+        );
+        meta.add_extended_attrib("FILTER", "Ha");
+        let frames: Vec<DynamicSerialImage> = (0..4u16)
+            .map(|i| {
+                let mut img: DynamicSerialImage = SerialImageBuffer::from_vec(4, 2, vec![i; 8])
+                    .unwrap()
+                    .into();
+                let mut meta = meta.clone();
+                meta.timestamp = UNIX_EPOCH + Duration::from_secs(i as u64);
+                meta.temperature += i as f32 * 0.1;
+                if i == 3 {
+                    meta.gain = 200;
+                }
+                img.set_metadata(meta);
+                img
+            })
+            .collect();
+
+        let mut sequence = SerialImageSequence::try_from(frames.clone()).unwrap();
+        assert_eq!(sequence.len(), 4);
+        assert_eq!(sequence.dimensions(), Some((4, 2)));
+        assert_eq!(sequence.sample_type(), Some(SampleType::U16));
+        assert_eq!(sequence.common_metadata().unwrap().camera_name, "Camera");
+        assert_eq!(sequence.get(1).unwrap(), frames[1]);
+        assert_eq!(sequence.get(3).unwrap().get_metadata().unwrap().gain, 200);
+        assert_eq!(
+            sequence.get_frame_metadata(2).unwrap().timestamp,
+            UNIX_EPOCH + Duration::from_secs(2)
+        );
+        assert!(sequence.get(4).is_none());
+        assert_eq!(sequence.iter().collect::<Vec<_>>(), frames);
+
+        // The common metadata is serialized once
+        let json = serde_json::to_string(&sequence).unwrap();
+        assert_eq!(json.matches("Camera").count(), 2);
+        let decoded: SerialImageSequence = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, sequence);
+
+        // Borrowed access
+        assert!(sequence.image(1).unwrap().get_metadata().is_none());
+        assert_eq!(
+            sequence
+                .image(1)
+                .unwrap()
+                .as_u16()
+                .unwrap()
+                .to_interleaved(),
+            vec![1; 8]
+        );
+        assert!(matches!(sequence.metadata(1), Some(Cow::Owned(_))));
+        assert!(matches!(sequence.metadata(3), Some(Cow::Borrowed(_))));
+        assert_eq!(
+            sequence.metadata(2).unwrap().as_ref(),
+            &frames[2].get_metadata().unwrap()
+        );
+        assert!(sequence.image(4).is_none() && sequence.metadata(4).is_none());
+
+        // Deserialized frames are checked like pushed frames
+        let mut value = serde_json::to_value(&sequence).unwrap();
+        let other: DynamicSerialImage = SerialImageBuffer::from_vec(2, 4, vec![0u16; 8])
+            .unwrap()
+            .into();
+        value["frames"][1]["image"] = serde_json::to_value(&other).unwrap();
+        assert!(serde_json::from_value::<SerialImageSequence>(value.clone()).is_err());
+        value["frames"][1]["image"] = serde_json::to_value(&frames[1]).unwrap();
+        assert!(serde_json::from_value::<SerialImageSequence>(value).is_err());
+
+        assert!(sequence
+            .push(
+                SerialImageBuffer::from_vec(2, 4, vec![0u16; 8])
+                    .unwrap()
+                    .into()
+            )
+            .is_err());
+        assert!(sequence
+            .push(
+                SerialImageBuffer::from_vec(4, 2, vec![0u8; 8])
+                    .unwrap()
+                    .into()
+            )
+            .is_err());
+        assert!(sequence
+            .push(
+                SerialImageBuffer::from_vec(4, 2, vec![0u16; 24])
+                    .unwrap()
+                    .into()
+            )
+            .is_err());
+        let plain: DynamicSerialImage = SerialImageBuffer::from_vec(4, 2, vec![5u16; 8])
+            .unwrap()
+            .into();
+        sequence.push(plain.clone()).unwrap();
+        assert!(sequence.get_metadata(4).is_none());
+
+        let images: Vec<DynamicSerialImage> = sequence.into();
+        assert_eq!(images[..4], frames[..]);
+        assert_eq!(images[4], plain);
+    }
+}
@@ -0,0 +1,804 @@
+#![warn(missing_docs)]
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Local, Offset, TimeZone};
+
+use crate::{
+    imagemetadata::utc_datetime, DynamicSerialImage, ImageMetaData, PixelArithmetic, SampleType,
+    SerialImageBuffer,
+};
+
+/// Signature at the start of SER files.
+const FILE_ID: &[u8; 14] = b"LUCAM-RECORDER";
+
+/// Length of the SER header.
+const HEADER_LEN: u64 = 178;
+
+/// Length of the observer, instrument and telescope fields of the header.
+const FIELD_LEN: usize = 40;
+
+/// Number of 100 ns ticks between 0001-01-01 and the Unix epoch.
+const EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+/// Extended attribute holding the observer.
+const OBSERVER_KEY: &str = "OBSERVER";
+
+/// Extended attribute holding the telescope.
+const TELESCOPE_KEY: &str = "TELESCOP";
+
+/// Extended attribute holding the Bayer pattern of raw frames.
+const BAYER_KEY: &str = "BAYERPAT";
+
+/// Color layout of the frames of a SER file (`ColorID`).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SerColorId {
+    /// Monochrome frames.
+    #[default]
+    Mono,
+    /// Raw frames with an RGGB Bayer pattern.
+    BayerRggb,
+    /// Raw frames with a GRBG Bayer pattern.
+    BayerGrbg,
+    /// Raw frames with a GBRG Bayer pattern.
+    BayerGbrg,
+    /// Raw frames with a BGGR Bayer pattern.
+    BayerBggr,
+    /// Raw frames with a CYYM Bayer pattern.
+    BayerCyym,
+    /// Raw frames with a YCMY Bayer pattern.
+    BayerYcmy,
+    /// Raw frames with a YMCY Bayer pattern.
+    BayerYmcy,
+    /// Raw frames with a MYYC Bayer pattern.
+    BayerMyyc,
+    /// Color frames, with interleaved red, green and blue samples.
+    Rgb,
+    /// Color frames, with interleaved blue, green and red samples.
+    Bgr,
+}
+
+impl SerColorId {
+    const ALL: [SerColorId; 11] = [
+        SerColorId::Mono,
+        SerColorId::BayerRggb,
+        SerColorId::BayerGrbg,
+        SerColorId::BayerGbrg,
+        SerColorId::BayerBggr,
+        SerColorId::BayerCyym,
+        SerColorId::BayerYcmy,
+        SerColorId::BayerYmcy,
+        SerColorId::BayerMyyc,
+        SerColorId::Rgb,
+        SerColorId::Bgr,
+    ];
+
+    /// Value of the `ColorID` field of the header.
+    pub fn id(self) -> i32 {
+        match self {
+            SerColorId::Mono => 0,
+            SerColorId::BayerRggb => 8,
+            SerColorId::BayerGrbg => 9,
+            SerColorId::BayerGbrg => 10,
+            SerColorId::BayerBggr => 11,
+            SerColorId::BayerCyym => 16,
+            SerColorId::BayerYcmy => 17,
+            SerColorId::BayerYmcy => 18,
+            SerColorId::BayerMyyc => 19,
+            SerColorId::Rgb => 100,
+            SerColorId::Bgr => 101,
+        }
+    }
+
+    /// Get the color layout from the value of the `ColorID` field of the header.
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|color| color.id() == id)
+    }
+
+    /// Bayer pattern of raw frames (e.g. `RGGB`), `None` for monochrome and color frames.
+    pub fn bayer_pattern(self) -> Option<&'static str> {
+        match self {
+            SerColorId::BayerRggb => Some("RGGB"),
+            SerColorId::BayerGrbg => Some("GRBG"),
+            SerColorId::BayerGbrg => Some("GBRG"),
+            SerColorId::BayerBggr => Some("BGGR"),
+            SerColorId::BayerCyym => Some("CYYM"),
+            SerColorId::BayerYcmy => Some("YCMY"),
+            SerColorId::BayerYmcy => Some("YMCY"),
+            SerColorId::BayerMyyc => Some("MYYC"),
+            _ => None,
+        }
+    }
+
+    /// Get the layout of raw frames with the given Bayer pattern.
+    fn from_bayer_pattern(pattern: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|color| color.bayer_pattern() == Some(pattern.trim()))
+    }
+
+    /// Number of samples per pixel.
+    fn planes(self) -> usize {
+        match self {
+            SerColorId::Rgb | SerColorId::Bgr => 3,
+            _ => 1,
+        }
+    }
+}
+
+/// Header of a SER file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerHeader {
+    /// Color layout of the frames.
+    pub color_id: SerColorId,
+    /// Width of the frames, in pixels.
+    pub width: usize,
+    /// Height of the frames, in pixels.
+    pub height: usize,
+    /// Number of significant bits per sample, `1` to `16`.
+    /// Samples of more than 8 bits are stored as 16-bit integers.
+    pub pixel_depth: u32,
+    /// Number of frames.
+    pub frame_count: usize,
+    /// Name of the observer.
+    pub observer: String,
+    /// Name of the camera.
+    pub instrument: String,
+    /// Name of the telescope.
+    pub telescope: String,
+    /// Start time of the capture (UTC).
+    pub start_time: SystemTime,
+}
+
+/// Options for writing SER files.
+///
+/// Unset fields are taken from the metadata of the first frame: the instrument from the camera
+/// name, and the observer, telescope and Bayer pattern from the `OBSERVER`, `TELESCOP` and
+/// `BAYERPAT` extended attributes. The color layout defaults to [`SerColorId::Mono`] for grayscale
+/// frames and [`SerColorId::Rgb`] for color frames.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SerOptions {
+    pub(crate) color_id: Option<SerColorId>,
+    pub(crate) observer: Option<String>,
+    pub(crate) instrument: Option<String>,
+    pub(crate) telescope: Option<String>,
+}
+
+impl SerOptions {
+    /// Create the default SER options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the color layout of grayscale frames, e.g. the Bayer pattern of raw frames.
+    pub fn color_id(mut self, color_id: SerColorId) -> Self {
+        self.color_id = Some(color_id);
+        self
+    }
+
+    /// Set the name of the observer. Names are truncated to 40 bytes.
+    pub fn observer(mut self, observer: &str) -> Self {
+        self.observer = Some(observer.to_owned());
+        self
+    }
+
+    /// Set the name of the camera. Names are truncated to 40 bytes.
+    pub fn instrument(mut self, instrument: &str) -> Self {
+        self.instrument = Some(instrument.to_owned());
+        self
+    }
+
+    /// Set the name of the telescope. Names are truncated to 40 bytes.
+    pub fn telescope(mut self, telescope: &str) -> Self {
+        self.telescope = Some(telescope.to_owned());
+        self
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Convert a timestamp to 100 ns ticks since 0001-01-01, saturating at the bounds of [`i64`].
+fn to_ticks(timestamp: SystemTime) -> i64 {
+    match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(since) => i64::try_from(since.as_nanos() / 100)
+            .map_or(i64::MAX, |ticks| EPOCH_TICKS.saturating_add(ticks)),
+        Err(err) => i64::try_from(err.duration().as_nanos() / 100)
+            .map_or(i64::MIN, |ticks| EPOCH_TICKS.saturating_sub(ticks)),
+    }
+}
+
+/// Convert 100 ns ticks since 0001-01-01 to a timestamp.
+fn from_ticks(ticks: i64) -> SystemTime {
+    let nanos = |ticks: u64| Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+    let since = nanos(ticks.abs_diff(EPOCH_TICKS));
+    if ticks >= EPOCH_TICKS {
+        UNIX_EPOCH + since
+    } else {
+        UNIX_EPOCH.checked_sub(since).unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// Fixed-length, null-padded header field, truncated to a character boundary.
+fn field(value: &str) -> [u8; FIELD_LEN] {
+    let mut field = [0; FIELD_LEN];
+    let mut len = value.len().min(FIELD_LEN);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    field[..len].copy_from_slice(&value.as_bytes()[..len]);
+    field
+}
+
+/// Sample type, color layout and geometry shared by all frames of a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameLayout {
+    sample_type: SampleType,
+    channels: u8,
+    width: usize,
+    height: usize,
+}
+
+impl FrameLayout {
+    fn of(frame: &DynamicSerialImage) -> Self {
+        let channels = match frame {
+            DynamicSerialImage::U8(img) => img.pixel_elems(),
+            DynamicSerialImage::U16(img) => img.pixel_elems(),
+            DynamicSerialImage::F32(img) => img.pixel_elems(),
+        };
+        Self {
+            sample_type: frame.sample_type(),
+            channels,
+            width: frame.width(),
+            height: frame.height(),
+        }
+    }
+}
+
+/// Writer of SER video files.
+///
+/// Frames are appended with [`SerWriter::write_frame`], and the header and the frame timestamps
+/// are written by [`SerWriter::finish`], which must be called to complete the file.
+///
+/// Frames are stored as 8-bit or 16-bit little-endian samples, with the `LittleEndian` field of
+/// the header set to `0` as done by most capture software.
+#[derive(Debug)]
+pub struct SerWriter<W: Write + Seek> {
+    writer: W,
+    options: SerOptions,
+    start: u64,
+    header: Option<(FrameLayout, SerHeader)>,
+    timestamps: Vec<i64>,
+}
+
+impl SerWriter<BufWriter<File>> {
+    /// Create a SER file at `path`. See [`SerWriter::new`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] if the file can not be created.
+    pub fn create(path: &Path, options: &SerOptions) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), options)
+    }
+}
+
+impl<W: Write + Seek> SerWriter<W> {
+    /// Start writing a SER file at the current position of the writer.
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    pub fn new(mut writer: W, options: &SerOptions) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        // The header is written once the number of frames is known
+        writer.write_all(&[0; HEADER_LEN as usize])?;
+        Ok(Self {
+            writer,
+            options: options.clone(),
+            start,
+            header: None,
+            timestamps: Vec::new(),
+        })
+    }
+
+    /// Number of frames written so far.
+    pub fn frame_count(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Append a frame, with the timestamp of its metadata, or the current time if the frame
+    /// has no metadata.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if the frame is not a [`u8`] or [`u16`] grayscale or RGB
+    ///    image, or if its size, sample type or number of channels differs from the first frame.
+    ///  * Any error of the writer.
+    pub fn write_frame(&mut self, frame: &DynamicSerialImage) -> io::Result<()> {
+        let layout = FrameLayout::of(frame);
+        match &self.header {
+            Some((first, _)) if *first != layout => {
+                return Err(invalid_input(
+                    "Frame size, sample type and channels must match the first frame",
+                ))
+            }
+            Some(_) => {}
+            None => self.header = Some((layout, self.first_header(frame, layout)?)),
+        }
+        let meta = frame.get_metadata();
+        let bytes = match frame {
+            DynamicSerialImage::U8(img) => img.to_interleaved(),
+            DynamicSerialImage::U16(img) => img
+                .to_interleaved()
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect(),
+            DynamicSerialImage::F32(_) => {
+                return Err(invalid_input(
+                    "Only u8 and u16 frames can be stored in SER files",
+                ))
+            }
+        };
+        self.writer.write_all(&bytes)?;
+        self.timestamps.push(to_ticks(
+            meta.map(|meta| meta.timestamp)
+                .unwrap_or_else(SystemTime::now),
+        ));
+        Ok(())
+    }
+
+    /// Build the header from the options and the first frame.
+    fn first_header(
+        &self,
+        frame: &DynamicSerialImage,
+        layout: FrameLayout,
+    ) -> io::Result<SerHeader> {
+        if matches!(frame, DynamicSerialImage::F32(_)) {
+            return Err(invalid_input(
+                "Only u8 and u16 frames can be stored in SER files",
+            ));
+        }
+        let meta = frame.get_metadata().unwrap_or_default();
+        let attribute = |key: &str| {
+            meta.get_extended_data()
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+        let color_id = match layout.channels {
+            1 => self
+                .options
+                .color_id
+                .or_else(|| attribute(BAYER_KEY).and_then(|p| SerColorId::from_bayer_pattern(&p)))
+                .unwrap_or(SerColorId::Mono),
+            3 => SerColorId::Rgb,
+            _ => {
+                return Err(invalid_input(
+                    "Only grayscale and RGB frames can be stored in SER files",
+                ))
+            }
+        };
+        if color_id.planes() != layout.channels as usize {
+            return Err(invalid_input("Color layout does not match the frames"));
+        }
+        Ok(SerHeader {
+            color_id,
+            width: layout.width,
+            height: layout.height,
+            pixel_depth: if layout.sample_type == SampleType::U16 {
+                16
+            } else {
+                8
+            },
+            frame_count: 0,
+            observer: self
+                .options
+                .observer
+                .clone()
+                .or_else(|| attribute(OBSERVER_KEY))
+                .unwrap_or_default(),
+            instrument: self
+                .options
+                .instrument
+                .clone()
+                .unwrap_or_else(|| meta.camera_name.clone()),
+            telescope: self
+                .options
+                .telescope
+                .clone()
+                .or_else(|| attribute(TELESCOPE_KEY))
+                .unwrap_or_default(),
+            start_time: frame
+                .get_metadata()
+                .map(|meta| meta.timestamp)
+                .unwrap_or_else(SystemTime::now),
+        })
+    }
+
+    /// Write the frame timestamps and the header, and return the underlying writer.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if no frame was written.
+    ///  * Any error of the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let header = match self.header.take() {
+            Some((_, header)) => header,
+            None => return Err(invalid_input("A SER file must contain at least one frame")),
+        };
+        for ticks in &self.timestamps {
+            self.writer.write_all(&ticks.to_le_bytes())?;
+        }
+        let end = self.writer.stream_position()?;
+
+        let utc = utc_datetime(header.start_time);
+        let offset = Local.offset_from_utc_datetime(&utc.naive_utc()).fix();
+        let utc_ticks = to_ticks(header.start_time);
+        let local_ticks =
+            utc_ticks.saturating_add(i64::from(offset.local_minus_utc()) * 10_000_000);
+        let mut bytes = Vec::with_capacity(HEADER_LEN as usize);
+        bytes.extend_from_slice(FILE_ID);
+        for value in [
+            0,
+            header.color_id.id(),
+            0,
+            header.width as i32,
+            header.height as i32,
+            header.pixel_depth as i32,
+            self.timestamps.len() as i32,
+        ] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [&header.observer, &header.instrument, &header.telescope] {
+            bytes.extend_from_slice(&field(value));
+        }
+        bytes.extend_from_slice(&local_ticks.to_le_bytes());
+        bytes.extend_from_slice(&utc_ticks.to_le_bytes());
+
+        self.writer.seek(SeekFrom::Start(self.start))?;
+        self.writer.write_all(&bytes)?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reader of SER video files, which reads the frames on demand.
+///
+/// The frames are returned as [`u8`] or [`u16`] images, grayscale for monochrome and raw
+/// frames, or RGB. The metadata of each frame holds the instrument as the camera name and the
+/// frame timestamp (or the start time of the capture if the file has no timestamps), with
+/// the observer, the telescope and the Bayer pattern of raw frames in the `OBSERVER`, `TELESCOP`
+/// and `BAYERPAT` extended attributes.
+///
+/// The reader is also an iterator over the remaining frames.
+#[derive(Debug)]
+pub struct SerReader<R: Read + Seek> {
+    reader: R,
+    start: u64,
+    header: SerHeader,
+    frame_len: usize,
+    big_endian: bool,
+    timestamps: Vec<SystemTime>,
+    next: usize,
+}
+
+impl SerReader<BufReader<File>> {
+    /// Open a SER file. See [`SerReader::new`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> SerReader<R> {
+    /// Read the header and the frame timestamps of a SER file starting at the current
+    /// position of the reader.
+    ///
+    /// The `LittleEndian` field of the header is interpreted as done by most software:
+    /// 16-bit samples are little-endian if the field is `0`, and big-endian otherwise.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the data is not a SER file.
+    ///  * Any error of the reader.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        let mut bytes = [0; HEADER_LEN as usize];
+        reader
+            .read_exact(&mut bytes)
+            .map_err(|_| invalid_data("Truncated SER header"))?;
+        if &bytes[..FILE_ID.len()] != FILE_ID {
+            return Err(invalid_data("Not a SER file"));
+        }
+        let int = |idx: usize| {
+            let offset = FILE_ID.len() + 4 * idx;
+            i32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let text = |idx: usize| {
+            let offset = 42 + FIELD_LEN * idx;
+            let field = &bytes[offset..offset + FIELD_LEN];
+            let len = field.iter().position(|&b| b == 0).unwrap_or(FIELD_LEN);
+            String::from_utf8_lossy(&field[..len]).trim_end().to_owned()
+        };
+        let utc_ticks = i64::from_le_bytes(bytes[170..178].try_into().unwrap());
+        let color_id =
+            SerColorId::from_id(int(1)).ok_or_else(|| invalid_data("Invalid SER color ID"))?;
+        let (width, height, pixel_depth, frame_count) = (int(3), int(4), int(5), int(6));
+        if width <= 0 || height <= 0 || frame_count < 0 || !(1..=16).contains(&pixel_depth) {
+            return Err(invalid_data("Invalid SER header"));
+        }
+        let header = SerHeader {
+            color_id,
+            width: width as usize,
+            height: height as usize,
+            pixel_depth: pixel_depth as u32,
+            frame_count: frame_count as usize,
+            observer: text(0),
+            instrument: text(1),
+            telescope: text(2),
+            start_time: from_ticks(utc_ticks),
+        };
+        // The sizes are checked once, so that the offsets of all frames fit in a u64
+        let sample_len = if header.pixel_depth > 8 { 2 } else { 1 };
+        let frame_len = header
+            .width
+            .checked_mul(header.height)
+            .and_then(|x| x.checked_mul(header.color_id.planes() * sample_len))
+            .ok_or_else(|| invalid_data("Invalid SER header"))?;
+        let trailer = u64::try_from(frame_len)
+            .ok()
+            .and_then(|x| x.checked_mul(header.frame_count as u64))
+            .and_then(|x| x.checked_add(start + HEADER_LEN))
+            .ok_or_else(|| invalid_data("Invalid SER header"))?;
+        let mut ser = Self {
+            reader,
+            start,
+            header,
+            frame_len,
+            big_endian: int(2) != 0,
+            timestamps: Vec::new(),
+            next: 0,
+        };
+        // The timestamps are optional
+        let len = ser.reader.seek(SeekFrom::End(0))?;
+        if len < trailer {
+            return Err(invalid_data("Truncated SER file"));
+        }
+        if len - trailer >= 8 * ser.header.frame_count as u64 {
+            ser.reader.seek(SeekFrom::Start(trailer))?;
+            let mut bytes = vec![0; 8 * ser.header.frame_count];
+            ser.reader.read_exact(&mut bytes)?;
+            ser.timestamps = bytes
+                .chunks_exact(8)
+                .map(|b| from_ticks(i64::from_le_bytes(b.try_into().unwrap())))
+                .collect();
+        }
+        Ok(ser)
+    }
+
+    /// Header of the file.
+    pub fn header(&self) -> &SerHeader {
+        &self.header
+    }
+
+    /// Number of frames of the file.
+    pub fn len(&self) -> usize {
+        self.header.frame_count
+    }
+
+    /// Check if the file has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.header.frame_count == 0
+    }
+
+    /// Timestamps of the frames (UTC), empty if the file has no timestamps.
+    pub fn timestamps(&self) -> &[SystemTime] {
+        &self.timestamps
+    }
+
+    /// Position of a frame in the reader.
+    fn frame_offset(&self, idx: usize) -> u64 {
+        self.start + HEADER_LEN + idx as u64 * self.frame_len as u64
+    }
+
+    /// Read the frame at `idx`.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if `idx` is out of range.
+    ///  * Any error of the reader.
+    pub fn read_frame(&mut self, idx: usize) -> io::Result<DynamicSerialImage> {
+        if idx >= self.header.frame_count {
+            return Err(invalid_input("Frame index out of range"));
+        }
+        self.reader.seek(SeekFrom::Start(self.frame_offset(idx)))?;
+        let mut bytes = vec![0; self.frame_len];
+        self.reader.read_exact(&mut bytes)?;
+
+        let header = &self.header;
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = header.instrument.clone();
+        meta.timestamp = self
+            .timestamps
+            .get(idx)
+            .copied()
+            .unwrap_or(header.start_time);
+        if !header.observer.is_empty() {
+            meta.add_extended_attrib(OBSERVER_KEY, &header.observer);
+        }
+        if !header.telescope.is_empty() {
+            meta.add_extended_attrib(TELESCOPE_KEY, &header.telescope);
+        }
+        if let Some(pattern) = header.color_id.bayer_pattern() {
+            meta.add_extended_attrib(BAYER_KEY, pattern);
+        }
+
+        let (width, height) = (header.width, header.height);
+        let bgr = header.color_id == SerColorId::Bgr;
+        if header.pixel_depth > 8 {
+            let data = bytes
+                .chunks_exact(2)
+                .map(|b| {
+                    if self.big_endian {
+                        u16::from_be_bytes([b[0], b[1]])
+                    } else {
+                        u16::from_le_bytes([b[0], b[1]])
+                    }
+                })
+                .collect();
+            Ok(frame(width, height, data, bgr, meta)?.into())
+        } else {
+            Ok(frame(width, height, bytes, bgr, meta)?.into())
+        }
+    }
+}
+
+/// Build a frame from interleaved samples.
+fn frame<T: PixelArithmetic>(
+    width: usize,
+    height: usize,
+    mut data: Vec<T>,
+    bgr: bool,
+    meta: ImageMetaData,
+) -> io::Result<SerialImageBuffer<T>> {
+    if bgr {
+        for pixel in data.chunks_exact_mut(3) {
+            pixel.swap(0, 2);
+        }
+    }
+    let mut img = SerialImageBuffer::from_vec(width, height, data).map_err(invalid_data)?;
+    img.set_metadata(Some(meta));
+    Ok(img)
+}
+
+impl<R: Read + Seek> Iterator for SerReader<R> {
+    type Item = io::Result<DynamicSerialImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.header.frame_count {
+            return None;
+        }
+        self.next += 1;
+        Some(self.read_frame(self.next - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.header.frame_count - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_ser() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let frames: Vec<DynamicSerialImage> = (0..3u16)
+            .map(|idx| {
+                let mut meta = ImageMetaData::default();
+                meta.camera_name = "Camera".to_owned();
+                meta.timestamp = start + Duration::from_millis(20 * idx as u64);
+                meta.add_extended_attrib(OBSERVER_KEY, "Observer");
+                meta.add_extended_attrib(BAYER_KEY, "GRBG");
+                let mut img =
+                    SerialImageBuffer::from_vec(3, 2, (0..6).map(|x| x * 1000 + idx).collect())
+                        .unwrap();
+                img.set_metadata(Some(meta));
+                img.into()
+            })
+            .collect();
+        let mut writer = SerWriter::new(Cursor::new(Vec::new()), &SerOptions::new()).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let rgb = DynamicSerialImage::from_vec_u8(1, 1, vec![1, 2, 3]).unwrap();
+        assert!(writer.write_frame(&rgb).is_err());
+        let bytes = writer.finish().unwrap().into_inner();
+        assert_eq!(bytes.len(), 178 + 3 * 12 + 3 * 8);
+
+        let mut reader = SerReader::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.len(), 3);
+        assert_eq!(reader.header().color_id, SerColorId::BayerGrbg);
+        assert_eq!(reader.header().start_time, start);
+        assert_eq!(&reader.read_frame(2).unwrap(), &frames[2]);
+        assert!(reader.read_frame(3).is_err());
+        let read = reader.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(read, frames);
+
+        // Color frames, with the header fields from the options
+        let mut frame = DynamicSerialImage::from_vec_u8(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        let options = SerOptions::new()
+            .telescope("Telescope")
+            .instrument("A very long camera name that does not fit");
+        let mut writer = SerWriter::new(Cursor::new(Vec::new()), &options).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let mut bytes = writer.finish().unwrap().into_inner();
+        let mut reader = SerReader::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.header().instrument.len(), 40);
+        let read = reader.next().unwrap().unwrap();
+        let meta = read.get_metadata().unwrap();
+        assert_eq!(meta.get_extended_data()[0].1, "Telescope");
+        frame.set_metadata(meta);
+        assert_eq!(read, frame);
+
+        // BGR frames without timestamps
+        bytes[18] = 101;
+        bytes.truncate(bytes.len() - 8);
+        let read = SerReader::new(Cursor::new(&bytes))
+            .unwrap()
+            .read_frame(0)
+            .unwrap();
+        assert_eq!(
+            read.as_u8().unwrap().to_interleaved(),
+            vec![3, 2, 1, 6, 5, 4]
+        );
+
+        // Frame and file sizes that overflow
+        let mut header = bytes[..178].to_vec();
+        header[26..34].copy_from_slice(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f]);
+        assert!(SerReader::new(Cursor::new(&header)).is_err());
+        header[18] = 0;
+        header[26..42].copy_from_slice(&[
+            0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0, 16, 0, 0, 0, 0xff, 0xff, 0xff, 0x7f,
+        ]);
+        assert!(SerReader::new(Cursor::new(&header)).is_err());
+
+        // Floating point frames
+        let float =
+            DynamicSerialImage::from(SerialImageBuffer::from_vec(1, 1, vec![0.5f32]).unwrap());
+        let mut writer = SerWriter::new(Cursor::new(Vec::new()), &SerOptions::new()).unwrap();
+        let err = writer.write_frame(&float).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        writer.write_frame(&frames[0]).unwrap();
+        let err = writer.write_frame(&float).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Timestamps out of range saturate
+        let far = Duration::from_secs(1 << 60);
+        assert_eq!(to_ticks(UNIX_EPOCH + far), i64::MAX);
+        assert_eq!(to_ticks(UNIX_EPOCH - far), i64::MIN);
+        let mut frame = frames[0].clone();
+        let mut meta = frame.get_metadata().unwrap_or_default();
+        meta.timestamp = UNIX_EPOCH + far;
+        frame.set_metadata(meta);
+        let mut writer = SerWriter::new(Cursor::new(Vec::new()), &SerOptions::new()).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.finish().unwrap();
+    }
+}
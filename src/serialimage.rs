@@ -1,28 +1,18 @@
 #![warn(missing_docs)]
 
 use image::{imageops::FilterType, DynamicImage, ImageBuffer, Luma, LumaA, Rgb};
-use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "fitsio")]
-use chrono::DateTime;
+use fitsio::errors::Error as FitsError;
 #[cfg(feature = "fitsio")]
-use fitsio::{
-    errors::Error as FitsError,
-    images::{ImageDescription, ImageType, WriteImage},
-    FitsFile,
-};
-#[cfg(feature = "fitsio")]
-use std::{
-    fs::remove_file,
-    io,
-    path::{Path, PathBuf},
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::path::{Path, PathBuf};
 
 pub use image::Primitive;
 
 use super::ImageMetaData;
+use crate::parallel::{deinterleave, interleave, ParallelSample};
+use crate::LumaWeights;
 
 /// Optional vector type alias.
 pub type OptionVec<T> = Option<Vec<T>>;
@@ -35,16 +25,42 @@ pub type TupleOptionVec<T> = (
     Option<Vec<T>>,
 );
 
+/// Image channels of a [`SerialImageBuffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Channel {
+    /// Luminosity channel of a grayscale image.
+    Luma,
+    /// Red channel of a color image.
+    Red,
+    /// Green channel of a color image.
+    Green,
+    /// Blue channel of a color image.
+    Blue,
+    /// Alpha (transparency) channel.
+    Alpha,
+}
+
+/// Get the channels of an image with the given number of pixel elements, in pixel element order.
+pub(crate) fn channels_of(pixel_elems: u8) -> &'static [Channel] {
+    match pixel_elems {
+        1 => &[Channel::Luma],
+        2 => &[Channel::Luma, Channel::Alpha],
+        3 => &[Channel::Red, Channel::Green, Channel::Blue],
+        4 => &[Channel::Red, Channel::Green, Channel::Blue, Channel::Alpha],
+        _ => &[],
+    }
+}
+
 /// Valid types for the serial image data structure: [`u8`], [`u16`], [`f32`].
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-struct SerialImageInternal<T: Primitive> {
-    luma: OptionVec<T>,
-    red: OptionVec<T>,
-    green: OptionVec<T>,
-    blue: OptionVec<T>,
-    alpha: OptionVec<T>,
-    pixel_elems: u8,
+pub(crate) struct SerialImageInternal<T: Primitive> {
+    pub(crate) luma: OptionVec<T>,
+    pub(crate) red: OptionVec<T>,
+    pub(crate) green: OptionVec<T>,
+    pub(crate) blue: OptionVec<T>,
+    pub(crate) alpha: OptionVec<T>,
+    pub(crate) pixel_elems: u8,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -52,13 +68,13 @@ struct SerialImageInternal<T: Primitive> {
 ///
 /// Image data is organized in channels. For example, a grayscale image stores data in the luma channel, while a color image stores data in the red, green and blue channels. Transparency is stored in the alpha channel.
 pub struct SerialImageBuffer<T: Primitive> {
-    meta: Option<ImageMetaData>,
-    data: SerialImageInternal<T>,
-    width: usize,
-    height: usize,
+    pub(crate) meta: Option<ImageMetaData>,
+    pub(crate) data: SerialImageInternal<T>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
 }
 
-impl<T: Primitive> SerialImageBuffer<T> {
+impl<T: Primitive + ParallelSample> SerialImageBuffer<T> {
     /// Create a new serializable image buffer from vector data.
     ///
     /// # Arguments
@@ -110,42 +126,72 @@ impl<T: Primitive> SerialImageBuffer<T> {
 
     fn from_vec_unsafe(size: usize, data: Vec<T>, elems: u8) -> TupleOptionVec<T> {
         if elems == 1 {
-            (Some(data), None, None, None, None)
-        } else if elems == 2 {
-            let mut luma = Vec::with_capacity(size);
-            let mut alpha = Vec::with_capacity(size);
-            for i in 0..size {
-                luma.push(data[i * 2]);
-                alpha.push(data[i * 2 + 1]);
-            }
-            return (Some(luma), None, None, None, Some(alpha));
-        } else if elems == 3 {
-            let mut red = Vec::with_capacity(size);
-            let mut green = Vec::with_capacity(size);
-            let mut blue = Vec::with_capacity(size);
-            for i in 0..size {
-                red.push(data[i * 3]);
-                green.push(data[i * 3 + 1]);
-                blue.push(data[i * 3 + 2]);
-            }
-            return (None, Some(red), Some(green), Some(blue), None);
-        } else if elems == 4 {
-            let mut red = Vec::with_capacity(size);
-            let mut green = Vec::with_capacity(size);
-            let mut blue = Vec::with_capacity(size);
-            let mut alpha = Vec::with_capacity(size);
-            for i in 0..size {
-                red.push(data[i * 4]);
-                green.push(data[i * 4 + 1]);
-                blue.push(data[i * 4 + 2]);
-                alpha.push(data[i * 4 + 3]);
-            }
-            return (None, Some(red), Some(green), Some(blue), Some(alpha));
-        } else {
+            debug_assert_eq!(data.len(), size);
+            return (Some(data), None, None, None, None);
+        }
+        Self::from_slice_unsafe(size, &data, elems)
+    }
+
+    /// De-interleave borrowed pixel data straight into the channel vectors, without
+    /// an intermediate copy of the source.
+    fn from_slice_unsafe(size: usize, data: &[T], elems: u8) -> TupleOptionVec<T> {
+        debug_assert_eq!(data.len(), size * elems as usize);
+        if elems == 1 {
+            return (Some(data.to_vec()), None, None, None, None);
+        }
+        let mut planes = deinterleave(data, elems as usize).into_iter();
+        match elems {
+            2 => (planes.next(), None, None, None, planes.next()),
+            3 => (None, planes.next(), planes.next(), planes.next(), None),
+            4 => (
+                None,
+                planes.next(),
+                planes.next(),
+                planes.next(),
+                planes.next(),
+            ),
+            _ => panic!("Invalid number of elements"),
+        }
+    }
+
+    /// Consume the image buffer and return a contiguous vector.
+    ///
+    /// Note:
+    ///  - If the image is grayscale, the vector contains the luma channel data.
+    ///  - If the image is grayscale with alpha channel, odd pixels are luminoisty and even pixels are alpha.
+    ///  - If the image is RGB, the first element of the vector is red, the second element is green and the third element is blue and so on.
+    ///  - If the image is RGB with alpha channel, the first element of the vector is red, the second element is green, the third element is blue and the fourth element is alpha and so on.
+    pub fn into_vec(self) -> Vec<T> {
+        if self.width * self.height == 0 {
+            return Vec::new();
+        } else if self.data.pixel_elems == 1 {
+            return self.data.luma.unwrap();
+        }
+        self.to_interleaved()
+    }
+
+    /// Return the image data as a contiguous vector, in the order of [`SerialImageBuffer::into_vec`],
+    /// without cloning the image.
+    pub(crate) fn to_interleaved(&self) -> Vec<T> {
+        if self.width * self.height == 0 {
+            return Vec::new();
+        }
+        let planes: Vec<&[T]> = self
+            .channels()
+            .iter()
+            .map(|channel| self.channel_data(*channel).as_ref().unwrap().as_slice())
+            .collect();
+        if planes.len() != self.data.pixel_elems as usize {
             panic!("Invalid number of elements");
         }
+        if let [luma] = planes[..] {
+            return luma.to_vec();
+        }
+        interleave(&planes)
     }
+}
 
+impl<T: Primitive> SerialImageBuffer<T> {
     /// Get the image metadata.
     pub fn get_metadata(&self) -> Option<ImageMetaData> {
         self.meta.clone()
@@ -234,201 +280,87 @@ impl<T: Primitive> SerialImageBuffer<T> {
         self.data.pixel_elems == 3
     }
 
-    /// Consume the image buffer and return a contiguous vector.
+    /// Get the channels present in the image, in pixel element order.
     ///
     /// Note:
-    ///  - If the image is grayscale, the vector contains the luma channel data.
-    ///  - If the image is grayscale with alpha channel, odd pixels are luminoisty and even pixels are alpha.
-    ///  - If the image is RGB, the first element of the vector is red, the second element is green and the third element is blue and so on.
-    ///  - If the image is RGB with alpha channel, the first element of the vector is red, the second element is green, the third element is blue and the fourth element is alpha and so on.
-    pub fn into_vec(self) -> Vec<T> {
-        let mut data =
-            Vec::with_capacity(self.width * self.height * self.data.pixel_elems as usize);
+    ///  - Grayscale images contain [`Channel::Luma`], followed by [`Channel::Alpha`] if the image has an alpha channel.
+    ///  - Color images contain [`Channel::Red`], [`Channel::Green`] and [`Channel::Blue`], followed by [`Channel::Alpha`] if the image has an alpha channel.
+    pub fn channels(&self) -> &'static [Channel] {
+        channels_of(self.data.pixel_elems)
+    }
 
-        if self.width * self.height == 0 {
-            return Vec::new();
-        } else if self.data.pixel_elems == 1 {
-            return self.data.luma.unwrap();
-        } else if self.data.pixel_elems == 2 {
-            let luma = self.data.luma.unwrap();
-            let alpha = self.data.alpha.unwrap();
-            for i in 0..self.width * self.height {
-                data.push(luma[i]);
-                data.push(alpha[i]);
-            }
-        } else if self.data.pixel_elems == 3 {
-            let red = self.data.red.unwrap();
-            let green = self.data.green.unwrap();
-            let blue = self.data.blue.unwrap();
-            for i in 0..self.width * self.height {
-                data.push(red[i]);
-                data.push(green[i]);
-                data.push(blue[i]);
-            }
-        } else if self.data.pixel_elems == 4 {
-            let red = self.data.red.unwrap();
-            let green = self.data.green.unwrap();
-            let blue = self.data.blue.unwrap();
-            let alpha = self.data.alpha.unwrap();
-            for i in 0..self.width * self.height {
-                data.push(red[i]);
-                data.push(green[i]);
-                data.push(blue[i]);
-                data.push(alpha[i]);
-            }
-        } else {
-            panic!("Invalid number of elements");
-        }
+    /// Get the data of a channel, or `None` if the channel is not present in the image.
+    pub fn channel(&self, channel: Channel) -> Option<&Vec<T>> {
+        self.channel_data(channel).as_ref()
+    }
 
-        data
+    /// Get a mutable reference to the data of a channel, or `None` if the channel is not present in the image.
+    pub fn channel_mut(&mut self, channel: Channel) -> Option<&mut Vec<T>> {
+        self.channel_data_mut(channel).as_mut()
     }
-}
 
-#[cfg_attr(docsrs, doc(cfg(feature = "fitsio")))]
-#[cfg(feature = "fitsio")]
-impl<T: Primitive + WriteImage> SerialImageBuffer<T> {
-    /// Save the image data to a FITS file.
-    ///
-    /// # Arguments
-    ///  * `dir_prefix` - The directory where the file will be saved.
-    ///  * `file_prefix` - The prefix of the file name. The file name will be of the form `{file_prefix}_{timestamp}.fits`.
-    ///  * `progname` - The name of the program that generated the image.
-    ///  * `compress` - Whether to compress the FITS file.
-    ///  * `overwrite` - Whether to overwrite the file if it already exists.
-    ///  * `image_type` - The type of the image data (e.g. [`ImageType::UnsignedByte`])
-    ///
-    /// # Errors
-    ///  * [`fitsio::errors::Error`] with the error description.
-    fn savefits_generic(
-        &self,
-        dir_prefix: &Path,
-        file_prefix: &str,
-        progname: Option<&str>,
-        compress: bool,
-        overwrite: bool,
-        image_type: ImageType,
-    ) -> Result<PathBuf, FitsError> {
-        if !dir_prefix.exists() {
-            return Err(FitsError::Io(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Directory {:?} does not exist", dir_prefix),
-            )));
-        }
-        let meta = self.get_metadata();
-        let timestamp;
-        let cameraname;
-        if let Some(metadata) = &meta {
-            timestamp = metadata
-                .timestamp
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or(Duration::from_secs(0))
-                .as_millis();
-            cameraname = metadata.camera_name.clone();
-        } else {
-            timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or(Duration::from_secs(0))
-                .as_millis();
-            cameraname = "unknown".to_owned();
-        }
-        let ts = timestamp as u64;
-        // Create a NaiveDateTime from the timestamp
-        let timestamp = DateTime::from_timestamp_millis(timestamp as i64).ok_or(
-            FitsError::Message("Could not convert timestamp to NaiveDateTime".to_owned()),
-        )?;
-
-        let timestamp = timestamp.format("%Y%m%d_%H%M%S");
-
-        let file_prefix = if file_prefix.trim().is_empty() {
-            cameraname.clone()
-        } else {
-            file_prefix.to_owned()
-        };
+    pub(crate) fn channel_data(&self, channel: Channel) -> &OptionVec<T> {
+        match channel {
+            Channel::Luma => &self.data.luma,
+            Channel::Red => &self.data.red,
+            Channel::Green => &self.data.green,
+            Channel::Blue => &self.data.blue,
+            Channel::Alpha => &self.data.alpha,
+        }
+    }
 
-        let fpath = dir_prefix.join(Path::new(&format!("{}_{}.fits", file_prefix, timestamp)));
-
-        if fpath.exists() {
-            if !overwrite {
-                return Err(FitsError::Io(io::Error::new(
-                    io::ErrorKind::AlreadyExists,
-                    format!("File {:?} already exists", fpath),
-                )));
-            } else {
-                let res = remove_file(fpath.clone());
-                if let Err(msg) = res {
-                    return Err(FitsError::Io(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Could not remove file {:?}: {}", fpath, msg),
-                    )));
-                }
-            }
+    pub(crate) fn channel_data_mut(&mut self, channel: Channel) -> &mut OptionVec<T> {
+        match channel {
+            Channel::Luma => &mut self.data.luma,
+            Channel::Red => &mut self.data.red,
+            Channel::Green => &mut self.data.green,
+            Channel::Blue => &mut self.data.blue,
+            Channel::Alpha => &mut self.data.alpha,
         }
-        let width = self.width();
-        let height = self.height();
-        let imgsize = [height, width];
-        let data_type = image_type;
+    }
 
-        let img_desc = ImageDescription {
-            data_type,
-            dimensions: &imgsize,
+    /// Create an image from planar channel data, in pixel element order
+    /// (see [`SerialImageBuffer::from_vec`] for the channel order).
+    pub(crate) fn from_planes(
+        width: usize,
+        height: usize,
+        mut planes: Vec<Vec<T>>,
+        meta: Option<ImageMetaData>,
+    ) -> Result<Self, &'static str> {
+        if width * height == 0 {
+            return Err("Width and height must be greater than zero");
+        }
+        if planes.is_empty() || planes.len() > 4 {
+            return Err("Invalid number of pixel elements");
+        }
+        if planes.iter().any(|p| p.len() != width * height) {
+            return Err("Length of channel data must be equal to width * height");
+        }
+        let pixel_elems = planes.len() as u8;
+        let alpha = if pixel_elems % 2 == 0 {
+            planes.pop()
+        } else {
+            None
         };
-
-        let path = Path::new(dir_prefix).join(Path::new(&format!(
-            "{}_{}.fits{}",
-            file_prefix,
-            timestamp,
-            if compress { "[compress]" } else { "" }
-        )));
-
-        let mut fptr = FitsFile::create(path)
-            .with_custom_primary(&img_desc)
-            .open()?;
-        let hdu = fptr.primary_hdu()?;
-        let channels;
-        if self.is_luma() {
-            hdu.write_image(&mut fptr, self.get_luma().unwrap())?;
-            hdu.write_key(&mut fptr, "CHANNELS", 1)?;
-            channels = 1;
-        } else if self.is_rgb() {
-            hdu.write_image(&mut fptr, self.get_red().unwrap())?;
-            let ghdu = fptr.create_image("GREEN", &img_desc)?;
-            ghdu.write_image(&mut fptr, self.get_green().unwrap())?;
-            let bhdu = fptr.create_image("BLUE", &img_desc)?;
-            bhdu.write_image(&mut fptr, self.get_blue().unwrap())?;
-            hdu.write_key(&mut fptr, "CHANNELS", 3)?;
-            channels = 3;
+        let mut planes = planes.into_iter();
+        let (luma, red, green, blue) = if pixel_elems < 3 {
+            (planes.next(), None, None, None)
         } else {
-            return Err(FitsError::Message(format!(
-                "Unsupported image type {:?}",
-                data_type
-            )));
-        }
-        if let Some(alpha) = self.get_alpha() {
-            let ahdu = fptr.create_image("ALPHA", &img_desc)?;
-            ahdu.write_image(&mut fptr, alpha)?;
-            hdu.write_key(&mut fptr, "CHANNELS", channels + 1)?;
-        }
-
-        hdu.write_key(&mut fptr, "PROGRAM", progname.unwrap_or("unknown"))?;
-        hdu.write_key(&mut fptr, "CAMERA", cameraname.as_str())?;
-        hdu.write_key(&mut fptr, "TIMESTAMP", ts)?;
-        if let Some(meta) = meta {
-            hdu.write_key(&mut fptr, "TEMPERATURE", meta.temperature)?;
-            hdu.write_key(&mut fptr, "EXPOSURE_US", meta.exposure.as_micros() as u64)?;
-            hdu.write_key(&mut fptr, "ORIGIN_X", meta.img_left)?;
-            hdu.write_key(&mut fptr, "ORIGIN_Y", meta.img_top)?;
-            hdu.write_key(&mut fptr, "BIN_X", meta.bin_x)?;
-            hdu.write_key(&mut fptr, "BIN_Y", meta.bin_y)?;
-            hdu.write_key(&mut fptr, "GAIN", meta.gain)?;
-            hdu.write_key(&mut fptr, "OFFSET", meta.offset)?;
-            hdu.write_key(&mut fptr, "GAIN_MIN", meta.min_gain)?;
-            hdu.write_key(&mut fptr, "GAIN_MAX", meta.max_gain)?;
-            for obj in meta.get_extended_data().iter() {
-                hdu.write_key(&mut fptr, &obj.0, obj.1.as_str())?;
-            }
-        }
-
-        Ok(fpath)
+            (None, planes.next(), planes.next(), planes.next())
+        };
+        Ok(Self {
+            meta,
+            data: SerialImageInternal {
+                luma,
+                red,
+                green,
+                blue,
+                alpha,
+                pixel_elems,
+            },
+            width,
+            height,
+        })
     }
 }
 
@@ -500,62 +432,24 @@ impl SerialImageBuffer<u8> {
         })
     }
 
-    /// Convert the image to grayscale, while discarding the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale, while discarding the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// The samples are promoted to 16 bits by a shift of 8 bits, i.e. `255` becomes `65280`.
+    /// See [`SerialImageBuffer::into_luma_with`] for other weights and output types, and full-scale
+    /// (`255` to `65535`) promotion.
     pub fn into_luma(&self) -> SerialImageBuffer<u16> {
-        let luma;
-        if self.is_luma() {
-            let sluma = self.data.luma.as_ref().unwrap();
-            luma = sluma.iter().map(|x| ((*x as u16) << 8)).collect();
-        } else if self.is_rgb() {
-            let sred = self.data.red.as_ref().unwrap();
-            let sgreen = self.data.green.as_ref().unwrap();
-            let sblue = self.data.blue.as_ref().unwrap();
-            luma = sred
-                .iter()
-                .zip(sgreen.iter())
-                .zip(sblue.iter())
-                .map(|((r, g), b)| {
-                    R_LUT_U16[((*r as u16) << 8) as usize]
-                        + G_LUT_U16[((*g as u16) << 8) as usize]
-                        + B_LUT_U16[((*b as u16) << 8) as usize]
-                })
-                .collect();
-        } else {
-            panic!("Cannot convert image");
-        }
-
-        SerialImageBuffer::<u16>::new(
-            self.meta.clone(),
-            Some(luma),
-            None,
-            None,
-            None,
-            None,
-            self.width,
-            self.height,
-        )
-        .unwrap()
+        self.luma_scaled(LumaWeights::Rec709, false, 256.)
+            .expect("Cannot convert image")
     }
 
-    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// The samples are promoted to 16 bits by a shift of 8 bits, i.e. `255` becomes `65280`.
+    /// See [`SerialImageBuffer::into_luma_alpha_with`] for other weights and output types, and full-scale
+    /// (`255` to `65535`) promotion.
     pub fn into_luma_alpha(&self) -> SerialImageBuffer<u16> {
-        let img = self.into_luma();
-        let alpha = self
-            .data
-            .alpha
-            .as_ref()
-            .map(|x| x.iter().map(|x| ((*x as u16) << 8)).collect());
-        SerialImageBuffer::<u16>::new(
-            img.meta,
-            img.data.luma,
-            None,
-            None,
-            None,
-            alpha,
-            self.width,
-            self.height,
-        )
-        .unwrap()
+        self.luma_scaled(LumaWeights::Rec709, true, 256.)
+            .expect("Cannot convert image")
     }
 
     /// Resize this image using the specified filter algorithm.
@@ -579,7 +473,7 @@ impl SerialImageBuffer<u8> {
     ///  * `dir_prefix` - The directory where the file will be saved.
     ///  * `file_prefix` - The prefix of the file name. The file name will be of the form `{file_prefix}_{timestamp}.fits`.
     ///  * `progname` - The name of the program that generated the image.
-    ///  * `compress` - Whether to compress the FITS file.
+    ///  * `compress` - Whether to compress the FITS file, using [`FitsCompression::Rice`](crate::FitsCompression::Rice).
     ///  * `overwrite` - Whether to overwrite the file if it already exists.
     ///
     /// # Errors
@@ -592,14 +486,7 @@ impl SerialImageBuffer<u8> {
         compress: bool,
         overwrite: bool,
     ) -> Result<PathBuf, FitsError> {
-        self.savefits_generic(
-            dir_prefix,
-            file_prefix,
-            progname,
-            compress,
-            overwrite,
-            ImageType::UnsignedByte,
-        )
+        self.savefits_generic(dir_prefix, file_prefix, progname, compress, overwrite)
     }
 }
 
@@ -671,53 +558,20 @@ impl SerialImageBuffer<u16> {
         })
     }
 
-    /// Convert the image to grayscale, while discarding the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale, while discarding the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// See [`SerialImageBuffer::into_luma_with`] for other weights and output types.
     pub fn into_luma(&self) -> SerialImageBuffer<u16> {
-        let luma;
-        if self.is_luma() {
-            luma = self.data.luma.as_ref().unwrap().clone();
-        } else if self.is_rgb() {
-            let sred = self.data.red.as_ref().unwrap();
-            let sgreen = self.data.green.as_ref().unwrap();
-            let sblue = self.data.blue.as_ref().unwrap();
-            luma = sred
-                .iter()
-                .zip(sgreen.iter())
-                .zip(sblue.iter())
-                .map(|((r, g), b)| {
-                    R_LUT_U16[*r as usize] + G_LUT_U16[*g as usize] + B_LUT_U16[*b as usize]
-                })
-                .collect();
-        } else {
-            panic!("Cannot convert image");
-        }
-        SerialImageBuffer::<u16>::new(
-            self.meta.clone(),
-            Some(luma),
-            None,
-            None,
-            None,
-            None,
-            self.width,
-            self.height,
-        )
-        .unwrap()
-    }
-
-    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+        self.luma_as(LumaWeights::Rec709, false)
+            .expect("Cannot convert image")
+    }
+
+    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// See [`SerialImageBuffer::into_luma_alpha_with`] for other weights and output types.
     pub fn into_luma_alpha(&self) -> SerialImageBuffer<u16> {
-        let img = self.into_luma();
-        SerialImageBuffer::<u16>::new(
-            img.meta,
-            img.data.luma,
-            None,
-            None,
-            None,
-            self.data.alpha.clone(),
-            self.width,
-            self.height,
-        )
-        .unwrap()
+        self.luma_as(LumaWeights::Rec709, true)
+            .expect("Cannot convert image")
     }
 
     /// Resize this image using the specified filter algorithm.
@@ -741,7 +595,7 @@ impl SerialImageBuffer<u16> {
     ///  * `dir_prefix` - The directory where the file will be saved.
     ///  * `file_prefix` - The prefix of the file name. The file name will be of the form `{file_prefix}_{timestamp}.fits`.
     ///  * `progname` - The name of the program that generated the image.
-    ///  * `compress` - Whether to compress the FITS file.
+    ///  * `compress` - Whether to compress the FITS file, using [`FitsCompression::Rice`](crate::FitsCompression::Rice).
     ///  * `overwrite` - Whether to overwrite the file if it already exists.
     ///
     /// # Errors
@@ -754,14 +608,7 @@ impl SerialImageBuffer<u16> {
         compress: bool,
         overwrite: bool,
     ) -> Result<PathBuf, FitsError> {
-        self.savefits_generic(
-            dir_prefix,
-            file_prefix,
-            progname,
-            compress,
-            overwrite,
-            ImageType::UnsignedShort,
-        )
+        self.savefits_generic(dir_prefix, file_prefix, progname, compress, overwrite)
     }
 }
 
@@ -818,60 +665,20 @@ impl SerialImageBuffer<f32> {
         })
     }
 
-    /// Convert the image to grayscale, discarding the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+    /// Convert the image to grayscale, while discarding the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// See [`SerialImageBuffer::into_luma_with`] for other weights and output types.
     pub fn into_luma(&self) -> SerialImageBuffer<u16> {
-        let luma;
-        if self.is_luma() {
-            let sluma = self.data.luma.as_ref().unwrap();
-            luma = sluma
-                .iter()
-                .map(|x| (*x * u16::MAX as f32).round() as u16)
-                .collect();
-        } else if self.is_rgb() {
-            let sred = self.data.red.as_ref().unwrap();
-            let sgreen = self.data.green.as_ref().unwrap();
-            let sblue = self.data.blue.as_ref().unwrap();
-            luma = sred
-                .iter()
-                .zip(sgreen.iter())
-                .zip(sblue.iter())
-                .map(|((r, g), b)| (0.2162 * *r + 0.7152 * *g + 0.0722 * *b).round() as u16)
-                .collect();
-        } else {
-            panic!("Cannot convert image");
-        }
-        SerialImageBuffer::<u16>::new(
-            self.meta.clone(),
-            Some(luma),
-            None,
-            None,
-            None,
-            None,
-            self.width,
-            self.height,
-        )
-        .unwrap()
-    }
-
-    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2162 * red + 0.7152 * green + 0.0722 * blue` for converting RGB to grayscale (see [here](https://stackoverflow.com/a/56678483)).
+        self.luma_as(LumaWeights::Rec709, false)
+            .expect("Cannot convert image")
+    }
+
+    /// Convert the image to grayscale, while preserving the alpha channel. The transformation used is `0.2126 * red + 0.7152 * green + 0.0722 * blue`
+    /// (ITU-R BT.709, see [here](https://stackoverflow.com/a/56678483)) on samples normalized to full scale.
+    /// See [`SerialImageBuffer::into_luma_alpha_with`] for other weights and output types.
     pub fn into_luma_alpha(&self) -> SerialImageBuffer<u16> {
-        let img = self.into_luma();
-        let alpha = self.data.alpha.as_ref().map(|x| {
-            x.iter()
-                .map(|x| (*x * u16::MAX as f32).round() as u16)
-                .collect()
-        });
-        SerialImageBuffer::<u16>::new(
-            img.meta,
-            img.data.luma,
-            None,
-            None,
-            None,
-            alpha,
-            self.width,
-            self.height,
-        )
-        .unwrap()
+        self.luma_as(LumaWeights::Rec709, true)
+            .expect("Cannot convert image")
     }
 
     /// Resize this image using the specified filter algorithm.
@@ -895,7 +702,7 @@ impl SerialImageBuffer<f32> {
     ///  * `dir_prefix` - The directory where the file will be saved.
     ///  * `file_prefix` - The prefix of the file name. The file name will be of the form `{file_prefix}_{timestamp}.fits`.
     ///  * `progname` - The name of the program that generated the image.
-    ///  * `compress` - Whether to compress the FITS file.
+    ///  * `compress` - Whether to compress the FITS file, using [`FitsCompression::Rice`](crate::FitsCompression::Rice).
     ///  * `overwrite` - Whether to overwrite the file if it already exists.
     ///
     /// # Errors
@@ -908,14 +715,7 @@ impl SerialImageBuffer<f32> {
         compress: bool,
         overwrite: bool,
     ) -> Result<PathBuf, FitsError> {
-        self.savefits_generic(
-            dir_prefix,
-            file_prefix,
-            progname,
-            compress,
-            overwrite,
-            ImageType::Float,
-        )
+        self.savefits_generic(dir_prefix, file_prefix, progname, compress, overwrite)
     }
 }
 
@@ -1221,15 +1021,15 @@ impl TryFrom<&DynamicImage> for SerialImageBuffer<u8> {
             }
             DynamicImage::ImageLumaA8(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             DynamicImage::ImageRgb8(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             DynamicImage::ImageRgba8(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             _ => {
                 return Err("Image type not supported");
@@ -1274,15 +1074,15 @@ impl TryFrom<&DynamicImage> for SerialImageBuffer<u16> {
             }
             DynamicImage::ImageLumaA16(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             DynamicImage::ImageRgb16(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             DynamicImage::ImageRgba16(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.as_raw().clone(), pixel_elems);
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
             }
             _ => {
                 return Err("Image type not supported");
@@ -1320,11 +1120,11 @@ impl TryFrom<&DynamicImage> for SerialImageBuffer<f32> {
         match image {
             DynamicImage::ImageRgb32F(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.clone().into_raw(), pixel_elems)
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems)
             }
             DynamicImage::ImageRgba32F(img) => {
                 (luma, red, green, blue, alpha) =
-                    Self::from_vec_unsafe(width * height, img.clone().into_raw(), pixel_elems)
+                    Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems)
             }
             _ => {
                 return Err("Image type not supported");
@@ -1353,7 +1153,7 @@ impl Into<DynamicImage> for &SerialImageBuffer<u8> {
         let width = self.width;
         let height = self.height;
         let pixel_elems = self.data.pixel_elems;
-        let data = self.clone().into_vec();
+        let data = self.to_interleaved();
 
         match pixel_elems {
             1 => {
@@ -1403,7 +1203,7 @@ impl Into<DynamicImage> for &SerialImageBuffer<u16> {
         let width = self.width;
         let height = self.height;
         let pixel_elems = self.data.pixel_elems;
-        let data = self.clone().into_vec();
+        let data = self.to_interleaved();
 
         match pixel_elems {
             1 => {
@@ -1453,7 +1253,7 @@ impl Into<DynamicImage> for &SerialImageBuffer<f32> {
         let width = self.width;
         let height = self.height;
         let pixel_elems = self.data.pixel_elems;
-        let data = self.clone().into_vec();
+        let data = self.to_interleaved();
 
         match pixel_elems {
             3 => {
@@ -1717,7 +1517,7 @@ impl TryInto<ImageBuffer<Rgb<f32>, Vec<f32>>> for &SerialImageBuffer<f32> {
     }
 }
 
-impl<T: Primitive> From<ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T> {
+impl<T: Primitive + ParallelSample> From<ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T> {
     fn from(img: ImageBuffer<Luma<T>, Vec<T>>) -> Self {
         let width = img.width() as usize;
         let height = img.height() as usize;
@@ -1741,14 +1541,13 @@ impl<T: Primitive> From<ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T> {
     }
 }
 
-impl<T: Primitive> From<&ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T> {
+impl<T: Primitive + ParallelSample> From<&ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T> {
     fn from(img: &ImageBuffer<Luma<T>, Vec<T>>) -> Self {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 1;
-        let data = img.clone().into_raw();
         let (luma, red, green, blue, alpha) =
-            Self::from_vec_unsafe(width * height, data, pixel_elems);
+            Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
         Self {
             meta: None,
             data: SerialImageInternal {
@@ -1765,7 +1564,7 @@ impl<T: Primitive> From<&ImageBuffer<Luma<T>, Vec<T>>> for SerialImageBuffer<T>
     }
 }
 
-impl<T: Primitive> From<ImageBuffer<LumaA<T>, Vec<T>>> for SerialImageBuffer<T> {
+impl<T: Primitive + ParallelSample> From<ImageBuffer<LumaA<T>, Vec<T>>> for SerialImageBuffer<T> {
     fn from(img: ImageBuffer<LumaA<T>, Vec<T>>) -> Self {
         let width = img.width() as usize;
         let height = img.height() as usize;
@@ -1789,14 +1588,13 @@ impl<T: Primitive> From<ImageBuffer<LumaA<T>, Vec<T>>> for SerialImageBuffer<T>
     }
 }
 
-impl<T: Primitive> From<&ImageBuffer<LumaA<T>, Vec<T>>> for SerialImageBuffer<T> {
+impl<T: Primitive + ParallelSample> From<&ImageBuffer<LumaA<T>, Vec<T>>> for SerialImageBuffer<T> {
     fn from(img: &ImageBuffer<LumaA<T>, Vec<T>>) -> Self {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 2;
-        let data = img.clone().into_raw();
         let (luma, red, green, blue, alpha) =
-            Self::from_vec_unsafe(width * height, data, pixel_elems);
+            Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
         Self {
             meta: None,
             data: SerialImageInternal {
@@ -1842,9 +1640,8 @@ impl From<&ImageBuffer<Rgb<u8>, Vec<u8>>> for SerialImageBuffer<u8> {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 3;
-        let data = img.clone().into_raw();
         let (luma, red, green, blue, alpha) =
-            Self::from_vec_unsafe(width * height, data, pixel_elems);
+            Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
         Self {
             meta: None,
             data: SerialImageInternal {
@@ -1890,9 +1687,8 @@ impl From<&ImageBuffer<Rgb<u16>, Vec<u16>>> for SerialImageBuffer<u16> {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 3;
-        let data = img.clone().into_raw();
         let (luma, red, green, blue, alpha) =
-            Self::from_vec_unsafe(width * height, data, pixel_elems);
+            Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
         Self {
             meta: None,
             data: SerialImageInternal {
@@ -1914,7 +1710,7 @@ impl From<ImageBuffer<Rgb<f32>, Vec<f32>>> for SerialImageBuffer<f32> {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 3;
-        let data = img.clone().into_raw();
+        let data = img.into_raw();
         let (luma, red, green, blue, alpha) =
             Self::from_vec_unsafe(width * height, data, pixel_elems);
         Self {
@@ -1938,9 +1734,8 @@ impl From<&ImageBuffer<Rgb<f32>, Vec<f32>>> for SerialImageBuffer<f32> {
         let width = img.width() as usize;
         let height = img.height() as usize;
         let pixel_elems = 3;
-        let data = img.clone().into_raw();
         let (luma, red, green, blue, alpha) =
-            Self::from_vec_unsafe(width * height, data, pixel_elems);
+            Self::from_slice_unsafe(width * height, img.as_raw(), pixel_elems);
         Self {
             meta: None,
             data: SerialImageInternal {
@@ -1956,37 +1751,3 @@ impl From<&ImageBuffer<Rgb<f32>, Vec<f32>>> for SerialImageBuffer<f32> {
         }
     }
 }
-
-fn get_red_lut_16() -> [u16; u16::MAX as usize + 1] {
-    let mut lut = [0u16; u16::MAX as usize + 1];
-    let mut ctr = 0;
-    while ctr < u16::MAX as usize + 1 {
-        lut[ctr] = (ctr as f32 * 0.2126).round() as u16;
-        ctr += 1;
-    }
-    lut
-}
-
-fn get_green_lut_16() -> [u16; u16::MAX as usize + 1] {
-    let mut lut = [0u16; u16::MAX as usize + 1];
-    let mut ctr = 0;
-    while ctr < u16::MAX as usize + 1 {
-        lut[ctr] = (ctr as f32 * 0.7152).round() as u16;
-        ctr += 1;
-    }
-    lut
-}
-
-fn get_blue_lut_16() -> [u16; u16::MAX as usize + 1] {
-    let mut lut = [0u16; u16::MAX as usize + 1];
-    let mut ctr = 0;
-    while ctr < u16::MAX as usize + 1 {
-        lut[ctr] = (ctr as f32 * 0.0722).round() as u16;
-        ctr += 1;
-    }
-    lut
-}
-
-static R_LUT_U16: Lazy<[u16; u16::MAX as usize + 1]> = Lazy::new(get_red_lut_16);
-static G_LUT_U16: Lazy<[u16; u16::MAX as usize + 1]> = Lazy::new(get_green_lut_16);
-static B_LUT_U16: Lazy<[u16; u16::MAX as usize + 1]> = Lazy::new(get_blue_lut_16);
@@ -0,0 +1,1017 @@
+#![warn(missing_docs)]
+
+use std::{
+    borrow::Cow,
+    io::{self, Read, Write},
+};
+
+#[cfg(feature = "compression")]
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::{DynamicSerialImage, ImageMetaData, PixelArithmetic, SampleType, SerialImageBuffer};
+
+/// Signature at the start of files in the binary format of this crate.
+pub(crate) const MAGIC: &[u8; 8] = b"SERIMAGE";
+
+/// Version of the binary format.
+const VERSION: u8 = 1;
+
+/// Layout of the pixel data: row bands, with the rows of each channel stored in turn.
+const LAYOUT_BANDS: u8 = 0;
+
+/// Layout of the pixel data: tiles of each channel, in row-major order, with an index of the
+/// tile lengths after the metadata.
+const LAYOUT_TILES: u8 = 1;
+
+/// Maximal expansion of compressed pixel data, the largest compression ratio of zlib.
+const MAX_EXPANSION: usize = 1032;
+
+/// Length of the fixed part of the header, up to the metadata.
+pub(crate) const HEADER_LEN: usize = 36;
+
+/// Callback reporting the progress of a [`StreamWriter`] or [`StreamReader`],
+/// with the number of rows processed and the height of the image.
+pub type ProgressCallback = Box<dyn FnMut(usize, usize) + Send>;
+
+/// Sample types that can be stored in the binary format of this crate.
+pub trait StreamPrimitive: PixelArithmetic {
+    /// Sample type of the images holding the samples.
+    const SAMPLE_TYPE: SampleType;
+
+    /// Append the samples to a buffer, in little-endian order.
+    #[doc(hidden)]
+    fn write_le(data: &[Self], out: &mut Vec<u8>);
+
+    /// Append the samples stored in little-endian order in a buffer to `out`.
+    #[doc(hidden)]
+    fn read_le(data: &[u8], out: &mut Vec<Self>);
+}
+
+impl StreamPrimitive for u8 {
+    const SAMPLE_TYPE: SampleType = SampleType::U8;
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        out.extend_from_slice(data);
+    }
+
+    fn read_le(data: &[u8], out: &mut Vec<Self>) {
+        out.extend_from_slice(data);
+    }
+}
+
+impl StreamPrimitive for u16 {
+    const SAMPLE_TYPE: SampleType = SampleType::U16;
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        for x in data {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    fn read_le(data: &[u8], out: &mut Vec<Self>) {
+        out.extend(
+            data.chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]])),
+        );
+    }
+}
+
+impl StreamPrimitive for f32 {
+    const SAMPLE_TYPE: SampleType = SampleType::F32;
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        for x in data {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    fn read_le(data: &[u8], out: &mut Vec<Self>) {
+        out.extend(
+            data.chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        );
+    }
+}
+
+pub(crate) fn sample_size(sample: SampleType) -> usize {
+    match sample {
+        SampleType::U8 => 1,
+        SampleType::U16 => 2,
+        SampleType::F32 => 4,
+    }
+}
+
+/// Compression of the pixel data in the binary format of this crate.
+///
+/// Compressed images require the `compression` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StreamCompression {
+    /// The pixel data is not compressed.
+    #[default]
+    None,
+    /// Deflate compression with a zlib header.
+    Zlib,
+    /// LZ4 block compression, faster with larger files.
+    Lz4,
+}
+
+impl StreamCompression {
+    fn id(&self) -> u8 {
+        match self {
+            StreamCompression::None => 0,
+            StreamCompression::Zlib => 1,
+            StreamCompression::Lz4 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(StreamCompression::None),
+            1 => Some(StreamCompression::Zlib),
+            2 => Some(StreamCompression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Compress a block of pixel data.
+#[cfg(feature = "compression")]
+pub(crate) fn compress_block(compression: StreamCompression, data: &[u8]) -> io::Result<Vec<u8>> {
+    Ok(match compression {
+        StreamCompression::None => data.to_vec(),
+        StreamCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        }
+        StreamCompression::Lz4 => lz4_flex::block::compress(data),
+    })
+}
+
+/// Decompress a block of pixel data of `len` bytes.
+#[cfg(feature = "compression")]
+pub(crate) fn decompress_block(
+    compression: StreamCompression,
+    block: &[u8],
+    len: usize,
+) -> io::Result<Vec<u8>> {
+    // The length comes from the header, and is checked against the maximal expansion
+    // of the block before allocating
+    if len > block.len().saturating_mul(MAX_EXPANSION) {
+        return Err(invalid_data("Invalid size of the decompressed data"));
+    }
+    let data = match compression {
+        StreamCompression::None => block.to_vec(),
+        StreamCompression::Zlib => {
+            let mut data = Vec::with_capacity(len);
+            ZlibDecoder::new(block)
+                .take(len as u64 + 1)
+                .read_to_end(&mut data)?;
+            data
+        }
+        StreamCompression::Lz4 => lz4_flex::block::decompress(block, len)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+    };
+    if data.len() != len {
+        return Err(invalid_data("Invalid size of the decompressed data"));
+    }
+    Ok(data)
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress_block(compression: StreamCompression, data: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        StreamCompression::None => Ok(data.to_vec()),
+        _ => Err(unsupported()),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress_block(
+    compression: StreamCompression,
+    block: &[u8],
+    len: usize,
+) -> io::Result<Vec<u8>> {
+    match compression {
+        StreamCompression::None if block.len() == len => Ok(block.to_vec()),
+        StreamCompression::None => Err(invalid_data("Invalid size of the pixel data")),
+        _ => Err(unsupported()),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Compressed images require the `compression` feature",
+    )
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Options for writing images in the binary format of this crate.
+///
+/// The default values are:
+/// * `band_rows` - `64`
+/// * `tile_size` - `256 x 256`
+/// * `compression` - [`StreamCompression::None`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamOptions {
+    pub(crate) band_rows: usize,
+    pub(crate) tile_width: usize,
+    pub(crate) tile_height: usize,
+    pub(crate) compression: StreamCompression,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            band_rows: 64,
+            tile_width: 256,
+            tile_height: 256,
+            compression: StreamCompression::None,
+        }
+    }
+}
+
+impl StreamOptions {
+    /// Create the default stream options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of rows in each band of pixel data, the unit in which images are written
+    /// and read. Larger bands use more memory. Values less than `1` are set to `1`.
+    pub fn band_rows(mut self, rows: usize) -> Self {
+        self.band_rows = rows.max(1);
+        self
+    }
+
+    /// Set the width and height of the tiles of images written with
+    /// [`SerialImageBuffer::write_tiled`]. Values less than `1` are set to `1`.
+    pub fn tile_size(mut self, width: usize, height: usize) -> Self {
+        self.tile_width = width.max(1);
+        self.tile_height = height.max(1);
+        self
+    }
+
+    /// Set the compression of the pixel data. Compressed images require the `compression` feature.
+    pub fn compression(mut self, compression: StreamCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
+/// Header of an image in the binary format of this crate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamHeader {
+    /// Image width
+    pub width: usize,
+    /// Image height
+    pub height: usize,
+    /// Sample type of the pixel data
+    pub sample_type: SampleType,
+    /// Number of pixel elements (channels)
+    pub pixel_elems: u8,
+    /// Number of rows in each band of pixel data, the tile height of tiled images
+    pub band_rows: usize,
+    /// Tile width of tiled images, `None` for images stored in row bands
+    pub tile_width: Option<usize>,
+    /// Compression of the pixel data
+    pub compression: StreamCompression,
+    /// Image metadata
+    pub meta: Option<ImageMetaData>,
+}
+
+impl StreamHeader {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let meta = match &self.meta {
+            Some(meta) => serde_json::to_vec(meta).map_err(io::Error::from)?,
+            None => Vec::new(),
+        };
+        let mut header = Vec::with_capacity(HEADER_LEN + meta.len());
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.push(match self.tile_width {
+            Some(_) => LAYOUT_TILES,
+            None => LAYOUT_BANDS,
+        });
+        header.push(match self.sample_type {
+            SampleType::U8 => 0,
+            SampleType::U16 => 1,
+            SampleType::F32 => 2,
+        });
+        header.push(self.pixel_elems);
+        header.push(self.compression.id());
+        header.extend_from_slice(&[0; 3]);
+        for value in [
+            self.width,
+            self.height,
+            self.tile_width.unwrap_or(self.width),
+            self.band_rows,
+            meta.len(),
+        ] {
+            let value = u32::try_from(value)
+                .map_err(|_| invalid_input("Image dimensions and metadata must fit in 32 bits"))?;
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&meta);
+        writer.write_all(&header)
+    }
+
+    /// Read the header, returning it with its length including the metadata.
+    fn read<R: Read>(reader: &mut R) -> io::Result<(Self, usize)> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid_data("Not a serial image"));
+        }
+        if header[8] != VERSION {
+            return Err(invalid_data("Unsupported serial image version"));
+        }
+        let tiled = match header[9] {
+            LAYOUT_BANDS => false,
+            LAYOUT_TILES => true,
+            _ => return Err(invalid_data("Unsupported serial image layout")),
+        };
+        let sample_type = match header[10] {
+            0 => SampleType::U8,
+            1 => SampleType::U16,
+            2 => SampleType::F32,
+            _ => return Err(invalid_data("Invalid sample type")),
+        };
+        let pixel_elems = header[11];
+        let compression = StreamCompression::from_id(header[12])
+            .ok_or_else(|| invalid_data("Invalid compression"))?;
+        let field = |idx: usize| {
+            let start = 16 + 4 * idx;
+            u32::from_le_bytes([
+                header[start],
+                header[start + 1],
+                header[start + 2],
+                header[start + 3],
+            ]) as usize
+        };
+        let (width, height, tile_width, band_rows, meta_len) =
+            (field(0), field(1), field(2), field(3), field(4));
+        if width == 0 || height == 0 {
+            return Err(invalid_data("Width and height must be greater than zero"));
+        }
+        if !(1..=4).contains(&pixel_elems) {
+            return Err(invalid_data("Invalid number of pixel elements"));
+        }
+        if band_rows == 0 || tile_width == 0 {
+            return Err(invalid_data(
+                "Band rows and tile width must be greater than zero",
+            ));
+        }
+        let meta = if meta_len > 0 {
+            let mut json = Vec::new();
+            reader.take(meta_len as u64).read_to_end(&mut json)?;
+            if json.len() != meta_len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Some(serde_json::from_slice(&json).map_err(io::Error::from)?)
+        } else {
+            None
+        };
+        let header = Self {
+            width,
+            height,
+            sample_type,
+            pixel_elems,
+            band_rows,
+            tile_width: if tiled { Some(tile_width) } else { None },
+            compression,
+            meta,
+        };
+        if header.data_len().is_none() {
+            return Err(invalid_data("Image dimensions are too large"));
+        }
+        Ok((header, HEADER_LEN + meta_len))
+    }
+
+    /// Check that the pixel data fits in `len` bytes once stored, given the maximal expansion
+    /// of compressed data.
+    pub(crate) fn check_stored_len(&self, len: usize) -> io::Result<()> {
+        let max_len = match self.compression {
+            StreamCompression::None => len,
+            _ => len.saturating_mul(MAX_EXPANSION),
+        };
+        match self.data_len() {
+            Some(data_len) if data_len <= max_len => Ok(()),
+            _ => Err(invalid_data(
+                "Image dimensions exceed the length of the data",
+            )),
+        }
+    }
+
+    /// Size of the pixel data, in bytes before compression, or `None` if it overflows.
+    pub(crate) fn data_len(&self) -> Option<usize> {
+        self.width
+            .checked_mul(self.height)?
+            .checked_mul(self.pixel_elems as usize)?
+            .checked_mul(sample_size(self.sample_type))
+    }
+
+    /// Number of tiles in a row and in a column of tiles, one tile for the row bands.
+    pub(crate) fn tiles(&self) -> (usize, usize) {
+        let tile_width = self.tile_width.unwrap_or(self.width);
+        (
+            (self.width + tile_width - 1) / tile_width,
+            (self.height + self.band_rows - 1) / self.band_rows,
+        )
+    }
+
+    /// Range of columns of the tile `tx` in a row of tiles.
+    pub(crate) fn tile_columns(&self, tx: usize) -> std::ops::Range<usize> {
+        let tile_width = self.tile_width.unwrap_or(self.width);
+        tx * tile_width..(tx * tile_width + tile_width).min(self.width)
+    }
+
+    /// Number of rows in the band starting at `row`.
+    pub(crate) fn rows_at(&self, row: usize) -> usize {
+        self.band_rows.min(self.height - row)
+    }
+}
+
+/// Writer of images in the binary format of this crate, one band of rows at a time.
+///
+/// The header is written when the writer is created. The pixel data is then written with
+/// [`StreamWriter::write_rows`], or [`StreamWriter::write_image`] for an image in memory,
+/// and the writer is closed with [`StreamWriter::finish`]. At most one band of rows is held
+/// in memory.
+pub struct StreamWriter<W: Write, T: StreamPrimitive> {
+    writer: W,
+    header: StreamHeader,
+    rows: usize,
+    band: Vec<T>,
+    buf: Vec<u8>,
+    progress: Option<ProgressCallback>,
+}
+
+impl<W: Write, T: StreamPrimitive> StreamWriter<W, T> {
+    /// Create a writer of an image with the given dimensions, number of pixel elements
+    /// and metadata, and write the header.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if the dimensions or the number of pixel elements are invalid.
+    ///  * Any error of the writer.
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        pixel_elems: u8,
+        meta: Option<&ImageMetaData>,
+        options: &StreamOptions,
+    ) -> io::Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(invalid_input("Width and height must be greater than zero"));
+        }
+        if !(1..=4).contains(&pixel_elems) {
+            return Err(invalid_input("Invalid number of pixel elements"));
+        }
+        let header = StreamHeader {
+            width,
+            height,
+            sample_type: T::SAMPLE_TYPE,
+            pixel_elems,
+            band_rows: options.band_rows.min(height),
+            tile_width: None,
+            compression: options.compression,
+            meta: meta.cloned(),
+        };
+        header.write(&mut writer)?;
+        Ok(Self {
+            writer,
+            header,
+            rows: 0,
+            band: Vec::new(),
+            buf: Vec::new(),
+            progress: None,
+        })
+    }
+
+    /// Create a writer of an image with the dimensions, number of pixel elements and metadata
+    /// of `image`, and write the header. The pixel data is written with [`StreamWriter::write_image`].
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    pub fn for_image(
+        writer: W,
+        image: &SerialImageBuffer<T>,
+        options: &StreamOptions,
+    ) -> io::Result<Self> {
+        Self::new(
+            writer,
+            image.width(),
+            image.height(),
+            image.pixel_elems(),
+            image.meta.as_ref(),
+            options,
+        )
+    }
+
+    /// Set a callback called with the number of rows written and the image height
+    /// after each band is written.
+    pub fn on_progress(mut self, callback: impl FnMut(usize, usize) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Get the header of the image.
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// Get the number of rows written so far, including the buffered rows.
+    pub fn rows_written(&self) -> usize {
+        self.rows + self.band.len() / self.row_len()
+    }
+
+    fn row_len(&self) -> usize {
+        self.header.width * self.header.pixel_elems as usize
+    }
+
+    /// Write whole rows of interleaved pixel data (see [`SerialImageBuffer::from_vec`] for the
+    /// order of the pixel elements). The rows are written once a band is complete.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if `data` does not hold whole rows, or more rows than the image height.
+    ///  * Any error of the writer.
+    pub fn write_rows(&mut self, mut data: &[T]) -> io::Result<()> {
+        let row_len = self.row_len();
+        if data.len() % row_len != 0 {
+            return Err(invalid_input("Data must contain whole rows"));
+        }
+        if self.rows_written() + data.len() / row_len > self.header.height {
+            return Err(invalid_input("Data must not exceed the image height"));
+        }
+        while !data.is_empty() {
+            let band_len = self.header.rows_at(self.rows) * row_len;
+            let count = (band_len - self.band.len()).min(data.len());
+            self.band.extend_from_slice(&data[..count]);
+            data = &data[count..];
+            if self.band.len() == band_len {
+                let band = std::mem::take(&mut self.band);
+                let elems = self.header.pixel_elems as usize;
+                let planes = (0..elems)
+                    .map(|ch| band.iter().skip(ch).step_by(elems).copied().collect())
+                    .collect::<Vec<Vec<T>>>();
+                self.write_band(planes.iter().map(|p| p.as_slice()))?;
+                self.band = band;
+                self.band.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the pixel data of an image in memory, one band at a time.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if rows were already written, or the dimensions or
+    ///    pixel elements of `image` differ from the header.
+    ///  * Any error of the writer.
+    pub fn write_image(&mut self, image: &SerialImageBuffer<T>) -> io::Result<()> {
+        if self.rows_written() > 0 {
+            return Err(invalid_input("Rows were already written"));
+        }
+        if (image.width(), image.height(), image.pixel_elems())
+            != (
+                self.header.width,
+                self.header.height,
+                self.header.pixel_elems,
+            )
+        {
+            return Err(invalid_input(
+                "Image dimensions and pixel elements must match the header",
+            ));
+        }
+        let width = self.header.width;
+        while self.rows < self.header.height {
+            let range = self.rows * width..(self.rows + self.header.rows_at(self.rows)) * width;
+            self.write_band(
+                image
+                    .channels()
+                    .iter()
+                    .map(|&ch| &image.channel_data(ch).as_ref().unwrap()[range.clone()]),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write a band of planar data, one channel after the other.
+    fn write_band<'a>(&mut self, planes: impl Iterator<Item = &'a [T]>) -> io::Result<()>
+    where
+        T: 'a,
+    {
+        for plane in planes {
+            self.buf.clear();
+            T::write_le(plane, &mut self.buf);
+            if self.header.compression == StreamCompression::None {
+                self.writer.write_all(&self.buf)?;
+            } else {
+                // Compressed blocks are prefixed with their length
+                let block = compress_block(self.header.compression, &self.buf)?;
+                self.writer.write_all(&(block.len() as u64).to_le_bytes())?;
+                self.writer.write_all(&block)?;
+            }
+        }
+        self.rows += self.header.rows_at(self.rows);
+        if let Some(progress) = self.progress.as_mut() {
+            progress(self.rows, self.header.height);
+        }
+        Ok(())
+    }
+
+    /// Flush the writer and return it.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if fewer rows than the image height were written.
+    ///  * Any error of the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.rows != self.header.height {
+            return Err(invalid_input("All rows of the image must be written"));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reader of images in the binary format of this crate, one band of rows at a time.
+///
+/// The header is read when the reader is created. The pixel data is then read band by band
+/// with [`StreamReader::read_band`], or at once with [`StreamReader::read_image`].
+/// Tiled images are read one row of tiles at a time.
+pub struct StreamReader<R: Read> {
+    pub(crate) reader: R,
+    header: StreamHeader,
+    /// Stored lengths of the tiles of tiled images, in file order.
+    pub(crate) index: Vec<u64>,
+    /// Length of the header, metadata and tile index.
+    pub(crate) data_offset: u64,
+    rows: usize,
+    block: usize,
+    buf: Vec<u8>,
+    progress: Option<ProgressCallback>,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Create a reader and read the header of the image.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the header is invalid.
+    ///  * Any error of the reader.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let (header, header_len) = StreamHeader::read(&mut reader)?;
+        let mut index = Vec::new();
+        if header.tile_width.is_some() {
+            let (tiles_x, tiles_y) = header.tiles();
+            let len = (tiles_x * tiles_y * header.pixel_elems as usize)
+                .checked_mul(8)
+                .ok_or_else(|| invalid_data("Image dimensions are too large"))?;
+            let mut bytes = Vec::new();
+            (&mut reader).take(len as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            index = bytes
+                .chunks_exact(8)
+                .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .collect();
+        }
+        Ok(Self {
+            reader,
+            data_offset: (header_len + index.len() * 8) as u64,
+            header,
+            index,
+            rows: 0,
+            block: 0,
+            buf: Vec::new(),
+            progress: None,
+        })
+    }
+
+    /// Set a callback called with the number of rows read and the image height
+    /// after each band is read.
+    pub fn on_progress(mut self, callback: impl FnMut(usize, usize) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Get the header of the image.
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// Get the number of rows read so far.
+    pub fn rows_read(&self) -> usize {
+        self.rows
+    }
+
+    /// Read the next band of rows as an image without metadata, or `None` after the last band.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if `T` is not the sample type of the image.
+    ///  * Any error of the reader.
+    pub fn read_band<T: StreamPrimitive>(&mut self) -> io::Result<Option<SerialImageBuffer<T>>> {
+        if T::SAMPLE_TYPE != self.header.sample_type {
+            return Err(invalid_input("Sample type must match the header"));
+        }
+        if self.rows == self.header.height {
+            return Ok(None);
+        }
+        let (rows, planes) = self.read_planes()?;
+        SerialImageBuffer::from_planes(self.header.width, rows, planes, None)
+            .map(Some)
+            .map_err(invalid_data)
+    }
+
+    /// Read the remaining pixel data as an image with the metadata of the header.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if `T` is not the sample type of the image,
+    ///    or bands were already read.
+    ///  * Any error of the reader.
+    pub fn read_image<T: StreamPrimitive>(&mut self) -> io::Result<SerialImageBuffer<T>> {
+        if T::SAMPLE_TYPE != self.header.sample_type {
+            return Err(invalid_input("Sample type must match the header"));
+        }
+        if self.rows > 0 {
+            return Err(invalid_input("Bands were already read"));
+        }
+        // The planes grow with the data actually read, rather than being allocated from the
+        // dimensions in the header
+        let mut planes = vec![Vec::new(); self.header.pixel_elems as usize];
+        while self.rows < self.header.height {
+            let (_, band) = self.read_planes::<T>()?;
+            for (plane, band) in planes.iter_mut().zip(band) {
+                plane.extend(band);
+            }
+        }
+        SerialImageBuffer::from_planes(
+            self.header.width,
+            self.header.height,
+            planes,
+            self.header.meta.clone(),
+        )
+        .map_err(invalid_data)
+    }
+
+    /// Read the remaining pixel data as an image with the metadata of the header.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if bands were already read.
+    ///  * Any error of the reader.
+    pub fn read_dynamic(&mut self) -> io::Result<DynamicSerialImage> {
+        Ok(match self.header.sample_type {
+            SampleType::U8 => self.read_image::<u8>()?.into(),
+            SampleType::U16 => self.read_image::<u16>()?.into(),
+            SampleType::F32 => self.read_image::<f32>()?.into(),
+        })
+    }
+
+    /// Read the next band, or row of tiles, as planar channel data.
+    fn read_planes<T: StreamPrimitive>(&mut self) -> io::Result<(usize, Vec<Vec<T>>)> {
+        let width = self.header.width;
+        let rows = self.header.rows_at(self.rows);
+        let elems = self.header.pixel_elems as usize;
+        let planes = match self.header.tile_width {
+            None => (0..elems)
+                .map(|_| self.read_samples(rows * width))
+                .collect::<io::Result<Vec<_>>>()?,
+            Some(_) => {
+                // The tiles are read before the planes are allocated
+                let (tiles_x, _) = self.header.tiles();
+                let tiles = (0..tiles_x * elems)
+                    .map(|idx| {
+                        self.read_samples(rows * self.header.tile_columns(idx / elems).len())
+                    })
+                    .collect::<io::Result<Vec<_>>>()?;
+                let mut planes = vec![vec![T::DEFAULT_MIN_VALUE; rows * width]; elems];
+                for (idx, tile) in tiles.iter().enumerate() {
+                    let columns = self.header.tile_columns(idx / elems);
+                    copy_rect(
+                        tile,
+                        columns.len(),
+                        &mut planes[idx % elems],
+                        width,
+                        columns.start,
+                    );
+                }
+                planes
+            }
+        };
+        self.rows += rows;
+        if let Some(progress) = self.progress.as_mut() {
+            progress(self.rows, self.header.height);
+        }
+        Ok((rows, planes))
+    }
+
+    /// Read the next block of pixel data, holding `count` samples.
+    fn read_samples<T: StreamPrimitive>(&mut self, count: usize) -> io::Result<Vec<T>> {
+        let len = count * sample_size(self.header.sample_type);
+        let stored = if self.header.tile_width.is_some() {
+            *self
+                .index
+                .get(self.block)
+                .ok_or_else(|| invalid_data("Invalid tile index"))?
+        } else if self.header.compression != StreamCompression::None {
+            let mut prefix = [0u8; 8];
+            self.reader.read_exact(&mut prefix)?;
+            u64::from_le_bytes(prefix)
+        } else {
+            len as u64
+        };
+        self.block += 1;
+        self.buf.clear();
+        (&mut self.reader).take(stored).read_to_end(&mut self.buf)?;
+        if self.buf.len() as u64 != stored {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        decode_samples(self.header.compression, &self.buf, count)
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Decode a block of pixel data holding `count` samples.
+pub(crate) fn decode_samples<T: StreamPrimitive>(
+    compression: StreamCompression,
+    block: &[u8],
+    count: usize,
+) -> io::Result<Vec<T>> {
+    let len = count * sample_size(T::SAMPLE_TYPE);
+    let data = if compression == StreamCompression::None {
+        if block.len() != len {
+            return Err(invalid_data("Invalid size of the pixel data"));
+        }
+        Cow::Borrowed(block)
+    } else {
+        Cow::Owned(decompress_block(compression, block, len)?)
+    };
+    let mut samples = Vec::with_capacity(count);
+    T::read_le(&data, &mut samples);
+    Ok(samples)
+}
+
+/// Copy the rows of `src`, `src_width` samples wide, to `dst`, `dst_width` samples wide,
+/// starting at column `x`.
+fn copy_rect<T: Copy>(src: &[T], src_width: usize, dst: &mut [T], dst_width: usize, x: usize) {
+    for (src, dst) in src
+        .chunks_exact(src_width)
+        .zip(dst.chunks_exact_mut(dst_width))
+    {
+        dst[x..x + src_width].copy_from_slice(src);
+    }
+}
+
+impl<T: StreamPrimitive> SerialImageBuffer<T> {
+    /// Write the image with its metadata in the binary format of this crate, one band of rows
+    /// at a time, so that the memory used does not grow with the image size.
+    /// Use [`StreamWriter`] to report progress.
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    pub fn write_stream<W: Write>(&self, writer: W, options: &StreamOptions) -> io::Result<()> {
+        let mut stream = StreamWriter::for_image(writer, self, options)?;
+        stream.write_image(self)?;
+        stream.finish().map(|_| ())
+    }
+
+    /// Read an image written by [`SerialImageBuffer::write_stream`] or [`StreamWriter`].
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the data is invalid.
+    ///  * [`io::ErrorKind::InvalidInput`] if `T` is not the sample type of the image.
+    ///  * Any error of the reader.
+    pub fn read_stream<R: Read>(reader: R) -> io::Result<Self> {
+        StreamReader::new(reader)?.read_image()
+    }
+}
+
+impl DynamicSerialImage {
+    /// Write the image with its metadata in the binary format of this crate.
+    /// See [`SerialImageBuffer::write_stream`].
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    pub fn write_stream<W: Write>(&self, writer: W, options: &StreamOptions) -> io::Result<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_stream(writer, options),
+            DynamicSerialImage::U16(value) => value.write_stream(writer, options),
+            DynamicSerialImage::F32(value) => value.write_stream(writer, options),
+        }
+    }
+
+    /// Read an image written by [`DynamicSerialImage::write_stream`] or [`StreamWriter`].
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the data is invalid.
+    ///  * Any error of the reader.
+    pub fn read_stream<R: Read>(reader: R) -> io::Result<Self> {
+        StreamReader::new(reader)?.read_dynamic()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_stream() {
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        let data: Vec<u16> = (0..7 * 10 * 3).map(|x| x * 97).collect();
+        let mut img = SerialImageBuffer::from_vec(7, 10, data.clone()).unwrap();
+        img.set_metadata(Some(meta));
+        let options = StreamOptions::new().band_rows(3);
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let log = progress.clone();
+        let mut writer = StreamWriter::for_image(Vec::new(), &img, &options)
+            .unwrap()
+            .on_progress(move |rows, height| log.lock().unwrap().push((rows, height)));
+        writer.write_image(&img).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_eq!(
+            *progress.lock().unwrap(),
+            vec![(3, 10), (6, 10), (9, 10), (10, 10)]
+        );
+        assert_eq!(
+            SerialImageBuffer::<u16>::read_stream(&bytes[..]).unwrap(),
+            img
+        );
+        assert_eq!(
+            DynamicSerialImage::read_stream(&bytes[..]).unwrap(),
+            img.clone().into()
+        );
+
+        // Rows written in arbitrary chunks give the same stream
+        let mut writer =
+            StreamWriter::new(Vec::new(), 7, 10, 3, img.meta.as_ref(), &options).unwrap();
+        for chunk in data.chunks(7 * 3 * 2) {
+            writer.write_rows(chunk).unwrap();
+        }
+        assert!(writer.write_rows(&data[..21]).is_err());
+        assert_eq!(writer.finish().unwrap(), bytes);
+
+        let mut reader = StreamReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.header().band_rows, 3);
+        assert!(reader.read_band::<u8>().is_err());
+        let band = reader.read_band::<u16>().unwrap().unwrap();
+        assert_eq!((band.width(), band.height()), (7, 3));
+        assert_eq!(band.get_red().unwrap()[..2], [0, 3 * 97]);
+        let mut bands = 1;
+        while reader.read_band::<u16>().unwrap().is_some() {
+            bands += 1;
+        }
+        assert_eq!(bands, 4);
+
+        let writer = StreamWriter::<_, u16>::new(Vec::new(), 7, 10, 3, None, &options).unwrap();
+        assert!(writer.finish().is_err());
+        assert!(DynamicSerialImage::read_stream(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_stream_invalid() {
+        // Headers with a width and height of u32::MAX, and no pixel data
+        let header = |layout: u8, sample_type: u8, pixel_elems: u8| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&[VERSION, layout, sample_type, pixel_elems, 0, 0, 0, 0]);
+            for value in [u32::MAX, u32::MAX, 1, 1, 0] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            assert_eq!(bytes.len(), HEADER_LEN);
+            bytes
+        };
+        for (layout, sample_type, pixel_elems) in [
+            (LAYOUT_BANDS, 0, 1),
+            (LAYOUT_BANDS, 2, 4),
+            (LAYOUT_TILES, 0, 1),
+            (LAYOUT_TILES, 1, 3),
+        ] {
+            let bytes = header(layout, sample_type, pixel_elems);
+            let err = DynamicSerialImage::read_stream(&bytes[..]).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ));
+        }
+        let bytes = header(LAYOUT_BANDS, 2, 4);
+        let err = StreamReader::new(&bytes[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
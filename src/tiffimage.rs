@@ -0,0 +1,591 @@
+#![warn(missing_docs)]
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::NaiveDateTime;
+use image::{
+    error::{DecodingError, EncodingError, ImageFormatHint},
+    ImageError, ImageFormat, ImageResult,
+};
+use tiff::{
+    decoder::{ifd::Value, Decoder, DecodingResult},
+    encoder::{
+        colortype, colortype::ColorType, Compression, DeflateLevel, Rational, TiffEncoder,
+        TiffValue,
+    },
+    tags::{PhotometricInterpretation, SampleFormat, Tag},
+    ColorType as TiffColorType, TiffResult,
+};
+
+use crate::{
+    imagemetadata::utc_datetime, DynamicSerialImage, ImageMetaData, PixelArithmetic,
+    SerialImageBuffer, TiffCompression,
+};
+
+/// EXIF tag `ExposureTime`, in seconds.
+const EXIF_EXPOSURE_TIME: u16 = 33434;
+/// EXIF tag `DateTimeOriginal`.
+const EXIF_DATE_TIME_ORIGINAL: u16 = 36867;
+/// EXIF tag `SubSecTimeOriginal`.
+const EXIF_SUBSEC_TIME_ORIGINAL: u16 = 37521;
+/// EXIF tag `OffsetTimeOriginal`.
+const EXIF_OFFSET_TIME_ORIGINAL: u16 = 36881;
+/// Format of the TIFF and EXIF date tags.
+const TIFF_DATE_FORMAT: &str = "%Y:%m:%d %H:%M:%S";
+
+macro_rules! gray_alpha {
+    ($name:ident, $t:ty, $bits:expr, $format:expr) => {
+        /// Grayscale samples with an alpha channel.
+        struct $name;
+
+        impl ColorType for $name {
+            type Inner = $t;
+            const TIFF_VALUE: PhotometricInterpretation = PhotometricInterpretation::BlackIsZero;
+            const BITS_PER_SAMPLE: &'static [u16] = &[$bits, $bits];
+            const SAMPLE_FORMAT: &'static [SampleFormat] = &[$format, $format];
+
+            fn horizontal_predict(row: &[Self::Inner], result: &mut Vec<Self::Inner>) {
+                // Images are written without a predictor
+                result.extend_from_slice(row);
+            }
+        }
+    };
+}
+
+gray_alpha!(GrayA8, u8, 8, SampleFormat::Uint);
+gray_alpha!(GrayA16, u16, 16, SampleFormat::Uint);
+gray_alpha!(GrayA32Float, f32, 32, SampleFormat::IEEEFP);
+
+/// Sample types that can be stored in and read from TIFF images.
+pub trait TiffPrimitive: PixelArithmetic {
+    /// Write an image with the interleaved samples `data` and `channels` samples per pixel.
+    #[doc(hidden)]
+    fn encode<W: Write + Seek>(
+        encoder: &mut TiffEncoder<W>,
+        width: u32,
+        height: u32,
+        channels: usize,
+        data: &[Self],
+        meta: Option<&ImageMetaData>,
+    ) -> TiffResult<()>;
+
+    /// Get the samples of a decoded image, if they are of this sample type.
+    #[doc(hidden)]
+    fn decode(result: DecodingResult) -> Option<Vec<Self>>;
+}
+
+macro_rules! impl_tiff_primitive {
+    ($t:ty, $variant:ident, $gray:ty, $gray_alpha:ty, $rgb:ty, $rgba:ty) => {
+        impl TiffPrimitive for $t {
+            fn encode<W: Write + Seek>(
+                encoder: &mut TiffEncoder<W>,
+                width: u32,
+                height: u32,
+                channels: usize,
+                data: &[Self],
+                meta: Option<&ImageMetaData>,
+            ) -> TiffResult<()> {
+                match channels {
+                    1 => encode_image::<W, $gray>(encoder, width, height, data, meta),
+                    2 => encode_image::<W, $gray_alpha>(encoder, width, height, data, meta),
+                    3 => encode_image::<W, $rgb>(encoder, width, height, data, meta),
+                    _ => encode_image::<W, $rgba>(encoder, width, height, data, meta),
+                }
+            }
+
+            fn decode(result: DecodingResult) -> Option<Vec<Self>> {
+                match result {
+                    DecodingResult::$variant(data) => Some(data),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+impl_tiff_primitive!(
+    u8,
+    U8,
+    colortype::Gray8,
+    GrayA8,
+    colortype::RGB8,
+    colortype::RGBA8
+);
+impl_tiff_primitive!(
+    u16,
+    U16,
+    colortype::Gray16,
+    GrayA16,
+    colortype::RGB16,
+    colortype::RGBA16
+);
+impl_tiff_primitive!(
+    f32,
+    F32,
+    colortype::Gray32Float,
+    GrayA32Float,
+    colortype::RGB32Float,
+    colortype::RGBA32Float
+);
+
+/// Write an image, with the metadata in the `ImageDescription` tag and the standard TIFF and EXIF tags.
+fn encode_image<W: Write + Seek, C: ColorType>(
+    encoder: &mut TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    data: &[C::Inner],
+    meta: Option<&ImageMetaData>,
+) -> TiffResult<()>
+where
+    [C::Inner]: TiffValue,
+{
+    // The EXIF directory is written before the image directory that points to it
+    let exif = match meta {
+        Some(meta) => {
+            let timestamp = utc_datetime(meta.timestamp.max(UNIX_EPOCH));
+            let mut exif = encoder.extra_directory()?;
+            exif.write_tag(
+                Tag::Unknown(EXIF_EXPOSURE_TIME),
+                exposure_rational(meta.exposure),
+            )?;
+            exif.write_tag(
+                Tag::Unknown(EXIF_DATE_TIME_ORIGINAL),
+                timestamp.format(TIFF_DATE_FORMAT).to_string().as_str(),
+            )?;
+            exif.write_tag(
+                Tag::Unknown(EXIF_SUBSEC_TIME_ORIGINAL),
+                timestamp.format("%3f").to_string().as_str(),
+            )?;
+            exif.write_tag(Tag::Unknown(EXIF_OFFSET_TIME_ORIGINAL), "+00:00")?;
+            Some(exif.finish_with_offsets()?)
+        }
+        None => None,
+    };
+    let mut image = encoder.new_image::<C>(width, height)?;
+    let dir = image.encoder();
+    if C::BITS_PER_SAMPLE.len() % 2 == 0 {
+        // Unassociated alpha
+        dir.write_tag(Tag::ExtraSamples, &[2u16][..])?;
+    }
+    dir.write_tag(
+        Tag::Software,
+        concat!("serialimage ", env!("CARGO_PKG_VERSION")),
+    )?;
+    if let (Some(meta), Some(exif)) = (meta, exif) {
+        let description = ascii_json(&serde_json::to_string(meta).unwrap_or_default());
+        dir.write_tag(Tag::ImageDescription, description.as_str())?;
+        let model: String = meta
+            .camera_name
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '?' })
+            .collect();
+        dir.write_tag(Tag::Model, model.as_str())?;
+        dir.write_tag(
+            Tag::DateTime,
+            utc_datetime(meta.timestamp.max(UNIX_EPOCH))
+                .format(TIFF_DATE_FORMAT)
+                .to_string()
+                .as_str(),
+        )?;
+        dir.write_tag(Tag::ExifDirectory, exif.offset)?;
+    }
+    image.write_data(data)
+}
+
+/// Escape the non-ASCII characters of a JSON document as `\uXXXX` sequences, since TIFF
+/// ASCII tags can only hold ASCII characters.
+fn ascii_json(json: &str) -> String {
+    let mut out = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    out
+}
+
+/// Exposure time as a rational number of seconds, with microsecond resolution if possible.
+fn exposure_rational(exposure: Duration) -> Rational {
+    match u32::try_from(exposure.as_micros()) {
+        Ok(us) => {
+            let gcd = gcd(us, 1_000_000);
+            Rational {
+                n: us / gcd,
+                d: 1_000_000 / gcd,
+            }
+        }
+        Err(_) => Rational {
+            n: u32::try_from(exposure.as_secs()).unwrap_or(u32::MAX),
+            d: 1,
+        },
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a.max(1)
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn encoding_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        err,
+    ))
+}
+
+fn decoding_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        err,
+    ))
+}
+
+/// Decoded TIFF image, with the interleaved samples.
+struct TiffImage {
+    width: usize,
+    height: usize,
+    data: DecodingResult,
+    meta: Option<ImageMetaData>,
+}
+
+impl TiffImage {
+    fn read<R: Read + Seek>(reader: R) -> ImageResult<Self> {
+        let mut decoder = Decoder::new(reader).map_err(decoding_error)?;
+        let (width, height) = decoder.dimensions().map_err(decoding_error)?;
+        match decoder.colortype().map_err(decoding_error)? {
+            TiffColorType::Gray(_) | TiffColorType::RGB(_) | TiffColorType::RGBA(_) => {}
+            TiffColorType::Multiband { num_samples: 2, .. } => {}
+            _ => return Err(decoding_error("Unsupported TIFF color type")),
+        }
+        let meta = read_metadata(&mut decoder);
+        let data = match decoder.read_image().map_err(decoding_error)? {
+            DecodingResult::F64(data) => {
+                DecodingResult::F32(data.iter().map(|&x| x as f32).collect())
+            }
+            data => data,
+        };
+        Ok(Self {
+            width: width as usize,
+            height: height as usize,
+            data,
+            meta,
+        })
+    }
+
+    fn into_buffer<T: TiffPrimitive>(self) -> ImageResult<SerialImageBuffer<T>> {
+        let data = T::decode(self.data)
+            .ok_or_else(|| decoding_error("Image sample type does not match"))?;
+        let mut img =
+            SerialImageBuffer::from_vec(self.width, self.height, data).map_err(decoding_error)?;
+        img.set_metadata(self.meta);
+        Ok(img)
+    }
+}
+
+/// Restore the image metadata from the `ImageDescription` tag written by this crate, or
+/// from the standard TIFF and EXIF tags.
+fn read_metadata<R: Read + Seek>(decoder: &mut Decoder<R>) -> Option<ImageMetaData> {
+    let description = decoder.get_tag_ascii_string(Tag::ImageDescription).ok();
+    if let Some(meta) = description.and_then(|d| serde_json::from_str(&d).ok()) {
+        return Some(meta);
+    }
+    let camera = decoder.get_tag_ascii_string(Tag::Model).ok();
+    let mut timestamp = decoder
+        .get_tag_ascii_string(Tag::DateTime)
+        .ok()
+        .and_then(|date| parse_tiff_date(&date, None));
+    let mut exposure = None;
+    let exif = decoder
+        .find_tag_unsigned::<u64>(Tag::ExifDirectory)
+        .ok()
+        .flatten();
+    if let Some(exif) = exif {
+        if let Ok(dir) = decoder.read_directory(tiff::tags::IfdPointer(exif)) {
+            let mut tags = decoder.read_directory_tags(&dir);
+            if let Ok(Some(Value::Rational(n, d))) = tags.find_tag(Tag::Unknown(EXIF_EXPOSURE_TIME))
+            {
+                if d != 0 {
+                    exposure = Some(Duration::from_secs_f64(f64::from(n) / f64::from(d)));
+                }
+            }
+            let subsec = tags
+                .find_tag(Tag::Unknown(EXIF_SUBSEC_TIME_ORIGINAL))
+                .ok()
+                .flatten()
+                .and_then(|v| v.into_string().ok());
+            let original = tags
+                .find_tag(Tag::Unknown(EXIF_DATE_TIME_ORIGINAL))
+                .ok()
+                .flatten()
+                .and_then(|v| v.into_string().ok())
+                .and_then(|date| parse_tiff_date(&date, subsec.as_deref()));
+            timestamp = original.or(timestamp);
+        }
+    }
+    if camera.is_none() && timestamp.is_none() && exposure.is_none() {
+        return None;
+    }
+    let mut meta = ImageMetaData::default();
+    meta.camera_name = camera.unwrap_or_default();
+    meta.timestamp = timestamp.unwrap_or(UNIX_EPOCH);
+    meta.exposure = exposure.unwrap_or_default();
+    Some(meta)
+}
+
+/// Parse a TIFF date in UTC, with optional sub-second digits.
+fn parse_tiff_date(date: &str, subsec: Option<&str>) -> Option<SystemTime> {
+    let datetime = NaiveDateTime::parse_from_str(date.trim(), TIFF_DATE_FORMAT).ok()?;
+    let timestamp: SystemTime = datetime.and_utc().into();
+    // SubSecTime holds the decimal digits of the fraction; anything past
+    // nanosecond precision is dropped.
+    let digits = subsec
+        .map(str::trim)
+        .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()))
+        .map(|s| &s[..s.len().min(9)]);
+    match digits {
+        Some(digits) => {
+            let nanos = digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32);
+            timestamp.checked_add(Duration::from_nanos(nanos.into()))
+        }
+        None => Some(timestamp),
+    }
+}
+
+impl<T: TiffPrimitive> SerialImageBuffer<T> {
+    /// Save the image data to a TIFF file at `path`. See [`SerialImageBuffer::write_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn save_tiff(&self, path: &Path) -> ImageResult<()> {
+        let file = File::create(path).map_err(ImageError::IoError)?;
+        self.write_tiff(BufWriter::new(file))
+    }
+
+    /// Write the image data as an uncompressed TIFF image, with 8-bit, 16-bit or 32-bit
+    /// floating point samples.
+    ///
+    /// The image metadata is stored as JSON in the `ImageDescription` tag. The camera name and
+    /// the timestamp are also stored in the `Model` and `DateTime` tags, and the exposure time
+    /// and the timestamp in the `ExposureTime` and `DateTimeOriginal` tags of the EXIF directory.
+    /// Since TIFF text tags are ASCII, non-ASCII characters are escaped in the JSON metadata,
+    /// and replaced by `?` in the `Model` tag.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn write_tiff<W: Write + Seek>(&self, writer: W) -> ImageResult<()> {
+        self.write_tiff_with(writer, TiffCompression::None)
+    }
+
+    /// Write the image data as a TIFF image with the given compression.
+    pub(crate) fn write_tiff_with<W: Write + Seek>(
+        &self,
+        writer: W,
+        compression: TiffCompression,
+    ) -> ImageResult<()> {
+        let compression = match compression {
+            TiffCompression::None => Compression::Uncompressed,
+            TiffCompression::Lzw => Compression::Lzw,
+            TiffCompression::Deflate => Compression::Deflate(DeflateLevel::default()),
+            TiffCompression::PackBits => Compression::Packbits,
+        };
+        let mut encoder = TiffEncoder::new(writer)
+            .map_err(encoding_error)?
+            .with_compression(compression);
+        T::encode(
+            &mut encoder,
+            self.width() as u32,
+            self.height() as u32,
+            self.channels().len(),
+            &self.to_interleaved(),
+            self.get_metadata().as_ref(),
+        )
+        .map_err(encoding_error)
+    }
+
+    /// Open a TIFF image. See [`SerialImageBuffer::read_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn open_tiff(path: &Path) -> ImageResult<Self> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        Self::read_tiff(BufReader::new(file))
+    }
+
+    /// Read a TIFF image, with grayscale or RGB samples and an optional alpha channel.
+    ///
+    /// The image metadata is restored from the `ImageDescription` tag written by
+    /// [`SerialImageBuffer::write_tiff`]. For other files, the camera name, timestamp and
+    /// exposure time are restored from the standard TIFF and EXIF tags, if present.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description, e.g. if the file does not contain an image
+    ///    of this sample type.
+    pub fn read_tiff<R: Read + Seek>(reader: R) -> ImageResult<Self> {
+        TiffImage::read(reader)?.into_buffer()
+    }
+}
+
+impl DynamicSerialImage {
+    /// Save the image data to a TIFF file at `path`, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn save_tiff(&self, path: &Path) -> ImageResult<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.save_tiff(path),
+            DynamicSerialImage::U16(value) => value.save_tiff(path),
+            DynamicSerialImage::F32(value) => value.save_tiff(path),
+        }
+    }
+
+    /// Write the image data as a TIFF image, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn write_tiff<W: Write + Seek>(&self, writer: W) -> ImageResult<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_tiff(writer),
+            DynamicSerialImage::U16(value) => value.write_tiff(writer),
+            DynamicSerialImage::F32(value) => value.write_tiff(writer),
+        }
+    }
+
+    /// Open a TIFF image. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn open_tiff(path: &Path) -> ImageResult<Self> {
+        let file = File::open(path).map_err(ImageError::IoError)?;
+        Self::read_tiff(BufReader::new(file))
+    }
+
+    /// Read a TIFF image. The sample type is determined from the file.
+    ///
+    /// 8-bit and 16-bit unsigned integer images are read as [`u8`] and [`u16`] images, and
+    /// 32-bit and 64-bit floating point images as [`f32`] images.
+    /// See [`SerialImageBuffer::read_tiff`] for details.
+    ///
+    /// # Errors
+    ///  * [`ImageError`] with the error description.
+    pub fn read_tiff<R: Read + Seek>(reader: R) -> ImageResult<Self> {
+        let img = TiffImage::read(reader)?;
+        match img.data {
+            DecodingResult::U8(_) => Ok(img.into_buffer::<u8>()?.into()),
+            DecodingResult::U16(_) => Ok(img.into_buffer::<u16>()?.into()),
+            DecodingResult::F32(_) => Ok(img.into_buffer::<f32>()?.into()),
+            _ => Err(decoding_error("Unsupported TIFF sample type")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_tiff() {
+        let mut meta = ImageMetaData::full_builder(
+            2,
+            2,
+            10,
+            20,
+            -10.5,
+            Duration::from_micros(1_500_250),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            "Caméra",
+            100,
+            10,
+            0,
+            400,
+        );
+        meta.add_extended_attrib("FILTER", "Hα");
+        meta.add_extended_attrib("OBJECT", "M 31 🌌");
+        let mut images: Vec<DynamicSerialImage> = vec![
+            SerialImageBuffer::from_vec(3, 2, (0..6u16).map(|x| x * 10000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(3, 2, (0..12u16).map(|x| x * 5000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(2, 2, (0..16).map(|x| x as f32 * 0.25 - 1.).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(2, 1, vec![0u8, 128, 255, 64, 32, 16])
+                .unwrap()
+                .into(),
+        ];
+        for (idx, img) in images.iter_mut().enumerate() {
+            img.set_metadata(meta.clone());
+            let mut bytes = Cursor::new(Vec::new());
+            img.write_tiff(&mut bytes).unwrap();
+            bytes.set_position(0);
+            assert_eq!(&DynamicSerialImage::read_tiff(&mut bytes).unwrap(), img);
+            // The image crate reads the same file, except for grayscale images with alpha
+            if idx != 1 {
+                let decoded =
+                    image::load_from_memory_with_format(bytes.get_ref(), ImageFormat::Tiff);
+                assert_eq!(decoded.unwrap().width(), img.width() as u32);
+            }
+            // Non-ASCII characters are replaced in the standard tags
+            bytes.set_position(0);
+            let mut decoder = Decoder::new(&mut bytes).unwrap();
+            assert_eq!(decoder.get_tag_ascii_string(Tag::Model).unwrap(), "Cam?ra");
+        }
+
+        // Metadata from the standard tags
+        let mut bytes = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+            let mut image = encoder.new_image::<colortype::Gray8>(1, 1).unwrap();
+            image.encoder().write_tag(Tag::Model, "Other").unwrap();
+            image
+                .encoder()
+                .write_tag(Tag::DateTime, "2023:11:14 22:13:20")
+                .unwrap();
+            image.write_data(&[7]).unwrap();
+        }
+        bytes.set_position(0);
+        let img = SerialImageBuffer::<u8>::read_tiff(&mut bytes).unwrap();
+        let meta = img.get_metadata().unwrap();
+        assert_eq!(meta.camera_name, "Other");
+        assert_eq!(
+            meta.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        bytes.set_position(0);
+        assert!(SerialImageBuffer::<u16>::read_tiff(&mut bytes).is_err());
+
+        // Sub-second digits past nanoseconds are dropped, anything else is ignored
+        let date = "2023:11:14 22:13:20";
+        let base = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            parse_tiff_date(date, Some("25")),
+            Some(base + Duration::from_millis(250))
+        );
+        assert_eq!(
+            parse_tiff_date(date, Some("1234567891")),
+            Some(base + Duration::from_nanos(123_456_789))
+        );
+        assert_eq!(parse_tiff_date(date, Some("1e30")), Some(base));
+        assert_eq!(parse_tiff_date(date, Some("-5")), Some(base));
+    }
+}
@@ -0,0 +1,342 @@
+#![warn(missing_docs)]
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{
+    stream::{compress_block, decode_samples, invalid_data, invalid_input},
+    DynamicSerialImage, SampleType, SerialImageBuffer, StreamHeader, StreamOptions,
+    StreamPrimitive, StreamReader,
+};
+
+impl<T: StreamPrimitive> SerialImageBuffer<T> {
+    /// Write the image with its metadata in the tiled variant of the binary format of this crate,
+    /// with the tile size and compression of the [`StreamOptions`].
+    ///
+    /// Each channel is split into tiles, which are compressed independently, and the lengths of the
+    /// tiles are stored in an index after the header, so that [`SerialImageBuffer::read_region`] reads
+    /// only the tiles covering a region. The writer must be seekable to fill in the index once the
+    /// tiles are written. Tiled images are also read by [`SerialImageBuffer::read_stream`].
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::Unsupported`] if compression is requested without the `compression` feature.
+    ///  * Any error of the writer.
+    pub fn write_tiled<W: Write + Seek>(
+        &self,
+        mut writer: W,
+        options: &StreamOptions,
+    ) -> io::Result<()> {
+        let header = StreamHeader {
+            width: self.width(),
+            height: self.height(),
+            sample_type: T::SAMPLE_TYPE,
+            pixel_elems: self.pixel_elems(),
+            band_rows: options.tile_height.min(self.height()),
+            tile_width: Some(options.tile_width.min(self.width())),
+            compression: options.compression,
+            meta: self.get_metadata(),
+        };
+        header.write(&mut writer)?;
+        let (tiles_x, tiles_y) = header.tiles();
+        let index_start = writer.stream_position()?;
+        let mut index = vec![0u8; tiles_x * tiles_y * self.channels().len() * 8];
+        writer.write_all(&index)?;
+
+        let mut tile = Vec::new();
+        let mut buf = Vec::new();
+        let mut entries = index.chunks_exact_mut(8);
+        for ty in 0..tiles_y {
+            let top = ty * header.band_rows;
+            let rows = header.rows_at(top);
+            for tx in 0..tiles_x {
+                let columns = header.tile_columns(tx);
+                for &channel in self.channels() {
+                    let data = self.channel_data(channel).as_ref().unwrap();
+                    tile.clear();
+                    for row in top..top + rows {
+                        tile.extend_from_slice(
+                            &data[row * self.width() + columns.start
+                                ..row * self.width() + columns.end],
+                        );
+                    }
+                    buf.clear();
+                    T::write_le(&tile, &mut buf);
+                    let block = compress_block(header.compression, &buf)?;
+                    writer.write_all(&block)?;
+                    entries
+                        .next()
+                        .unwrap()
+                        .copy_from_slice(&(block.len() as u64).to_le_bytes());
+                }
+            }
+        }
+
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(index_start))?;
+        writer.write_all(&index)?;
+        writer.seek(SeekFrom::Start(end))?;
+        writer.flush()
+    }
+
+    /// Read the region of `width` x `height` pixels at (`x`, `y`) of an image written by
+    /// [`SerialImageBuffer::write_tiled`], decoding only the tiles covering the region.
+    ///
+    /// Images stored in row bands, written by [`SerialImageBuffer::write_stream`], are also
+    /// accepted, and are read up to the last band covering the region. The origin of the region
+    /// is added to `img_left` and `img_top` of the metadata.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if `T` is not the sample type of the image, or the region
+    ///    is empty or not inside the image.
+    ///  * [`io::ErrorKind::InvalidData`] if the data is invalid.
+    ///  * Any error of the reader.
+    pub fn read_region<R: Read + Seek>(
+        mut reader: R,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        let mut stream = StreamReader::new(&mut reader)?;
+        let header = stream.header().clone();
+        if T::SAMPLE_TYPE != header.sample_type {
+            return Err(invalid_input("Sample type must match the header"));
+        }
+        let inside = |start: usize, len: usize, size: usize| {
+            len > 0 && matches!(start.checked_add(len), Some(end) if end <= size)
+        };
+        if !inside(x, width, header.width) || !inside(y, height, header.height) {
+            return Err(invalid_input(
+                "Region must be non-empty and inside the image",
+            ));
+        }
+        let elems = header.pixel_elems as usize;
+        let mut planes = vec![vec![T::DEFAULT_MIN_VALUE; width * height]; elems];
+        // Copy the part of a tile or band at (`left`, `top`) of the image covered by the region
+        let copy = |tile: &[T], plane: &mut [T], left: usize, top: usize, tile_width: usize| {
+            let rows = tile.len() / tile_width;
+            let (x0, x1) = (x.max(left), (x + width).min(left + tile_width));
+            let (y0, y1) = (y.max(top), (y + height).min(top + rows));
+            for row in y0..y1 {
+                let src = (row - top) * tile_width;
+                let dst = (row - y) * width;
+                plane[dst + x0 - x..dst + x1 - x]
+                    .copy_from_slice(&tile[src + x0 - left..src + x1 - left]);
+            }
+        };
+
+        match header.tile_width {
+            Some(tile_width) => {
+                let mut offsets = Vec::with_capacity(stream.index.len());
+                let mut offset = Some(start + stream.data_offset);
+                for &len in &stream.index {
+                    let current = offset.ok_or_else(|| invalid_data("Invalid tile index"))?;
+                    offsets.push(current);
+                    offset = current.checked_add(len);
+                }
+                let (tiles_x, _) = header.tiles();
+                let mut block = Vec::new();
+                for ty in y / header.band_rows..=(y + height - 1) / header.band_rows {
+                    let top = ty * header.band_rows;
+                    let rows = header.rows_at(top);
+                    for tx in x / tile_width..=(x + width - 1) / tile_width {
+                        let columns = header.tile_columns(tx);
+                        for (ch, plane) in planes.iter_mut().enumerate() {
+                            let idx = (ty * tiles_x + tx) * elems + ch;
+                            let len = stream.index[idx];
+                            stream.reader.seek(SeekFrom::Start(offsets[idx]))?;
+                            block.clear();
+                            (&mut stream.reader).take(len).read_to_end(&mut block)?;
+                            if block.len() as u64 != len {
+                                return Err(io::ErrorKind::UnexpectedEof.into());
+                            }
+                            let tile: Vec<T> =
+                                decode_samples(header.compression, &block, rows * columns.len())?;
+                            copy(&tile, plane, columns.start, top, columns.len());
+                        }
+                    }
+                }
+            }
+            None => {
+                while stream.rows_read() < y + height {
+                    let top = stream.rows_read();
+                    let band = stream
+                        .read_band::<T>()?
+                        .ok_or_else(|| invalid_data("Truncated pixel data"))?;
+                    if top + band.height() <= y {
+                        continue;
+                    }
+                    for (&channel, plane) in band.channels().iter().zip(planes.iter_mut()) {
+                        let data = band.channel_data(channel).as_ref().unwrap();
+                        copy(data, plane, 0, top, header.width);
+                    }
+                }
+            }
+        }
+
+        let meta = header.meta.map(|mut meta| {
+            meta.img_left = meta.img_left.saturating_add(x as u32);
+            meta.img_top = meta.img_top.saturating_add(y as u32);
+            meta
+        });
+        SerialImageBuffer::from_planes(width, height, planes, meta).map_err(invalid_data)
+    }
+}
+
+impl DynamicSerialImage {
+    /// Write the image with its metadata in the tiled variant of the binary format of this crate.
+    /// See [`SerialImageBuffer::write_tiled`].
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::Unsupported`] if compression is requested without the `compression` feature.
+    ///  * Any error of the writer.
+    pub fn write_tiled<W: Write + Seek>(
+        &self,
+        writer: W,
+        options: &StreamOptions,
+    ) -> io::Result<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_tiled(writer, options),
+            DynamicSerialImage::U16(value) => value.write_tiled(writer, options),
+            DynamicSerialImage::F32(value) => value.write_tiled(writer, options),
+        }
+    }
+
+    /// Read a region of an image in the binary format of this crate.
+    /// See [`SerialImageBuffer::read_region`].
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidInput`] if the region is empty or not inside the image.
+    ///  * [`io::ErrorKind::InvalidData`] if the data is invalid.
+    ///  * Any error of the reader.
+    pub fn read_region<R: Read + Seek>(
+        mut reader: R,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        let sample_type = StreamReader::new(&mut reader)?.header().sample_type;
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(match sample_type {
+            SampleType::U8 => {
+                SerialImageBuffer::<u8>::read_region(reader, x, y, width, height)?.into()
+            }
+            SampleType::U16 => {
+                SerialImageBuffer::<u16>::read_region(reader, x, y, width, height)?.into()
+            }
+            SampleType::F32 => {
+                SerialImageBuffer::<f32>::read_region(reader, x, y, width, height)?.into()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ImageMetaData, StreamCompression};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_tiled() {
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        let data: Vec<u16> = (0..23 * 17 * 2).map(|x| x * 31).collect();
+        let mut img = SerialImageBuffer::from_vec(23, 17, data).unwrap();
+        img.set_metadata(Some(meta));
+        let expected = |x: usize, y: usize, w: usize, h: usize| {
+            let planes = img
+                .channels()
+                .iter()
+                .map(|&ch| {
+                    let data = img.channel(ch).unwrap();
+                    (y..y + h)
+                        .flat_map(|row| data[row * 23 + x..row * 23 + x + w].to_vec())
+                        .collect()
+                })
+                .collect();
+            let mut meta = img.get_metadata().unwrap();
+            meta.img_left = x as u32;
+            meta.img_top = y as u32;
+            SerialImageBuffer::from_planes(w, h, planes, Some(meta)).unwrap()
+        };
+
+        let mut compressions = vec![StreamCompression::None];
+        if cfg!(feature = "compression") {
+            compressions.extend([StreamCompression::Zlib, StreamCompression::Lz4]);
+        }
+        for compression in compressions {
+            let options = StreamOptions::new()
+                .tile_size(8, 5)
+                .band_rows(4)
+                .compression(compression);
+            let mut tiled = Cursor::new(Vec::new());
+            img.write_tiled(&mut tiled, &options).unwrap();
+            let mut bands = Vec::new();
+            img.write_stream(&mut bands, &options).unwrap();
+            for source in [tiled.into_inner(), bands] {
+                assert_eq!(
+                    SerialImageBuffer::<u16>::read_stream(&source[..]).unwrap(),
+                    img
+                );
+                for (x, y, w, h) in [(0, 0, 23, 17), (3, 4, 9, 7), (22, 16, 1, 1), (8, 5, 8, 5)] {
+                    let region =
+                        SerialImageBuffer::<u16>::read_region(Cursor::new(&source), x, y, w, h)
+                            .unwrap();
+                    assert_eq!(region, expected(x, y, w, h));
+                }
+                assert!(
+                    DynamicSerialImage::read_region(Cursor::new(&source), 20, 0, 4, 1).is_err()
+                );
+                assert!(
+                    SerialImageBuffer::<u8>::read_region(Cursor::new(&source), 0, 0, 1, 1).is_err()
+                );
+            }
+        }
+        #[cfg(not(feature = "compression"))]
+        assert_eq!(
+            img.write_tiled(
+                Cursor::new(Vec::new()),
+                &StreamOptions::new().compression(StreamCompression::Zlib)
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn test_tiled_invalid() {
+        let mut img = SerialImageBuffer::from_vec(4, 4, vec![1u8; 16]).unwrap();
+        let options = StreamOptions::new().tile_size(2, 2);
+        let mut tiled = Cursor::new(Vec::new());
+        img.write_tiled(&mut tiled, &options).unwrap();
+        let mut bytes = tiled.into_inner();
+        let read = |bytes: &[u8], x, y, w, h| {
+            SerialImageBuffer::<u8>::read_region(Cursor::new(bytes), x, y, w, h)
+        };
+
+        // Regions whose end overflows
+        for (x, y) in [(usize::MAX, 0), (0, usize::MAX)] {
+            let err = read(&bytes, x, y, 2, 2).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        // Tile lengths whose offsets overflow
+        bytes[36..44].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = read(&bytes, 2, 2, 2, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // The origin of the region saturates
+        let mut meta = ImageMetaData::default();
+        meta.img_left = u32::MAX;
+        img.set_metadata(Some(meta));
+        let mut tiled = Cursor::new(Vec::new());
+        img.write_tiled(&mut tiled, &options).unwrap();
+        let region = read(tiled.get_ref(), 1, 1, 2, 2).unwrap();
+        let meta = region.get_metadata().unwrap();
+        assert_eq!((meta.img_left, meta.img_top), (u32::MAX, 1));
+    }
+}
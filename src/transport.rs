@@ -0,0 +1,412 @@
+#![warn(missing_docs)]
+
+use std::io::{self, Read, Write};
+
+use crate::{stream::invalid_data, DynamicSerialImage, StreamOptions, StreamReader};
+
+/// Signature at the start of each frame.
+const FRAME_MAGIC: &[u8; 2] = b"SI";
+
+/// Version of the frame format.
+const FRAME_VERSION: u8 = 1;
+
+/// Length of the frame header: signature, version, message type and payload length.
+const FRAME_HEADER_LEN: usize = 12;
+
+/// Default maximum payload length accepted by [`FrameReader`], 1 GiB.
+const DEFAULT_MAX_FRAME_LEN: usize = 1 << 30;
+
+/// Maximum number of bytes reserved at once by [`FrameCodec`] for an incomplete frame, so that
+/// the length in a header does not allocate the whole payload before it is received.
+#[cfg(feature = "tokio")]
+const MAX_RESERVE_LEN: usize = 64 << 10;
+
+/// A message carrying an image, sent with [`FrameWriter`] and received with [`FrameReader`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Application-defined type of the message
+    pub message_type: u8,
+    /// Image carried by the message, with its metadata
+    pub image: DynamicSerialImage,
+}
+
+impl Frame {
+    /// Create a frame carrying an image.
+    pub fn new(message_type: u8, image: DynamicSerialImage) -> Self {
+        Self {
+            message_type,
+            image,
+        }
+    }
+}
+
+/// Encode a frame: the header followed by the image in the binary format of this crate.
+fn encode_frame(
+    message_type: u8,
+    image: &DynamicSerialImage,
+    options: &StreamOptions,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    let start = out.len();
+    out.extend_from_slice(FRAME_MAGIC);
+    out.push(FRAME_VERSION);
+    out.push(message_type);
+    out.extend_from_slice(&[0; 8]);
+    image.write_stream(&mut *out, options)?;
+    let len = (out.len() - start - FRAME_HEADER_LEN) as u64;
+    out[start + 4..start + FRAME_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Decode a frame header, returning the message type and the payload length.
+fn decode_header(header: &[u8], max_len: usize) -> io::Result<(u8, usize)> {
+    if &header[..2] != FRAME_MAGIC {
+        return Err(invalid_data("Invalid frame signature"));
+    }
+    if header[2] != FRAME_VERSION {
+        return Err(invalid_data("Unsupported frame version"));
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[4..FRAME_HEADER_LEN]);
+    let len = u64::from_le_bytes(len);
+    if len > max_len as u64 {
+        return Err(invalid_data("Frame exceeds the maximum length"));
+    }
+    Ok((header[3], len as usize))
+}
+
+/// Decode the image of a payload of `len` bytes, checking the image dimensions against the
+/// payload length before decoding the pixel data.
+fn decode_payload<R: Read>(payload: R, len: usize) -> io::Result<DynamicSerialImage> {
+    let mut stream = StreamReader::new(payload)?;
+    stream.header().check_stored_len(len)?;
+    stream.read_dynamic()
+}
+
+/// Writer of length-prefixed, versioned frames carrying images, e.g. over a TCP or Unix socket.
+///
+/// Each frame holds a 12-byte header (signature `SI`, format version, message type and payload
+/// length as a little-endian `u64`), followed by the image in the binary format of this crate
+/// (see [`DynamicSerialImage::write_stream`]).
+pub struct FrameWriter<W: Write> {
+    writer: W,
+    options: StreamOptions,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Create a frame writer, with the default [`StreamOptions`].
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            options: StreamOptions::default(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Set the [`StreamOptions`] of the images, e.g. to compress them.
+    pub fn options(mut self, options: StreamOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Write a frame carrying `image` and flush the writer.
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    pub fn write_frame(&mut self, message_type: u8, image: &DynamicSerialImage) -> io::Result<()> {
+        self.buf.clear();
+        encode_frame(message_type, image, &self.options, &mut self.buf)?;
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reader of the frames written by [`FrameWriter`].
+pub struct FrameReader<R: Read> {
+    reader: R,
+    max_len: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Create a frame reader, accepting payloads of up to 1 GiB.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            max_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    /// Set the maximum payload length, in bytes. Longer frames are rejected before they are read.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_len = len;
+        self
+    }
+
+    /// Read the next frame, or `None` if the stream ends before a frame.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the frame is invalid or too long, or if the image
+    ///    dimensions exceed what the payload can hold.
+    ///  * [`io::ErrorKind::UnexpectedEof`] if the stream ends inside a frame.
+    ///  * Any error of the reader.
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        let mut filled = 0;
+        while filled < FRAME_HEADER_LEN {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let (message_type, len) = decode_header(&header, self.max_len)?;
+        // The image is decoded from the payload as it is read
+        let mut payload = (&mut self.reader).take(len as u64);
+        let image = decode_payload(&mut payload, len)?;
+        if payload.limit() > 0 {
+            return Err(invalid_data("Frame payload has trailing data"));
+        }
+        Ok(Some(Frame::new(message_type, image)))
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// [`tokio_util::codec`] encoder and decoder of the frames written by [`FrameWriter`],
+/// for use with e.g. [`tokio_util::codec::Framed`].
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    options: StreamOptions,
+    max_len: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            options: StreamOptions::default(),
+            max_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl FrameCodec {
+    /// Create a codec with the default [`StreamOptions`], accepting payloads of up to 1 GiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the [`StreamOptions`] of the encoded images.
+    pub fn options(mut self, options: StreamOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the maximum payload length of decoded frames, in bytes.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_len = len;
+        self
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio_util::codec::Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> io::Result<Option<Frame>> {
+        if src.len() < FRAME_HEADER_LEN {
+            src.reserve(FRAME_HEADER_LEN - src.len());
+            return Ok(None);
+        }
+        let (message_type, len) = decode_header(&src[..FRAME_HEADER_LEN], self.max_len)?;
+        let frame_len = FRAME_HEADER_LEN + len;
+        if src.len() < frame_len {
+            src.reserve((frame_len - src.len()).min(MAX_RESERVE_LEN));
+            return Ok(None);
+        }
+        let frame = src.split_to(frame_len);
+        let mut payload = &frame[FRAME_HEADER_LEN..];
+        let image = decode_payload(&mut payload, len)?;
+        if !payload.is_empty() {
+            return Err(invalid_data("Frame payload has trailing data"));
+        }
+        Ok(Some(Frame::new(message_type, image)))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio_util::codec::Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut bytes::BytesMut) -> io::Result<()> {
+        let mut buf = Vec::new();
+        encode_frame(frame.message_type, &frame.image, &self.options, &mut buf)?;
+        dst.extend_from_slice(&buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ImageMetaData, SerialImageBuffer};
+
+    fn image(value: u16) -> DynamicSerialImage {
+        let mut img: DynamicSerialImage = SerialImageBuffer::from_vec(5, 4, vec![value; 60])
+            .unwrap()
+            .into();
+        let mut meta = ImageMetaData::default();
+        meta.camera_name = "Camera".to_owned();
+        img.set_metadata(meta);
+        img
+    }
+
+    #[test]
+    fn test_frames() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = std::thread::spawn(move || {
+            let mut writer = FrameWriter::new(std::net::TcpStream::connect(addr).unwrap());
+            for i in 0..3 {
+                writer.write_frame(i, &image(i as u16 * 1000)).unwrap();
+            }
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let frames = FrameReader::new(stream)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        sender.join().unwrap();
+        assert_eq!(frames.len(), 3);
+        for (i, frame) in frames.into_iter().enumerate() {
+            assert_eq!(frame, Frame::new(i as u8, image(i as u16 * 1000)));
+        }
+
+        let mut bytes = FrameWriter::new(Vec::new());
+        bytes.write_frame(7, &image(1)).unwrap();
+        let bytes = bytes.into_inner();
+        assert!(FrameReader::new(&bytes[..bytes.len() - 1])
+            .read_frame()
+            .is_err());
+        assert!(FrameReader::new(&bytes[..])
+            .max_frame_len(16)
+            .read_frame()
+            .is_err());
+        let mut invalid = bytes.clone();
+        invalid[2] = 2;
+        assert!(FrameReader::new(&invalid[..]).read_frame().is_err());
+
+        // A small frame claiming a large image is rejected before the pixel data is read
+        let mut forged = bytes.clone();
+        forged[28..36].copy_from_slice(&[0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0]);
+        let err = FrameReader::new(&forged[..]).read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_codec() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_util::codec::Framed;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = tokio::spawn(async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let mut framed = Framed::new(stream, FrameCodec::new());
+            for i in 0..3 {
+                framed
+                    .send(Frame::new(i, image(i as u16 * 1000)))
+                    .await
+                    .unwrap();
+            }
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec::new());
+        for i in 0..3 {
+            let frame = framed.next().await.unwrap().unwrap();
+            assert_eq!(frame, Frame::new(i, image(i as u16 * 1000)));
+        }
+        assert!(framed.next().await.is_none());
+        sender.await.unwrap();
+
+        // Frames written by FrameWriter are decoded by the codec
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write_frame(9, &image(5)).unwrap();
+        let mut bytes = bytes::BytesMut::from(&writer.into_inner()[..]);
+        let mut codec = FrameCodec::new();
+        let mut partial = bytes.split_to(20);
+        assert!(tokio_util::codec::Decoder::decode(&mut codec, &mut partial)
+            .unwrap()
+            .is_none());
+        partial.unsplit(bytes);
+        let frame = tokio_util::codec::Decoder::decode(&mut codec, &mut partial)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame, Frame::new(9, image(5)));
+        assert!(partial.is_empty());
+
+        // The length of an incomplete frame does not reserve its whole payload
+        let mut header = bytes::BytesMut::from(&FRAME_MAGIC[..]);
+        header.extend_from_slice(&[FRAME_VERSION, 9]);
+        header.extend_from_slice(&(1u64 << 29).to_le_bytes());
+        assert!(tokio_util::codec::Decoder::decode(&mut codec, &mut header)
+            .unwrap()
+            .is_none());
+        assert!(header.capacity() <= FRAME_HEADER_LEN + MAX_RESERVE_LEN * 2);
+
+        // Invalid frames: a forged image size, and trailing data after the image
+        let decode = |bytes: &[u8]| {
+            tokio_util::codec::Decoder::decode(&mut FrameCodec::new(), &mut bytes.into())
+        };
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write_frame(9, &image(5)).unwrap();
+        let bytes = writer.into_inner();
+        let mut forged = bytes.clone();
+        forged[28..36].copy_from_slice(&[0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0]);
+        assert_eq!(
+            decode(&forged).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut trailing = bytes;
+        trailing.push(0);
+        let len = (trailing.len() - FRAME_HEADER_LEN) as u64;
+        trailing[4..FRAME_HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+        assert_eq!(
+            decode(&trailing).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,950 @@
+#![warn(missing_docs)]
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    mem,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use roxmltree::{Document, Node};
+
+use crate::{
+    fitscommon::{header_cards, Header},
+    imagemetadata::utc_datetime,
+    DynamicSerialImage, ImageMetaData, PixelArithmetic, SerialImageBuffer,
+};
+
+/// Signature of monolithic XISF 1.0 files.
+const SIGNATURE: &[u8; 8] = b"XISF0100";
+
+/// Length of the signature, the header length and the reserved field.
+const PREAMBLE_LEN: usize = 16;
+
+/// Id of the property holding the image metadata as JSON.
+const METADATA_PROPERTY: &str = "SerialImage:MetaData";
+
+/// Compression codec of the data block of XISF images.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum XisfCompression {
+    /// The data block is not compressed.
+    #[default]
+    None,
+    /// zlib compression (codec `zlib`), with the default compression level.
+    Zlib,
+    /// LZ4 compression (codec `lz4`), faster but usually less compact than zlib.
+    Lz4,
+}
+
+/// Options for writing XISF files.
+///
+/// The default values are:
+/// * `compression` - [`XisfCompression::None`]
+/// * `byte_shuffle` - `false`
+/// * `progname` - `None`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct XisfOptions {
+    pub(crate) compression: XisfCompression,
+    pub(crate) byte_shuffle: bool,
+    pub(crate) progname: Option<String>,
+}
+
+impl XisfOptions {
+    /// Create the default XISF options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the compression codec of the data block, see [`XisfCompression`].
+    pub fn compression(mut self, compression: XisfCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Shuffle the bytes of the samples before compression, which usually compresses
+    /// 16-bit and floating point images better. Ignored for uncompressed and 8-bit images.
+    pub fn byte_shuffle(mut self, byte_shuffle: bool) -> Self {
+        self.byte_shuffle = byte_shuffle;
+        self
+    }
+
+    /// Set the name of the program that generated the image, written to the `PROGRAM` keyword.
+    pub fn progname(mut self, progname: &str) -> Self {
+        self.progname = Some(progname.to_owned());
+        self
+    }
+}
+
+/// Sample types that can be stored in and read from XISF images.
+pub trait XisfPrimitive: PixelArithmetic {
+    /// `sampleFormat` of the XISF images holding the samples.
+    const SAMPLE_FORMAT: &'static str;
+
+    /// Append the samples to a data block, in little-endian order.
+    #[doc(hidden)]
+    fn write_le(data: &[Self], out: &mut Vec<u8>);
+
+    /// Convert the bytes of a data block to samples.
+    #[doc(hidden)]
+    fn from_bytes(data: &[u8], big_endian: bool) -> Vec<Self>;
+
+    /// `(lower, upper)` bounds of floating point samples, `None` for integer samples.
+    #[doc(hidden)]
+    fn bounds(_data: &[Self]) -> Option<(f32, f32)> {
+        None
+    }
+}
+
+impl XisfPrimitive for u8 {
+    const SAMPLE_FORMAT: &'static str = "UInt8";
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        out.extend_from_slice(data);
+    }
+
+    fn from_bytes(data: &[u8], _big_endian: bool) -> Vec<Self> {
+        data.to_vec()
+    }
+}
+
+impl XisfPrimitive for u16 {
+    const SAMPLE_FORMAT: &'static str = "UInt16";
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        for x in data {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    fn from_bytes(data: &[u8], big_endian: bool) -> Vec<Self> {
+        data.chunks_exact(2)
+            .map(|b| {
+                let b = [b[0], b[1]];
+                if big_endian {
+                    u16::from_be_bytes(b)
+                } else {
+                    u16::from_le_bytes(b)
+                }
+            })
+            .collect()
+    }
+}
+
+impl XisfPrimitive for f32 {
+    const SAMPLE_FORMAT: &'static str = "Float32";
+
+    fn write_le(data: &[Self], out: &mut Vec<u8>) {
+        for x in data {
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+    }
+
+    fn from_bytes(data: &[u8], big_endian: bool) -> Vec<Self> {
+        data.chunks_exact(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                if big_endian {
+                    f32::from_be_bytes(b)
+                } else {
+                    f32::from_le_bytes(b)
+                }
+            })
+            .collect()
+    }
+
+    fn bounds(data: &[Self]) -> Option<(f32, f32)> {
+        // Normalized images use the conventional [0, 1] range
+        let (min, max) = data
+            .iter()
+            .filter(|x| x.is_finite())
+            .fold((0f32, 1f32), |(min, max), &x| (min.min(x), max.max(x)));
+        Some((min, max))
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, msg)
+}
+
+impl<T: XisfPrimitive> SerialImageBuffer<T> {
+    /// Save the image data to an XISF file at `path`. See [`SerialImageBuffer::write_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn save_xisf(&self, path: &Path, options: &XisfOptions) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_xisf(&mut writer, options)?;
+        writer.flush()
+    }
+
+    /// Write the image data as a monolithic XISF file, with the specified [`XisfOptions`].
+    ///
+    /// The channels are stored in a single planar data block (`Gray` or `RGB` color space, the alpha
+    /// channel as an additional channel), optionally compressed. The image metadata is stored with
+    /// the standard XISF properties (`Observation:Time:Start`, `Instrument:ExposureTime`,
+    /// `Instrument:Camera:Name`, `Instrument:Sensor:Temperature` and `Instrument:Camera:XBinning`/`YBinning`),
+    /// the same FITS keywords as the FITS files written by this crate, and as JSON in the
+    /// `SerialImage:MetaData` property, so that the extended attributes are preserved.
+    ///
+    /// # Errors
+    ///  * Any error of the writer.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn write_xisf<W: Write>(&self, mut writer: W, options: &XisfOptions) -> io::Result<()> {
+        let channels = self.channels();
+        let mut data = Vec::new();
+        let mut bounds = None;
+        for &channel in channels {
+            let plane = self.channel_data(channel).as_ref().unwrap();
+            T::write_le(plane, &mut data);
+            bounds = match (bounds, T::bounds(plane)) {
+                (Some((lo, hi)), Some((min, max))) => Some((f32::min(lo, min), f32::max(hi, max))),
+                (_, new) => new,
+            };
+        }
+        let (block, compression) = compress_block(data, options, mem::size_of::<T>())?;
+
+        let mut attributes = format!(
+            "geometry=\"{}:{}:{}\" sampleFormat=\"{}\" colorSpace=\"{}\"",
+            self.width(),
+            self.height(),
+            channels.len(),
+            T::SAMPLE_FORMAT,
+            if channels.len() < 3 { "Gray" } else { "RGB" },
+        );
+        if let Some((lo, hi)) = bounds {
+            attributes.push_str(&format!(" bounds=\"{:?}:{:?}\"", lo, hi));
+        }
+        if let Some(compression) = compression {
+            attributes.push_str(&format!(" compression=\"{}\"", compression));
+        }
+        let properties = image_properties(
+            channels.len(),
+            self.get_metadata().as_ref(),
+            options.progname.as_deref(),
+        )?;
+        // The header holds the position of the data block, which follows the header
+        let mut position = 0;
+        let header = loop {
+            let header = xml_header(&attributes, position, block.len(), &properties);
+            if PREAMBLE_LEN + header.len() == position {
+                break header;
+            }
+            position = PREAMBLE_LEN + header.len();
+        };
+        writer.write_all(SIGNATURE)?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&[0; 4])?;
+        writer.write_all(header.as_bytes())?;
+        writer.write_all(&block)
+    }
+
+    /// Open an XISF file. See [`SerialImageBuffer::read_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn open_xisf(path: &Path) -> io::Result<Self> {
+        XisfImage::parse(&fs::read(path)?)?.into_buffer()
+    }
+
+    /// Read the first image of a monolithic XISF file.
+    ///
+    /// Uncompressed, `zlib`, `lz4` and `lz4hc` compressed data blocks, with or without byte
+    /// shuffling, are accepted, in planar or normal (interleaved) pixel storage. The image metadata
+    /// is restored from the `SerialImage:MetaData` property written by [`SerialImageBuffer::write_xisf`],
+    /// or else from the FITS keywords, or else from the standard XISF properties.
+    ///
+    /// # Errors
+    ///  * [`io::ErrorKind::InvalidData`] if the data is not an XISF file, or does not contain
+    ///    an image of this sample type.
+    ///  * [`io::ErrorKind::Unsupported`] for inline or external data blocks, `zstd` compression,
+    ///    and images with more than two dimensions or four channels.
+    ///  * Any error of the reader.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn read_xisf<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        XisfImage::parse(&bytes)?.into_buffer()
+    }
+}
+
+impl DynamicSerialImage {
+    /// Save the image data to an XISF file at `path`, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn save_xisf(&self, path: &Path, options: &XisfOptions) -> io::Result<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.save_xisf(path, options),
+            DynamicSerialImage::U16(value) => value.save_xisf(path, options),
+            DynamicSerialImage::F32(value) => value.save_xisf(path, options),
+        }
+    }
+
+    /// Write the image data as a monolithic XISF file, preserving the image metadata.
+    ///
+    /// See [`SerialImageBuffer::write_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn write_xisf<W: Write>(&self, writer: W, options: &XisfOptions) -> io::Result<()> {
+        match self {
+            DynamicSerialImage::U8(value) => value.write_xisf(writer, options),
+            DynamicSerialImage::U16(value) => value.write_xisf(writer, options),
+            DynamicSerialImage::F32(value) => value.write_xisf(writer, options),
+        }
+    }
+
+    /// Open an XISF file. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn open_xisf(path: &Path) -> io::Result<Self> {
+        XisfImage::parse(&fs::read(path)?)?.into_dynamic()
+    }
+
+    /// Read the first image of a monolithic XISF file. The sample type is determined from the file.
+    ///
+    /// See [`SerialImageBuffer::read_xisf`] for details.
+    ///
+    /// # Errors
+    ///  * [`io::Error`] with the error description.
+    #[cfg_attr(docsrs, doc(cfg(feature = "xisf")))]
+    pub fn read_xisf<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        XisfImage::parse(&bytes)?.into_dynamic()
+    }
+}
+
+/// Compress a data block, returning the `compression` attribute of compressed blocks.
+fn compress_block(
+    data: Vec<u8>,
+    options: &XisfOptions,
+    item_size: usize,
+) -> io::Result<(Vec<u8>, Option<String>)> {
+    let codec = match options.compression {
+        XisfCompression::None => return Ok((data, None)),
+        XisfCompression::Zlib => "zlib",
+        XisfCompression::Lz4 => "lz4",
+    };
+    let size = data.len();
+    let (data, attribute) = if options.byte_shuffle && item_size > 1 {
+        (
+            shuffle(&data, item_size),
+            format!("{}+sh:{}:{}", codec, size, item_size),
+        )
+    } else {
+        (data, format!("{}:{}", codec, size))
+    };
+    let block = match options.compression {
+        XisfCompression::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            encoder.finish()?
+        }
+        _ => lz4_flex::block::compress(&data),
+    };
+    Ok((block, Some(attribute)))
+}
+
+/// Build the properties and FITS keywords of the `Image` element.
+fn image_properties(
+    channels: usize,
+    meta: Option<&ImageMetaData>,
+    progname: Option<&str>,
+) -> io::Result<String> {
+    let mut xml = String::new();
+    if let Some(meta) = meta {
+        let mut property = |id: &str, kind: &str, value: &str| {
+            xml.push_str(&format!(
+                "<Property id=\"{}\" type=\"{}\" value=\"{}\"/>",
+                id,
+                kind,
+                escape(value)
+            ));
+        };
+        property(
+            "Observation:Time:Start",
+            "TimePoint",
+            &time_point(meta.timestamp),
+        );
+        property(
+            "Instrument:ExposureTime",
+            "Float32",
+            &format!("{:?}", meta.exposure.as_secs_f32()),
+        );
+        property(
+            "Instrument:Sensor:Temperature",
+            "Float32",
+            &format!("{:?}", meta.temperature),
+        );
+        property(
+            "Instrument:Camera:XBinning",
+            "Int32",
+            &meta.bin_x.to_string(),
+        );
+        property(
+            "Instrument:Camera:YBinning",
+            "Int32",
+            &meta.bin_y.to_string(),
+        );
+        let json = serde_json::to_string(meta)?;
+        for (id, value) in [
+            ("Instrument:Camera:Name", meta.camera_name.as_str()),
+            (METADATA_PROPERTY, &json),
+        ] {
+            xml.push_str(&format!(
+                "<Property id=\"{}\" type=\"String\">{}</Property>",
+                id,
+                escape(value)
+            ));
+        }
+    }
+    for card in header_cards(channels, meta, progname, false) {
+        xml.push_str(&format!(
+            "<FITSKeyword name=\"{}\" value=\"{}\" comment=\"{}\"/>",
+            escape(&card.key),
+            escape(&card.value.to_string()),
+            escape(card.comment)
+        ));
+    }
+    Ok(xml)
+}
+
+/// Build the XML header, with the data block at `position`.
+fn xml_header(attributes: &str, position: usize, size: usize, properties: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<xisf version=\"1.0\" xmlns=\"http://www.pixinsight.com/xisf\" ",
+            "xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" ",
+            "xsi:schemaLocation=\"http://www.pixinsight.com/xisf http://pixinsight.com/xisf/xisf-1.0.xsd\">",
+            "<Image {} location=\"attachment:{}:{}\">{}</Image>",
+            "<Metadata>",
+            "<Property id=\"XISF:CreationTime\" type=\"TimePoint\" value=\"{}\"/>",
+            "<Property id=\"XISF:CreatorApplication\" type=\"String\">serialimage {}</Property>",
+            "</Metadata>",
+            "</xisf>"
+        ),
+        attributes,
+        position,
+        size,
+        properties,
+        time_point(SystemTime::now()),
+        env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Format a timestamp as an XISF `TimePoint`.
+fn time_point(timestamp: SystemTime) -> String {
+    utc_datetime(timestamp.max(UNIX_EPOCH)).to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Decoded XISF image, with the samples of the data block.
+struct XisfImage {
+    width: usize,
+    height: usize,
+    channels: usize,
+    sample_format: String,
+    data: Vec<u8>,
+    planar: bool,
+    big_endian: bool,
+    meta: Option<ImageMetaData>,
+}
+
+impl XisfImage {
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() < PREAMBLE_LEN || &bytes[..8] != SIGNATURE {
+            return Err(invalid_data("Not a monolithic XISF file"));
+        }
+        let len = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let header = bytes
+            .get(PREAMBLE_LEN..PREAMBLE_LEN + len)
+            .ok_or_else(|| invalid_data("Truncated XISF header"))?;
+        let header = std::str::from_utf8(header)
+            .map_err(|_| invalid_data("XISF header is not valid UTF-8"))?;
+        // The header may be padded with null characters
+        let doc = Document::parse(header.trim_end_matches('\0'))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("xisf") {
+            return Err(invalid_data("Not a monolithic XISF file"));
+        }
+        let image = children(root, "Image")
+            .next()
+            .ok_or_else(|| invalid_data("XISF file does not contain an image"))?;
+        let attr = |name: &str| {
+            image
+                .attribute(name)
+                .ok_or_else(|| invalid_data("Missing attribute of the XISF image"))
+        };
+
+        let geometry = attr("geometry")?
+            .split(':')
+            .map(|x| x.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid_data("Invalid XISF image geometry"))?;
+        let (width, height, channels) = match geometry[..] {
+            [width, height, channels] if width > 0 && height > 0 => (width, height, channels),
+            [_, _, _, ..] => return Err(unsupported("Only two-dimensional images are supported")),
+            _ => return Err(invalid_data("Invalid XISF image geometry")),
+        };
+        let rgb = match image.attribute("colorSpace").unwrap_or("Gray") {
+            "Gray" => false,
+            "RGB" => true,
+            _ => return Err(unsupported("Unsupported XISF color space")),
+        };
+        // Channels beyond the nominal channels of the color space are alpha channels
+        if (rgb && !(3..=4).contains(&channels)) || (!rgb && !(1..=2).contains(&channels)) {
+            return Err(unsupported("Unsupported number of channels"));
+        }
+        let sample_format = attr("sampleFormat")?.to_owned();
+        let item_size = match sample_format.as_str() {
+            "UInt8" => 1,
+            "UInt16" => 2,
+            "Float32" => 4,
+            _ => return Err(unsupported("Unsupported XISF sample format")),
+        };
+        let planar = match image.attribute("pixelStorage").unwrap_or("Planar") {
+            "Planar" => true,
+            "Normal" => false,
+            _ => return Err(invalid_data("Invalid XISF pixel storage")),
+        };
+        let big_endian = image.attribute("byteOrder") == Some("big");
+        let size = width
+            .checked_mul(height)
+            .and_then(|x| x.checked_mul(channels))
+            .and_then(|x| x.checked_mul(item_size))
+            .ok_or_else(|| invalid_data("Invalid XISF image geometry"))?;
+
+        let block = data_block(bytes, attr("location")?)?;
+        let data = match image.attribute("compression") {
+            Some(compression) => {
+                decompress_block(block, compression, image.attribute("subblocks"), size)?
+            }
+            None => block.to_vec(),
+        };
+        if data.len() != size {
+            return Err(invalid_data(
+                "XISF data block does not match the image geometry",
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            channels,
+            sample_format,
+            data,
+            planar,
+            big_endian,
+            meta: read_metadata(image),
+        })
+    }
+
+    fn into_buffer<T: XisfPrimitive>(self) -> io::Result<SerialImageBuffer<T>> {
+        if self.sample_format != T::SAMPLE_FORMAT {
+            return Err(invalid_data("Image sample type does not match"));
+        }
+        let data = T::from_bytes(&self.data, self.big_endian);
+        let len = self.width * self.height;
+        let planes = if self.planar {
+            data.chunks_exact(len).map(|plane| plane.to_vec()).collect()
+        } else {
+            (0..self.channels)
+                .map(|ch| {
+                    data.iter()
+                        .skip(ch)
+                        .step_by(self.channels)
+                        .copied()
+                        .collect()
+                })
+                .collect()
+        };
+        SerialImageBuffer::from_planes(self.width, self.height, planes, self.meta)
+            .map_err(invalid_data)
+    }
+
+    fn into_dynamic(self) -> io::Result<DynamicSerialImage> {
+        match self.sample_format.as_str() {
+            "UInt8" => Ok(self.into_buffer::<u8>()?.into()),
+            "UInt16" => Ok(self.into_buffer::<u16>()?.into()),
+            _ => Ok(self.into_buffer::<f32>()?.into()),
+        }
+    }
+}
+
+/// Get the data block at an `attachment:position:size` location.
+fn data_block<'a>(bytes: &'a [u8], location: &str) -> io::Result<&'a [u8]> {
+    let mut parts = location.split(':');
+    match parts.next() {
+        Some("attachment") => {}
+        Some("inline") | Some("embedded") => {
+            return Err(unsupported(
+                "Inline and embedded XISF data blocks are not supported",
+            ))
+        }
+        _ => return Err(unsupported("External XISF data blocks are not supported")),
+    }
+    let mut number = || {
+        parts
+            .next()
+            .and_then(|x| x.trim().parse::<usize>().ok())
+            .ok_or_else(|| invalid_data("Invalid XISF data block location"))
+    };
+    let (position, size) = (number()?, number()?);
+    bytes
+        .get(position..position.saturating_add(size))
+        .ok_or_else(|| invalid_data("Truncated XISF data block"))
+}
+
+/// Upper bound of the compression ratio of LZ4 blocks.
+const LZ4_MAX_RATIO: usize = 256;
+
+/// Decompress a data block with a `codec:size[:item_size]` compression attribute, where
+/// `expected` is the size of the image data given by its geometry.
+fn decompress_block(
+    block: &[u8],
+    compression: &str,
+    subblocks: Option<&str>,
+    expected: usize,
+) -> io::Result<Vec<u8>> {
+    let invalid = || invalid_data("Invalid XISF compression attribute");
+    let mut parts = compression.split(':');
+    let codec = parts.next().ok_or_else(invalid)?;
+    let size: usize = parts
+        .next()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(invalid)?;
+    // The sizes are checked before allocating, so that a forged header can not request
+    // more memory than the image needs
+    if size != expected {
+        return Err(invalid_data(
+            "XISF data block does not match the image geometry",
+        ));
+    }
+    let (codec, item_size) = match codec.strip_suffix("+sh") {
+        Some(codec) => {
+            let item_size: usize = parts
+                .next()
+                .and_then(|x| x.parse().ok())
+                .filter(|&x| x > 0)
+                .ok_or_else(invalid)?;
+            (codec, Some(item_size))
+        }
+        None => (codec, None),
+    };
+    // Blocks larger than 4 GiB are compressed as independent sub-blocks
+    let subblocks = match subblocks {
+        Some(subblocks) => subblocks
+            .split(':')
+            .map(|pair| {
+                let (compressed, uncompressed) = pair.split_once(',').ok_or_else(invalid)?;
+                Ok((
+                    compressed.trim().parse().map_err(|_| invalid())?,
+                    uncompressed.trim().parse().map_err(|_| invalid())?,
+                ))
+            })
+            .collect::<io::Result<Vec<(usize, usize)>>>()?,
+        None => vec![(block.len(), size)],
+    };
+    let total = subblocks
+        .iter()
+        .try_fold(0usize, |total, &(_, uncompressed)| {
+            total.checked_add(uncompressed)
+        });
+    if total != Some(size) {
+        return Err(invalid_data("Invalid size of the decompressed data"));
+    }
+    let max_len = |compressed: usize| compressed.saturating_mul(LZ4_MAX_RATIO);
+    let mut data = Vec::with_capacity(size.min(max_len(block.len())));
+    let mut rest = block;
+    for (compressed, uncompressed) in subblocks {
+        if compressed > rest.len() {
+            return Err(invalid_data("Truncated XISF data block"));
+        }
+        let (subblock, tail) = rest.split_at(compressed);
+        rest = tail;
+        match codec {
+            "zlib" => {
+                let start = data.len();
+                // Read one byte more than expected to detect oversized streams
+                ZlibDecoder::new(subblock)
+                    .take(uncompressed as u64 + 1)
+                    .read_to_end(&mut data)?;
+                if data.len() - start != uncompressed {
+                    return Err(invalid_data("Invalid size of the decompressed data"));
+                }
+            }
+            "lz4" | "lz4hc" if uncompressed > max_len(compressed) => {
+                return Err(invalid_data("Invalid size of the decompressed data"));
+            }
+            "lz4" | "lz4hc" => data.extend(
+                lz4_flex::block::decompress(subblock, uncompressed)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            ),
+            _ => return Err(unsupported("Unsupported XISF compression codec")),
+        }
+    }
+    if data.len() != size {
+        return Err(invalid_data("Invalid size of the decompressed data"));
+    }
+    Ok(match item_size {
+        Some(item_size) => unshuffle(&data, item_size),
+        None => data,
+    })
+}
+
+/// Restore the image metadata from the properties and FITS keywords of an `Image` element.
+fn read_metadata(image: Node) -> Option<ImageMetaData> {
+    // Scalar values are stored in the `value` attribute, and strings as the element text
+    let property = |id: &str| {
+        children(image, "Property")
+            .find(|p| p.attribute("id") == Some(id))
+            .map(|p| {
+                p.attribute("value")
+                    .or_else(|| p.text())
+                    .unwrap_or("")
+                    .to_owned()
+            })
+    };
+    if let Some(meta) =
+        property(METADATA_PROPERTY).and_then(|json| serde_json::from_str(&json).ok())
+    {
+        return Some(meta);
+    }
+    let mut header = Header::default();
+    for keyword in children(image, "FITSKeyword") {
+        if let (Some(name), Some(value)) = (keyword.attribute("name"), keyword.attribute("value")) {
+            header.push(name.trim(), value);
+        }
+    }
+    if let Some(meta) = header.metadata() {
+        return Some(meta);
+    }
+    let exposure = property("Instrument:ExposureTime").and_then(|x| x.trim().parse::<f64>().ok());
+    let timestamp = property("Observation:Time:Start")
+        .and_then(|date| DateTime::parse_from_rfc3339(date.trim()).ok())
+        .map(|date| SystemTime::from(date.with_timezone(&Utc)));
+    if exposure.is_none() && timestamp.is_none() {
+        return None;
+    }
+    let mut meta = ImageMetaData::default();
+    meta.exposure = Duration::from_micros((exposure.unwrap_or(0.).max(0.) * 1e6).round() as u64);
+    meta.timestamp = timestamp.unwrap_or(UNIX_EPOCH);
+    meta.camera_name = property("Instrument:Camera:Name").unwrap_or_default();
+    if let Some(temperature) = property("Instrument:Sensor:Temperature") {
+        meta.temperature = temperature.trim().parse().unwrap_or(0.);
+    }
+    let binning = |id: &str| {
+        property(id)
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or(1)
+    };
+    meta.bin_x = binning("Instrument:Camera:XBinning");
+    meta.bin_y = binning("Instrument:Camera:YBinning");
+    Some(meta)
+}
+
+/// Child elements of a node with the given local name.
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+/// Group the bytes of the items by significance, followed by the trailing bytes.
+fn shuffle(data: &[u8], item_size: usize) -> Vec<u8> {
+    let count = data.len() / item_size;
+    let mut out = Vec::with_capacity(data.len());
+    for byte in 0..item_size {
+        out.extend((0..count).map(|idx| data[idx * item_size + byte]));
+    }
+    out.extend_from_slice(&data[count * item_size..]);
+    out
+}
+
+/// Inverse of [`shuffle`].
+fn unshuffle(data: &[u8], item_size: usize) -> Vec<u8> {
+    let count = data.len() / item_size;
+    let mut out = vec![0; data.len()];
+    for byte in 0..item_size {
+        for idx in 0..count {
+            out[idx * item_size + byte] = data[byte * count + idx];
+        }
+    }
+    out[count * item_size..].copy_from_slice(&data[count * item_size..]);
+    out
+}
+
+/// Escape the XML special characters of text and attribute values.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_xisf() {
+        let mut meta = ImageMetaData::full_builder(
+            2,
+            2,
+            10,
+            20,
+            -10.5,
+            Duration::from_micros(1_500_250),
+            UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            "Caméra <\"1\">",
+            100,
+            10,
+            0,
+            400,
+        );
+        meta.add_extended_attrib("FILTER", "Hα");
+        let mut images: Vec<DynamicSerialImage> = vec![
+            SerialImageBuffer::from_vec(3, 2, (0..6u8).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(4, 4, (0..32u16).map(|x| x * 1000).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(8, 8, (0..192u16).map(|x| x / 10).collect())
+                .unwrap()
+                .into(),
+            SerialImageBuffer::from_vec(2, 2, (0..16).map(|x| x as f32 / 8. - 0.5).collect())
+                .unwrap()
+                .into(),
+        ];
+        for compression in [
+            XisfCompression::None,
+            XisfCompression::Zlib,
+            XisfCompression::Lz4,
+        ] {
+            for byte_shuffle in [false, true] {
+                let options = XisfOptions::new()
+                    .compression(compression)
+                    .byte_shuffle(byte_shuffle);
+                for img in images.iter_mut() {
+                    img.set_metadata(meta.clone());
+                    let mut bytes = Vec::new();
+                    img.write_xisf(&mut bytes, &options).unwrap();
+                    assert_eq!(&bytes[..8], SIGNATURE);
+                    assert_eq!(
+                        &DynamicSerialImage::read_xisf(Cursor::new(&bytes)).unwrap(),
+                        img
+                    );
+                }
+            }
+        }
+        let mut bytes = Vec::new();
+        images[0]
+            .write_xisf(&mut bytes, &XisfOptions::new())
+            .unwrap();
+        assert!(SerialImageBuffer::<u16>::read_xisf(Cursor::new(&bytes)).is_err());
+        assert!(DynamicSerialImage::read_xisf(Cursor::new(&bytes[..bytes.len() - 1])).is_err());
+
+        // Metadata from the standard properties, interleaved big-endian data
+        let header = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- Test -->\n",
+            "<xisf version=\"1.0\" xmlns=\"http://www.pixinsight.com/xisf\">",
+            "<Image geometry=\"2:1:2\" sampleFormat=\"UInt16\" colorSpace=\"Gray\" ",
+            "pixelStorage=\"Normal\" byteOrder=\"big\" location=\"attachment:4096:8\">",
+            "<Property id=\"Instrument:ExposureTime\" type=\"Float32\" value=\"2.5\"/>",
+            "<Property id=\"Instrument:Camera:Name\" type=\"String\">Other &amp; &#x41;</Property>",
+            "<Property id=\"Observation:Time:Start\" type=\"TimePoint\" value=\"2023-11-14T22:13:20Z\"/>",
+            "</Image></xisf>"
+        );
+        let mut bytes = SIGNATURE.to_vec();
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.resize(4096, 0);
+        bytes.extend_from_slice(&[0, 1, 0, 2, 0, 3, 0, 4]);
+        let img = SerialImageBuffer::<u16>::read_xisf(Cursor::new(&bytes)).unwrap();
+        assert_eq!(img.channels().len(), 2);
+        assert_eq!(
+            img.channel_data(img.channels()[0]).as_deref(),
+            Some(&[1, 3][..])
+        );
+        let meta = img.get_metadata().unwrap();
+        assert_eq!(meta.camera_name, "Other & A");
+        assert_eq!(meta.exposure, Duration::from_millis(2500));
+        assert_eq!(
+            meta.timestamp,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn test_xisf_invalid() {
+        let xisf = |attributes: &str, block: &[u8]| {
+            let header = format!(
+                "<xisf version=\"1.0\"><Image sampleFormat=\"UInt16\" {} location=\"attachment:4096:{}\"/></xisf>",
+                attributes,
+                block.len()
+            );
+            let mut bytes = SIGNATURE.to_vec();
+            bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+            bytes.extend_from_slice(header.as_bytes());
+            bytes.resize(4096, 0);
+            bytes.extend_from_slice(block);
+            DynamicSerialImage::read_xisf(Cursor::new(&bytes))
+        };
+        assert!(xisf("geometry=\"2:1:1\"", &[0; 4]).is_ok());
+        assert!(xisf("geometry=\"0:1:1\"", &[]).is_err());
+        // The size of the image overflows
+        let huge = format!("geometry=\"{0}:{0}:1\"", usize::MAX);
+        assert!(xisf(&huge, &[0; 4]).is_err());
+        // The decompressed sizes do not match the geometry
+        let block = lz4_flex::block::compress(&[0; 4]);
+        let lz4 = |size: usize| format!("geometry=\"2:1:1\" compression=\"lz4:{}\"", size);
+        assert!(xisf(&lz4(4), &block).is_ok());
+        assert!(xisf(&lz4(1 << 40), &block).is_err());
+        let huge = format!(
+            "geometry=\"65536:65536:1\" compression=\"lz4:{}\"",
+            1u64 << 33
+        );
+        assert!(xisf(&huge, &block).is_err());
+        let subblocks = format!("{} subblocks=\"{},{}\"", lz4(4), block.len(), 1u64 << 40);
+        assert!(xisf(&subblocks, &block).is_err());
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(&shuffle(&data, 4)[..4], &[0, 4, 8, 1]);
+        assert_eq!(unshuffle(&shuffle(&data, 4), 4), data);
+    }
+}